@@ -1,4 +1,7 @@
 fn main() {
-    let config = slint_build::CompilerConfiguration::new().with_style("cupertino-dark".into());
-    slint_build::compile_with_config("./ui/mainwindow.slint", config).unwrap();
+    #[cfg(feature = "gui")]
+    {
+        let config = slint_build::CompilerConfiguration::new().with_style("cupertino-dark".into());
+        slint_build::compile_with_config("./ui/mainwindow.slint", config).unwrap();
+    }
 }
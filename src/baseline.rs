@@ -0,0 +1,161 @@
+use anyhow::Context;
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::{
+    enums::ReportStatus,
+    report,
+    structs::{Config, DataStore, IgnoreRules},
+    utilities::url_selected,
+};
+
+/// Copies the latest state of every URL selected by `pattern` into its reference
+/// state, later runs are judged against it instead of the history.
+#[instrument(skip(data_store))]
+pub fn approve(data_store: &DataStore, pattern: &str) -> anyhow::Result<Vec<Url>> {
+    let approved = data_store.edit(|page_datas| {
+        let mut approved = vec![];
+        for (url, page_data) in page_datas.iter_mut() {
+            if !url_selected(pattern, url) {
+                continue;
+            }
+            if let Some(latest) = page_data.history.last() {
+                page_data.reference_state = Some(latest.clone());
+                approved.push(url.clone());
+            }
+        }
+        approved
+    });
+
+    data_store.save().context("Failed to save data store")?;
+    info!("Approved {} baselines", approved.len());
+    Ok(approved)
+}
+
+/// Approves every URL the report would currently list as valid.
+#[instrument(skip(data_store, config))]
+pub fn approve_all_valid(data_store: &DataStore, config: &Config) -> anyhow::Result<Vec<Url>> {
+    let valid: Vec<Url> = report::classify(data_store.snapshot(), config)
+        .into_iter()
+        .filter(|entry| entry.status == ReportStatus::Valid)
+        .map(|entry| entry.url)
+        .collect();
+
+    let approved = data_store.edit(|page_datas| {
+        let mut approved = vec![];
+        for url in valid {
+            if let Some(page_data) = page_datas.get_mut(&url) {
+                if let Some(latest) = page_data.history.last() {
+                    page_data.reference_state = Some(latest.clone());
+                    approved.push(url);
+                }
+            }
+        }
+        approved
+    });
+
+    data_store.save().context("Failed to save data store")?;
+    info!("Approved {} baselines", approved.len());
+    Ok(approved)
+}
+
+/// Adds `rules` to the ignore rules of every URL selected by `pattern`, `None` removes
+/// all of them instead.
+#[instrument(skip(data_store))]
+pub fn ignore(
+    data_store: &DataStore,
+    pattern: &str,
+    rules: Option<IgnoreRules>,
+) -> anyhow::Result<Vec<Url>> {
+    let changed = data_store.edit(|page_datas| {
+        let mut changed = vec![];
+        for (url, page_data) in page_datas.iter_mut() {
+            if !url_selected(pattern, url) {
+                continue;
+            }
+            page_data.ignore = match rules {
+                Some(rules) => IgnoreRules {
+                    page_hash: page_data.ignore.page_hash || rules.page_hash,
+                    screenshot: page_data.ignore.screenshot || rules.screenshot,
+                    title: page_data.ignore.title || rules.title,
+                    skip: page_data.ignore.skip || rules.skip,
+                },
+                None => IgnoreRules::default(),
+            };
+            changed.push(url.clone());
+        }
+        changed
+    });
+
+    data_store.save().context("Failed to save data store")?;
+    info!("Updated the ignore rules of {} URLs", changed.len());
+    Ok(changed)
+}
+
+#[instrument(skip(data_store))]
+pub fn clear(data_store: &DataStore, pattern: &str) -> anyhow::Result<Vec<Url>> {
+    let cleared = data_store.edit(|page_datas| {
+        page_datas
+            .iter_mut()
+            .filter(|(url, _)| url_selected(pattern, url))
+            .filter_map(|(url, page_data)| page_data.reference_state.take().map(|_| url.clone()))
+            .collect::<Vec<Url>>()
+    });
+
+    data_store.save().context("Failed to save data store")?;
+    info!("Cleared {} baselines", cleared.len());
+    Ok(cleared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        disc_op,
+        enums::StorageBackend,
+        test_support::{state, url},
+    };
+
+    #[test]
+    fn ignore_rules_add_up_and_outlive_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = disc_op::open_storage(
+            StorageBackend::Json,
+            &dir.path().join("data_store.json"),
+            &dir.path().join("data_store.sqlite"),
+        )
+        .unwrap();
+        let data_store = DataStore::load(storage).unwrap();
+        let (feed, other) = (url("https://example.com/feed"), url("https://example.org/"));
+        data_store.merge(feed.clone(), state("one"), 1);
+        data_store.merge(other.clone(), state("one"), 1);
+
+        let title = IgnoreRules {
+            title: true,
+            ..IgnoreRules::default()
+        };
+        let screenshot = IgnoreRules {
+            screenshot: true,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(
+            ignore(&data_store, "example.com", Some(title)).unwrap(),
+            std::slice::from_ref(&feed)
+        );
+        let _ = ignore(&data_store, "example.com", Some(screenshot)).unwrap();
+
+        // Trimming the history to the latest check keeps the rules
+        data_store.merge(feed.clone(), state("two"), 1);
+        let page_datas = data_store.snapshot();
+        let both = IgnoreRules {
+            title: true,
+            screenshot: true,
+            ..IgnoreRules::default()
+        };
+        assert_eq!(page_datas[&feed].ignore, both);
+        assert!(page_datas[&other].ignore.is_empty());
+
+        let _ = ignore(&data_store, "example.com", None).unwrap();
+        assert!(data_store.snapshot()[&feed].ignore.is_empty());
+    }
+}
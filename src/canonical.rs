@@ -0,0 +1,316 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::Context;
+use tracing::{info, instrument, warn};
+use url::Url;
+
+use crate::{
+    enums::Locations,
+    structs::{Config, DataStore, IgnoreRules, PageData, Paths},
+    utilities::{hash_string, normalize_url, percent_decode},
+};
+
+/// Whether the query parameter `name` is listed in `strip_query_params`, a trailing
+/// `*` matches any suffix. Matched ignoring case.
+fn stripped_param(name: &str, patterns: &[String]) -> bool {
+    let name = name.to_lowercase();
+
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_lowercase();
+        match pattern.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == pattern,
+        }
+    })
+}
+
+/// The URL a link is checked and stored under, its escapes normalized. Kept query
+/// parameters aren't re-encoded beyond that, it could change what the server sees.
+pub fn canonicalize(url: &Url, config: &Config) -> Url {
+    let mut canonical = normalize_url(url);
+
+    // A fragment is what the anchor check looks at, it has to stay then
+    if config.strip_fragments && !config.check_anchors {
+        canonical.set_fragment(None);
+    }
+
+    if let Some(query) = canonical.query().map(str::to_string) {
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split('=').next().unwrap_or_default();
+                !stripped_param(&percent_decode(name), &config.strip_query_params)
+            })
+            .collect();
+        if kept.is_empty() {
+            canonical.set_query(None);
+        } else {
+            canonical.set_query(Some(&kept.join("&")));
+        }
+    }
+
+    // Hosts of http and https URLs are already lowercased when parsed, this only
+    // changes the other schemes
+    if config.lowercase_host {
+        let lowercase = canonical
+            .host_str()
+            .map(str::to_lowercase)
+            .filter(|lowercase| canonical.host_str() != Some(lowercase.as_str()));
+        if let Some(lowercase) = lowercase {
+            if canonical.set_host(Some(&lowercase)).is_err() {
+                warn!("Failed to lowercase the host of {url}");
+            }
+        }
+    }
+
+    canonical
+}
+
+/// Maps every canonical URL to the URLs from the source that turned into it, the
+/// canonical URL itself is only listed when the source had it as written.
+pub fn group(urls: HashSet<Url>, config: &Config) -> BTreeMap<Url, BTreeSet<Url>> {
+    let mut groups: BTreeMap<Url, BTreeSet<Url>> = BTreeMap::new();
+    for url in urls {
+        let _ = groups
+            .entry(canonicalize(&url, config))
+            .or_default()
+            .insert(url);
+    }
+
+    let duplicates = groups.values().map(|urls| urls.len() - 1).sum::<usize>();
+    if duplicates > 0 {
+        info!(
+            "{duplicates} URLs are duplicates of another URL once canonicalized, {} left to check",
+            groups.len()
+        );
+    }
+
+    groups
+}
+
+/// Folds `entries`, newest first, into one entry for `canonical`. The most recently
+/// checked entry wins where only one value can be kept, histories merge by check time.
+fn merge_entries(canonical: &Url, entries: &[(Url, PageData)], history_length: usize) -> PageData {
+    let mut history = vec![];
+    let mut aliases = BTreeSet::new();
+    let mut linked_from = BTreeSet::new();
+    let mut ignore = IgnoreRules::default();
+    let mut marker = None;
+    let mut reference_state = None;
+    for (url, page_data) in entries {
+        history.extend(page_data.history.iter().cloned());
+        aliases.extend(page_data.aliases.iter().cloned());
+        linked_from.extend(page_data.linked_from.iter().cloned());
+        if url != canonical {
+            let _ = aliases.insert(url.clone());
+        }
+        ignore = IgnoreRules {
+            page_hash: ignore.page_hash || page_data.ignore.page_hash,
+            screenshot: ignore.screenshot || page_data.ignore.screenshot,
+            title: ignore.title || page_data.ignore.title,
+            skip: ignore.skip || page_data.ignore.skip,
+        };
+        marker = marker.or_else(|| page_data.marker.clone());
+        reference_state = reference_state.or_else(|| page_data.reference_state.clone());
+    }
+    history.sort_by_key(|state| state.check_time);
+    let excess = history.len().saturating_sub(history_length.max(1));
+    let _ = history.drain(..excess);
+
+    PageData {
+        marker,
+        reference_state,
+        ignore,
+        aliases,
+        linked_from,
+        last_checked: entries
+            .first()
+            .map_or_else(chrono::Utc::now, |(_, page_data)| page_data.last_checked),
+        last_seen_in_source: entries
+            .iter()
+            .filter_map(|(_, page_data)| page_data.last_seen_in_source)
+            .max(),
+        url_hash: hash_string(&canonical.to_string()),
+        history,
+    }
+}
+
+/// Moves the data store entries, and their local records, of URLs that aren't in
+/// canonical form to their canonical URL, merging entries that end up on the same one.
+///
+/// Data stores from before canonicalization get migrated by the first run, later runs
+/// only find something to do when the rules change. Returns how many entries moved.
+#[instrument(skip_all)]
+pub fn migrate(data_store: &DataStore, config: &Config, paths: &Paths) -> anyhow::Result<usize> {
+    let pages_dir = paths.get(Locations::PagesSubdir);
+
+    let (moved, record_moves) = data_store.edit(|page_datas| {
+        let mut groups: BTreeMap<Url, Vec<Url>> = BTreeMap::new();
+        for url in page_datas.keys() {
+            groups
+                .entry(canonicalize(url, config))
+                .or_default()
+                .push(url.clone());
+        }
+
+        let mut moved = 0;
+        let mut record_moves: Vec<(PathBuf, PathBuf)> = vec![];
+        for (canonical, urls) in groups {
+            if urls.len() == 1 && urls[0] == canonical {
+                continue;
+            }
+
+            let mut entries: Vec<(Url, PageData)> = urls
+                .into_iter()
+                .filter_map(|url| page_datas.remove(&url).map(|page_data| (url, page_data)))
+                .collect();
+            entries.sort_by_key(|(_, page_data)| std::cmp::Reverse(page_data.last_checked));
+            moved += entries.iter().filter(|(url, _)| *url != canonical).count();
+
+            // The local records of the most recently checked entry that has any
+            // become the canonical URL's, unless it already has its own
+            let canonical_dir = pages_dir.join(hash_string(&canonical.to_string()));
+            if !canonical_dir.exists() {
+                if let Some(dir) = entries
+                    .iter()
+                    .map(|(url, _)| pages_dir.join(hash_string(&url.to_string())))
+                    .find(|dir| dir.exists())
+                {
+                    record_moves.push((dir, canonical_dir));
+                }
+            }
+
+            let merged = merge_entries(&canonical, &entries, config.history_length);
+            let _ = page_datas.insert(canonical, merged);
+        }
+
+        (moved, record_moves)
+    });
+
+    if moved == 0 {
+        return Ok(0);
+    }
+
+    data_store
+        .save()
+        .context("Failed to save data store after canonicalizing URLs")?;
+    for (from, to) in record_moves {
+        if let Err(err) = fs::rename(&from, &to) {
+            warn!("Failed to move local records from {from:?} to {to:?}: {err:?}");
+        }
+    }
+
+    info!("Moved {moved} data store entries to their canonical URL");
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        structs::State,
+        test_support::{page_data, state, url},
+    };
+
+    fn at(seconds: i64) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::from_timestamp(1_700_000_000 + seconds, 0).unwrap()
+    }
+
+    fn checked_at(seconds: &[i64]) -> Vec<State> {
+        seconds
+            .iter()
+            .map(|&second| {
+                let mut state = state(&second.to_string());
+                state.check_time = at(second);
+                state
+            })
+            .collect()
+    }
+
+    fn entries() -> Vec<(Url, PageData)> {
+        let mut newer = page_data(
+            "https://example.com/page?utm_source=mail",
+            checked_at(&[2, 4, 6]),
+        );
+        newer.last_checked = at(6);
+        newer.marker = Some("Newer".to_string());
+        newer.ignore.title = true;
+        let _ = newer.linked_from.insert(url("https://example.com/"));
+
+        let mut older = page_data("https://example.com/page", checked_at(&[1, 3, 5]));
+        older.last_checked = at(5);
+        older.marker = Some("Older".to_string());
+        older.reference_state = Some(state("approved"));
+        older.last_seen_in_source = Some(at(7));
+        older.ignore.screenshot = true;
+        let _ = older.aliases.insert(url("https://example.com/page/"));
+
+        vec![
+            (url("https://example.com/page?utm_source=mail"), newer),
+            (url("https://example.com/page"), older),
+        ]
+    }
+
+    #[test]
+    fn the_newest_entry_wins_single_values() {
+        let canonical = url("https://example.com/page");
+        let merged = merge_entries(&canonical, &entries(), 10);
+
+        assert_eq!(merged.marker(), Some(&"Newer".to_string()));
+        assert_eq!(merged.last_checked, at(6));
+        assert_eq!(merged.last_seen_in_source, Some(at(7)));
+        // Only the older entry has a baseline, it is kept
+        assert_eq!(merged.reference_state.unwrap().hash, state("approved").hash);
+        assert_eq!(merged.url_hash, hash_string(&canonical.to_string()));
+        assert_eq!(
+            merged.aliases,
+            BTreeSet::from([
+                url("https://example.com/page/"),
+                url("https://example.com/page?utm_source=mail"),
+            ])
+        );
+        assert_eq!(
+            merged.linked_from,
+            BTreeSet::from([url("https://example.com/")])
+        );
+    }
+
+    #[test]
+    fn histories_interleave_by_check_time_and_keep_the_newest() {
+        let canonical = url("https://example.com/page");
+        let check_times = |history_length| {
+            merge_entries(&canonical, &entries(), history_length)
+                .history
+                .iter()
+                .map(|state| state.check_time)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(check_times(10), [1, 2, 3, 4, 5, 6].map(at));
+        assert_eq!(check_times(4), [3, 4, 5, 6].map(at));
+        assert_eq!(check_times(0), [at(6)]);
+    }
+
+    #[test]
+    fn ignore_rules_of_any_entry_apply() {
+        let merged = merge_entries(&url("https://example.com/page"), &entries(), 10);
+        assert_eq!(
+            merged.ignore,
+            IgnoreRules {
+                page_hash: false,
+                screenshot: true,
+                title: true,
+                skip: false,
+            }
+        );
+
+        let mut entries = entries();
+        entries[1].1.ignore.skip = true;
+        let merged = merge_entries(&url("https://example.com/page"), &entries, 10);
+        assert!(merged.ignore.skip && merged.ignore.title && merged.ignore.screenshot);
+    }
+}
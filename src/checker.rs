@@ -0,0 +1,1356 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use anyhow::Context;
+use thirtyfour::WebDriver;
+use tokio::time::{sleep_until, Instant};
+use tracing::{error, info, instrument, warn};
+use url::Url;
+
+use crate::{
+    canonical, disc_op, driver,
+    driver::new_tab,
+    enums::{
+        CheckMode, CustomError, LinkType, Locations, ReportStatus, RunOutcome, RunTrigger,
+        TimeoutPolicy,
+    },
+    extract, http_check, mailto, metrics, normalize, notify, plan, report, robots, soft_404,
+    structs::{
+        ActivePages, Checker, Config, Credentials, DataStore, LatestMetrics, Paths, ReportEntry,
+        ReportSummary, RobotsCache, RunControl, RunPreview, RunSummary, State, SweepState,
+    },
+    utilities,
+};
+
+/// Classifies by the URL alone, see `content_by_header` for links without an extension.
+#[instrument(skip(config))]
+pub fn check_link_type(url: &Url, config: &Config) -> anyhow::Result<LinkType> {
+    let url_string = url.to_string();
+    let is_content = url.scheme().starts_with("http")
+        && utilities::url_extension(url).is_some_and(|extension| {
+            config
+                .content_extensions
+                .iter()
+                .any(|content| content.eq_ignore_ascii_case(&extension))
+        });
+    let is_local_prefix = config
+        .local_prefixes
+        .iter()
+        .any(|prefix| url_string.starts_with(&prefix.url_prefix));
+
+    let link_type = if is_local_prefix {
+        LinkType::Local
+    } else if is_content {
+        LinkType::Content
+    } else if url_string.starts_with("http") {
+        LinkType::Generic
+    } else if url_string.contains("/User") || url_string.starts_with("file://") {
+        LinkType::Local
+    } else if url_string.starts_with("mailto:") {
+        LinkType::Mailto
+    } else {
+        LinkType::Unknown
+    };
+
+    Ok(link_type)
+}
+
+/// Asks the server what an extensionless link is, so a document behind e.g.
+/// `/download?id=1` doesn't end up in a browser tab.
+async fn content_by_header(url: &Url, config: &Config) -> bool {
+    if utilities::url_extension(url).is_some() {
+        return false;
+    }
+
+    let credentials = utilities::credentials_for(url, &config.auth).ok().flatten();
+    let Some(content_type) =
+        utilities::head_content_type(url, credentials.as_ref(), config.proxy.as_ref()).await
+    else {
+        return false;
+    };
+
+    utilities::content_type_extension(&content_type).is_some_and(|extension| {
+        config
+            .content_extensions
+            .iter()
+            .any(|content| content.eq_ignore_ascii_case(extension))
+    })
+}
+
+/// Only a missing file is an error. The size and modified time stand in for the content,
+/// so a changed file shows up like a changed page.
+fn check_local_link(url: &Url, config: &Config) -> State {
+    let Some(path) = utilities::local_path(url, &config.local_prefixes) else {
+        warn!("Can't resolve {url} to a path on this machine");
+        return State::new(
+            "",
+            None,
+            None,
+            LinkType::Local,
+            vec![CustomError::LocalFileMissing],
+        );
+    };
+
+    let metadata = match std::fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(err) => {
+            info!("Local file {path:?} is missing: {err}");
+            return State::new(
+                "",
+                None,
+                None,
+                LinkType::Local,
+                vec![CustomError::LocalFileMissing],
+            );
+        }
+    };
+
+    let readable = if metadata.is_dir() {
+        std::fs::read_dir(&path).map(|_| ())
+    } else {
+        std::fs::File::open(&path).map(|_| ())
+    };
+    if let Err(err) = readable {
+        warn!("Local file {path:?} exists but can't be read: {err}");
+    }
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .map(chrono::DateTime::<chrono::Utc>::from);
+    let size = metadata.len();
+    let mut state = State::new(
+        &format!("{size} {modified:?}"),
+        None,
+        None,
+        LinkType::Local,
+        vec![],
+    );
+    state.local_file_size = Some(size);
+    state.local_file_modified = modified;
+    state
+}
+
+fn title_check(title: &str) -> Result<(), CustomError> {
+    if title.contains("404") || title.contains("Not Found") {
+        return Err(CustomError::PageNotFound);
+    }
+    if title.contains("Warning") {
+        return Err(CustomError::Warning);
+    }
+    if title.contains("Error") || title.contains("Unable to") || title.contains("Problem") {
+        return Err(CustomError::PageError);
+    }
+
+    Ok(())
+}
+
+/// The state of a page the driver failed to deliver part of, `error` comes after
+/// whatever went wrong loading it. Nothing is hashed, made up empty content would only
+/// pollute the history.
+fn unavailable_state(navigation_error: Option<CustomError>, error: CustomError) -> State {
+    let errors: Vec<CustomError> = navigation_error.into_iter().chain([error]).collect();
+    State::new("", None, None, LinkType::Generic, errors)
+}
+
+/// What is left of `navigation_error` under `policy`, and whether the page is still
+/// checked. A half rendered page would only make the history flap, so unless the policy
+/// says to check anyway a timeout is recorded and nothing else.
+fn apply_timeout_policy(
+    navigation_error: Option<CustomError>,
+    policy: TimeoutPolicy,
+) -> (Option<CustomError>, bool) {
+    if navigation_error != Some(CustomError::PageLoadTimeout) {
+        return (navigation_error, true);
+    }
+
+    match policy {
+        TimeoutPolicy::CheckAnyway => (None, true),
+        TimeoutPolicy::MarkError | TimeoutPolicy::RetryOnce => (navigation_error, false),
+    }
+}
+
+pub(crate) async fn check_link(
+    driver: &WebDriver,
+    url: &Url,
+    marker: Option<&String>,
+    config: &Config,
+    paths: &Paths,
+    linktype: LinkType,
+    navigation_error: Option<CustomError>,
+) -> State {
+    let credentials = match utilities::credentials_for(url, &config.auth) {
+        Ok(credentials) => credentials,
+        Err(err) => {
+            error!("{err:#}");
+            return State::new(
+                "",
+                None,
+                None,
+                linktype,
+                vec![CustomError::MissingCredentials],
+            );
+        }
+    };
+
+    match linktype {
+        LinkType::Generic => {
+            if let Err(err) = driver
+                .switch_to_named_window(&driver::window_name(url))
+                .await
+            {
+                warn!("Failed to switch to the window of {url}: {err:?}");
+                return unavailable_state(navigation_error, CustomError::WindowLost);
+            }
+
+            let title = match driver.title().await {
+                Ok(title) => title,
+                Err(err) => {
+                    warn!("Failed to get the title of {url}: {err:?}");
+                    return unavailable_state(navigation_error, CustomError::TitleUnavailable);
+                }
+            };
+
+            let (navigation_error, check_content) =
+                apply_timeout_policy(navigation_error, config.timeout_policy);
+            if !check_content {
+                return State::new(
+                    "",
+                    None,
+                    Some(title),
+                    LinkType::Generic,
+                    navigation_error.into_iter().collect(),
+                );
+            }
+            let mut raw_page_source = match driver.source().await {
+                Ok(source) => source,
+                Err(err) => {
+                    warn!("Failed to get the source of {url}: {err:?}");
+                    return unavailable_state(navigation_error, CustomError::SourceUnavailable);
+                }
+            };
+            let source_truncated = utilities::truncate_at_char_boundary(
+                &mut raw_page_source,
+                config.max_page_source_bytes,
+            );
+            if source_truncated {
+                warn!(
+                    "The source of {url} is longer than {} bytes, only its start is checked",
+                    config.max_page_source_bytes
+                );
+            }
+
+            // Validated on load, a filter can only fail here if the config was edited since
+            let filters = config.source_filters().unwrap_or_else(|err| {
+                error!("Ignoring source filters: {err:#}");
+                vec![]
+            });
+            let page_source =
+                normalize::normalize(&raw_page_source, &filters, config.normalize_source);
+            let mask = plan::screenshot_mask(url, config);
+            let mask_selectors = mask.map(|mask| mask.selectors.as_slice());
+            let screenshot = driver::screenshot(driver, config, mask_selectors.unwrap_or_default())
+                .await
+                .and_then(|screenshot| {
+                    let img = image::load_from_memory(&screenshot.png)
+                        .context("Failed to decode the screenshot")?;
+                    Ok((screenshot, img))
+                });
+            let (screenshot, mut img) = match screenshot {
+                Ok(screenshot) => screenshot,
+                Err(err) => {
+                    warn!("Failed to screenshot {url}: {err:?}");
+                    return unavailable_state(navigation_error, CustomError::ScreenshotFailed);
+                }
+            };
+            if let Some(mask) = mask {
+                utilities::apply_masks(&mut img, mask.rects.iter().chain(&screenshot.masked));
+            }
+
+            // Losing the local copy doesn't make the link any less valid, note it and move on
+            let mut local_record_error = None;
+            if config.keep_local_records {
+                if let Err(err) =
+                    disc_op::save_page_data(url, config, &raw_page_source, &img, paths)
+                {
+                    error!("Failed to save page data for {url}: {err:?}");
+                    local_record_error = Some(format!("{err:#}"));
+                }
+            }
+
+            // Most important first, the first one is what `State::error` reports.
+            // Whatever else looked wrong most likely followed from the page not loading
+            let mut errors: Vec<CustomError> = navigation_error.into_iter().collect();
+
+            let (status_code, status_error) = match utilities::http_status(
+                url,
+                credentials.as_ref(),
+                config.proxy.as_ref(),
+            )
+            .await
+            {
+                Ok(code) => (Some(code), utilities::status_error(code)),
+                Err(err) => {
+                    warn!("Failed to get status code for {url}: {err:?}");
+                    let error =
+                        utilities::network_error(&err).or_else(|| title_check(&title).err());
+                    (None, error)
+                }
+            };
+
+            // Firefox may keep the credentials it was handed in the location
+            let final_url = driver
+                .current_url()
+                .await
+                .ok()
+                .map(|final_url| utilities::without_credentials(&final_url));
+            if let Some(final_url) = &final_url {
+                if !utilities::is_allowed_redirect(url, final_url, &config.redirect_allowlist) {
+                    errors.push(CustomError::Redirected);
+                }
+            }
+            errors.extend(status_error);
+
+            if let Some(marker) = marker {
+                if !raw_page_source.contains(marker) {
+                    if config.marker_required {
+                        errors.push(CustomError::MarkerNotFound);
+                    } else {
+                        warn!("Marker not found on {url}");
+                    }
+                }
+            }
+
+            // A blank screenshot hashes to all zeros whatever the hash settings
+            let screenshot_hash = utilities::hash_img(&img, config);
+            if screenshot_hash.chars().all(|c| c == 'A') {
+                errors.push(CustomError::BadScreenshot);
+            }
+
+            // A trailing # has nothing to look for
+            let mut anchor_found = false;
+            if let Some(fragment) = url.fragment().filter(|fragment| !fragment.is_empty()) {
+                if navigation_error.is_none() && config.check_anchors {
+                    match driver::anchor_exists(driver, fragment).await {
+                        Ok(true) => anchor_found = true,
+                        Ok(false) => errors.push(CustomError::AnchorNotFound),
+                        Err(err) => warn!("Failed to check anchor of {url}: {err:?}"),
+                    }
+                }
+            }
+
+            let console_errors = driver::console_errors(driver).await;
+            let failed_assets = if config.check_page_assets {
+                driver::failed_assets(driver, config.max_failed_assets).await
+            } else {
+                None
+            };
+            if let (Some((count, _)), Some(max)) = (&console_errors, config.max_console_errors) {
+                if *count > max {
+                    errors.push(CustomError::ConsoleErrors);
+                }
+            }
+
+            // Scripts only get to judge pages that passed everything else, the reason then
+            // always belongs to the recorded error
+            let mut validation = None;
+            if errors.is_empty() {
+                validation = driver::run_validators(driver, url, &config.validators).await;
+                errors.extend(validation.as_ref().map(|(e, _)| *e));
+            }
+
+            let mut state = State::new(
+                &page_source,
+                Some(screenshot_hash),
+                Some(title),
+                LinkType::Generic,
+                errors,
+            );
+            state.status_code = status_code;
+            state.final_url = final_url;
+            state.local_record_error = local_record_error;
+            state.validator_reason = validation.map(|(_, reason)| reason);
+            state.anchor_found = anchor_found;
+            state.source_truncated = source_truncated;
+            state.full_page_screenshot = screenshot.full_page;
+            state.mask_version = mask.map(utilities::mask_version);
+            state.screenshot_hasher = Some(utilities::hasher_version(config));
+            state.browser_locale = driver::applied_locale(config);
+            state.browser_timezone = driver::applied_timezone(config);
+            (state.load_time_ms, state.transfer_size) = driver::navigation_timing(driver).await;
+            if let Some((count, messages)) = console_errors {
+                state.console_error_count = Some(count);
+                state.console_errors = Some(messages);
+            }
+            if let Some((count, assets)) = failed_assets {
+                state.failed_asset_count = Some(count);
+                state.failed_assets = Some(assets);
+            }
+            state.filter_version = Some(normalize::filter_version(config));
+            let text = normalize::visible_text(&raw_page_source);
+            state.text_hash = Some(utilities::hash_string(&text));
+            state.text_length = Some(text.chars().count());
+            // Relative links resolve against where the page ended up
+            if config.crawl_depth > 0 && state.errors.is_empty() {
+                let base = state.final_url.as_ref().unwrap_or(url);
+                state.links = extract::html_links(raw_page_source.as_bytes(), Some(base))
+                    .urls
+                    .into_iter()
+                    .collect();
+            }
+            state
+        }
+
+        _ => check_without_browser(url, config, linktype, credentials.as_ref()).await,
+    }
+}
+
+/// Every link type but Generic, none of them need the browser.
+async fn check_without_browser(
+    url: &Url,
+    config: &Config,
+    linktype: LinkType,
+    credentials: Option<&Credentials>,
+) -> State {
+    match linktype {
+        LinkType::Generic => http_check::check(url, config).await,
+        LinkType::Content => {
+            match utilities::download_file(
+                url,
+                credentials,
+                config.max_content_download,
+                utilities::CONTENT_DOWNLOAD_TIMEOUT,
+                config.proxy.as_ref(),
+            )
+            .await
+            {
+                Ok(download) => {
+                    let mut state =
+                        State::new(&download.content, None, None, LinkType::Content, vec![]);
+                    state.status_code = Some(download.status_code);
+                    state.content_length = Some(download.length);
+                    state.content_truncated = download.truncated;
+                    state
+                }
+                Err(err) => {
+                    warn!("Failed to download {url}: {err:#}");
+                    let mut state = State::new(
+                        "",
+                        None,
+                        None,
+                        LinkType::Content,
+                        vec![CustomError::ContentDownloadFailed],
+                    );
+                    state.status_code = err
+                        .downcast_ref::<reqwest::Error>()
+                        .and_then(reqwest::Error::status)
+                        .map(|status| status.as_u16());
+                    state
+                }
+            }
+        }
+
+        LinkType::Local if config.check_local_links => check_local_link(url, config),
+        LinkType::Local => State::new(
+            "",
+            None,
+            None,
+            LinkType::Local,
+            vec![CustomError::LinkTypeLocal],
+        ),
+        LinkType::Mailto => mailto::check(url, config).await,
+        LinkType::Unknown => State::new(
+            "",
+            None,
+            None,
+            LinkType::Unknown,
+            vec![CustomError::UnknownLinkType],
+        ),
+        LinkType::InternalError => State::new(
+            "",
+            None,
+            None,
+            LinkType::InternalError,
+            vec![CustomError::WebDriverError],
+        ),
+    }
+}
+
+/// Checks `urls` without the browser, in auto mode the Generic pages whose result
+/// doesn't match what is stored are returned to be checked in the browser as well.
+async fn check_over_http(
+    urls: HashSet<Url>,
+    data_store: &DataStore,
+    config: &Config,
+    control: &RunControl,
+) -> HashSet<Url> {
+    let mut to_browser = HashSet::new();
+
+    for url in urls {
+        if control.is_cancelled() {
+            info!("Cancelled, HTTP checks stopped");
+            break;
+        }
+        control.loading(&url);
+
+        let linktype = match check_link_type(&url, config) {
+            Ok(LinkType::Generic) if content_by_header(&url, config).await => LinkType::Content,
+            Ok(linktype) => linktype,
+            Err(e) => {
+                error!("Failed to check link type: {e:?}");
+                LinkType::InternalError
+            }
+        };
+        let credentials = match utilities::credentials_for(&url, &config.auth) {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                error!("{err:#}");
+                let state = State::new(
+                    "",
+                    None,
+                    None,
+                    linktype,
+                    vec![CustomError::MissingCredentials],
+                );
+                control.finished(true);
+                data_store.merge(url, state, config.history_length);
+                continue;
+            }
+        };
+
+        let state = check_without_browser(&url, config, linktype, credentials.as_ref()).await;
+        if config.check_mode == CheckMode::Auto
+            && linktype == LinkType::Generic
+            && data_store.edit(|page_datas| http_check::needs_browser(&state, page_datas.get(&url)))
+        {
+            info!("{url} goes to the browser, its HTTP result doesn't match the stored one");
+            let _ = to_browser.insert(url);
+            continue;
+        }
+
+        control.finished(!state.errors.is_empty());
+        data_store.merge(url, state, config.history_length);
+    }
+
+    to_browser
+}
+
+/// Returns how often the browser had to be restarted and how many pages timed out. A
+/// cancelled run stops opening tabs, skips the retries and keeps every result it
+/// already has. So does a sweep that fails, the browser is quit before the error is
+/// returned.
+async fn check_links(
+    mut driver: WebDriver,
+    urls: HashSet<Url>,
+    data_store: &DataStore,
+    config: &Config,
+    paths: &Paths,
+    control: &RunControl,
+) -> anyhow::Result<(usize, usize)> {
+    let mut restarts = 0;
+    let robots = RobotsCache::default();
+    let mut probes = HashMap::new();
+    if config.detect_soft_404 {
+        (driver, probes) = soft_404::probe(driver, &urls, config, paths, control).await?;
+    }
+    let mut pass = SweepState::new(0, urls);
+    pass.soft_404_probes = probes.clone();
+    let mut outcome = sweep_with_restarts(
+        &mut driver,
+        &mut pass,
+        data_store,
+        config,
+        paths,
+        control,
+        &robots,
+        &mut restarts,
+    )
+    .await;
+    let mut timed_out = pass.timed_out;
+    let mut failed = pass.failed;
+
+    let timeout_retries = usize::from(config.timeout_policy == TimeoutPolicy::RetryOnce);
+    for attempt in 1..=config.retry_attempts.max(timeout_retries) {
+        // Under retry_once a timed out page only gets the first retry
+        let retry: HashSet<Url> = failed
+            .iter()
+            .filter(|(_, state)| {
+                attempt == 1
+                    || config.timeout_policy != TimeoutPolicy::RetryOnce
+                    || state.error() != Some(CustomError::PageLoadTimeout)
+            })
+            .map(|(url, _)| url.clone())
+            .collect();
+        if retry.is_empty() || control.is_cancelled() || outcome.is_err() {
+            break;
+        }
+
+        info!("Retry attempt {attempt}, re-checking {} URLs", retry.len());
+        let mut pass = SweepState::new(attempt, retry.clone());
+        pass.soft_404_probes = probes.clone();
+        outcome = sweep_with_restarts(
+            &mut driver,
+            &mut pass,
+            data_store,
+            config,
+            paths,
+            control,
+            &robots,
+            &mut restarts,
+        )
+        .await;
+        timed_out += pass.timed_out;
+        failed.retain(|url, _| !retry.contains(url) || pass.failed.contains_key(url));
+    }
+
+    // Nothing got better on retry, keep the original results
+    for (url, state) in failed {
+        data_store.merge(url, state, config.history_length);
+    }
+
+    let quit = driver.quit().await.context("Failed to quit the browser");
+    outcome?;
+    quit?;
+    Ok((restarts, timed_out))
+}
+
+/// Runs a sweep, relaunching the browser whenever its session dies. Everything merged
+/// before the crash is saved first and only the URLs still pending are checked again.
+/// `driver` is always left holding the latest session, even when the sweep fails.
+#[allow(clippy::too_many_arguments)]
+async fn sweep_with_restarts(
+    driver: &mut WebDriver,
+    pass: &mut SweepState,
+    data_store: &DataStore,
+    config: &Config,
+    paths: &Paths,
+    control: &RunControl,
+    robots: &RobotsCache,
+    restarts: &mut usize,
+) -> anyhow::Result<()> {
+    loop {
+        match sweep(
+            driver.clone(),
+            pass,
+            data_store,
+            config,
+            paths,
+            control,
+            robots,
+        )
+        .await
+        {
+            Ok(swept) => {
+                *driver = swept;
+                return Ok(());
+            }
+            Err(err) if driver::is_session_lost(&err) && *restarts < config.max_driver_restarts => {
+                *restarts += 1;
+                error!(
+                    "Browser session lost, restart {restarts} of {}: {err:?}",
+                    config.max_driver_restarts
+                );
+
+                data_store
+                    .checkpoint()
+                    .context("Failed to save data store before restarting the browser")?;
+                driver::stop_drivers(config, paths).await;
+                (*driver, _) = driver::fire_up_and_setup_the_driver(config, paths)
+                    .await
+                    .context("Failed to restart the browser")?;
+
+                info!("Resuming with {} unchecked URLs", pass.pending.len());
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Merges a finished check into the data store. Results that are worth retrying are
+/// held back in `failed` instead, on a retry anything short of no error is held back.
+fn record_result(
+    url: Url,
+    mut state: State,
+    data_store: &DataStore,
+    config: &Config,
+    control: &RunControl,
+    pass: &mut SweepState,
+) {
+    let _ = pass.pending.remove(&url);
+    soft_404::flag(&url, &mut state, &pass.soft_404_probes, config);
+
+    if pass.attempt == 0 {
+        control.finished(!state.errors.is_empty());
+    } else if state.errors.is_empty() {
+        control.recovered_on_retry();
+    }
+
+    // Retries only recheck, crawling happens on the first pass
+    let links = std::mem::take(&mut state.links);
+    if pass.attempt == 0 && !links.is_empty() {
+        let queued = pass.discovered.len();
+        let followed = plan::crawl(&url, links, pass, config);
+        for link in &followed {
+            data_store.add_linked_from(link, &url);
+        }
+        data_store.mark_seen(&followed);
+        control.discovered(pass.discovered.len() - queued);
+    }
+
+    let hold = if pass.attempt == 0 {
+        plan::is_retryable(&state, config)
+    } else {
+        !state.errors.is_empty()
+    };
+
+    if hold {
+        let _ = pass.failed.insert(url, state);
+    } else {
+        state.succeeded_on_retry = pass.attempt > 0;
+        data_store.merge(url, state, config.history_length);
+        pass.merged += 1;
+
+        if config.checkpoint_interval > 0 && pass.merged.is_multiple_of(config.checkpoint_interval)
+        {
+            match data_store.checkpoint() {
+                Ok(()) => info!("Checkpoint saved after {} results", pass.merged),
+                Err(e) => error!("Failed to save checkpoint: {e:?}"),
+            }
+        }
+    }
+}
+
+/// Sleeps until `deadline` but wakes up early once the run is cancelled.
+pub(crate) async fn sleep_until_or_cancelled(deadline: Instant, control: &RunControl) {
+    while Instant::now() < deadline && !control.is_cancelled() {
+        sleep_until(deadline.min(Instant::now() + Duration::from_millis(250))).await;
+    }
+}
+
+/// Returns early when cancelled, tabs still waiting stay in `pass.pending`.
+async fn sweep(
+    mut driver: WebDriver,
+    pass: &mut SweepState,
+    data_store: &DataStore,
+    config: &Config,
+    paths: &Paths,
+    control: &RunControl,
+    robots: &RobotsCache,
+) -> anyhow::Result<WebDriver> {
+    let mut url_in_waiting: Vec<ActivePages> = Vec::new();
+    // Crawled URLs are in pending as well, a restarted sweep picks them up from there
+    pass.discovered.clear();
+    let mut queue: VecDeque<Url> = pass.pending.iter().cloned().collect();
+    let ready_poll = config.wait_for_ready.then_some(plan::READY_POLL_INTERVAL);
+
+    loop {
+        if control.is_cancelled() {
+            info!("Cancelled, {} URLs left unchecked", pass.pending.len());
+            return Ok(driver);
+        }
+
+        queue.extend(pass.discovered.drain(..));
+        // Out of URLs, waiting tabs may still crawl new ones once they're checked
+        let Some(url) = queue.pop_front() else {
+            let Some(deadline) = plan::next_deadline(&url_in_waiting, ready_poll) else {
+                break;
+            };
+            sleep_until_or_cancelled(deadline, control).await;
+            if control.is_cancelled() {
+                info!("Cancelled, {} URLs left unchecked", pass.pending.len());
+                return Ok(driver);
+            }
+            check_due_pages(
+                &driver,
+                &mut url_in_waiting,
+                data_store,
+                config,
+                paths,
+                control,
+                pass,
+            )
+            .await?;
+            continue;
+        };
+
+        let linktype = match check_link_type(&url, config) {
+            Ok(LinkType::Generic) if content_by_header(&url, config).await => LinkType::Content,
+            Ok(linktype) => linktype,
+            Err(e) => {
+                error!("Failed to check link type: {e:?}");
+                LinkType::InternalError
+            }
+        };
+
+        if linktype == LinkType::Generic
+            && config.respect_robots_txt
+            && !robots::allowed(
+                robots,
+                &url,
+                &config.robots_user_agent,
+                config.proxy.as_ref(),
+            )
+            .await
+        {
+            info!("Skipping {url}, disallowed by robots.txt");
+            control.loading(&url);
+            let state = State::new(
+                "",
+                None,
+                None,
+                LinkType::Generic,
+                vec![CustomError::DisallowedByRobots],
+            );
+            record_result(url, state, data_store, config, control, pass);
+        } else if linktype == LinkType::Generic {
+            // Open tabs are what use the ram, never go above the cap
+            while plan::tab_pool_full(&url_in_waiting, config) {
+                if let Some(deadline) = plan::next_deadline(&url_in_waiting, ready_poll) {
+                    sleep_until_or_cancelled(deadline, control).await;
+                }
+                if control.is_cancelled() {
+                    info!("Cancelled, {} URLs left unchecked", pass.pending.len());
+                    return Ok(driver);
+                }
+                check_due_pages(
+                    &driver,
+                    &mut url_in_waiting,
+                    data_store,
+                    config,
+                    paths,
+                    control,
+                    pass,
+                )
+                .await?;
+            }
+
+            info!("Loading link: {}", url.as_str());
+            control.loading(&url);
+            // A missing password is recorded by check_link, the page still gets loaded
+            let target = match utilities::credentials_for(&url, &config.auth) {
+                Ok(Some(credentials)) => utilities::with_credentials(&url, &credentials),
+                _ => url.clone(),
+            };
+            let navigation_error;
+            (driver, navigation_error) =
+                new_tab(driver, &url, target.as_str(), config.browser).await?;
+            url_in_waiting.push(ActivePages {
+                dwell: plan::dwell_time(&url, config),
+                url,
+                time_added: Instant::now(),
+                linktype,
+                navigation_error,
+                ready_at: None,
+            });
+
+            check_due_pages(
+                &driver,
+                &mut url_in_waiting,
+                data_store,
+                config,
+                paths,
+                control,
+                pass,
+            )
+            .await?;
+        } else {
+            control.loading(&url);
+            let state = check_link(&driver, &url, None, config, paths, linktype, None).await;
+            record_result(url, state, data_store, config, control, pass);
+        }
+    }
+
+    Ok(driver)
+}
+
+/// With `wait_for_ready`, marks the waiting tabs that are ready to be checked. Tabs that
+/// failed to load wait out their dwell time, there's nothing to become ready.
+async fn mark_ready_pages(driver: &WebDriver, url_in_waiting: &mut [ActivePages], config: &Config) {
+    if !config.wait_for_ready {
+        return;
+    }
+
+    for page in url_in_waiting
+        .iter_mut()
+        .filter(|page| page.ready_at.is_none() && page.navigation_error.is_none())
+    {
+        let selector = plan::ready_selector(&page.url, config);
+        if driver::page_ready(driver, &page.url, selector).await {
+            info!(
+                "{} is ready after {:?}",
+                page.url,
+                page.time_added.elapsed()
+            );
+            page.ready_at = Some(Instant::now());
+        }
+    }
+}
+
+/// Checks and closes every tab that is ready or whose dwell time has elapsed, in whatever
+/// order they come due. Tabs are found by window name so the order they were opened in
+/// doesn't matter.
+async fn check_due_pages(
+    driver: &WebDriver,
+    url_in_waiting: &mut Vec<ActivePages>,
+    data_store: &DataStore,
+    config: &Config,
+    paths: &Paths,
+    control: &RunControl,
+    pass: &mut SweepState,
+) -> anyhow::Result<()> {
+    mark_ready_pages(driver, url_in_waiting, config).await;
+
+    let now = Instant::now();
+    for ActivePages {
+        url,
+        linktype,
+        navigation_error,
+        time_added,
+        ready_at,
+        ..
+    } in plan::take_due(url_in_waiting, now)
+    {
+        info!("Removing {} from waiting list", url.as_str());
+
+        let marker = data_store.marker(&url);
+
+        let check = check_link(
+            driver,
+            &url,
+            marker.as_ref(),
+            config,
+            paths,
+            linktype,
+            navigation_error,
+        );
+        let mut state = match tokio::time::timeout(config.per_page_check_timeout, check).await {
+            Ok(state) => state,
+            Err(_) => {
+                warn!(
+                    "Checking {url} took longer than {:?}, closing it",
+                    config.per_page_check_timeout
+                );
+                pass.timed_out += 1;
+                State::new("", None, None, linktype, vec![CustomError::CheckTimedOut])
+            }
+        };
+        state.waited = Some(ready_at.unwrap_or(now).duration_since(time_added));
+        // A tab that is already gone doesn't take the check with it
+        if let Err(err) = driver::safely_close_window(driver, &url).await {
+            if driver::is_session_lost(&err) {
+                return Err(err);
+            }
+            warn!("{err:#}");
+        }
+        record_result(url, state, data_store, config, control, pass);
+    }
+
+    Ok(())
+}
+
+/// Report bucket of every URL of the source the filters let through, see
+/// `RunSummary::statuses`.
+fn source_statuses(
+    entries: &[ReportEntry],
+    source_urls: &HashSet<Url>,
+) -> BTreeMap<Url, ReportStatus> {
+    entries
+        .iter()
+        .filter(|entry| source_urls.contains(&entry.url))
+        .map(|entry| (entry.url.clone(), entry.status))
+        .collect()
+}
+
+#[instrument(skip(config, paths, data_store, control))]
+async fn link_checker(
+    config: &Config,
+    paths: &Paths,
+    metrics: &LatestMetrics,
+    urls: Option<Vec<String>>,
+    data_store: &DataStore,
+    control: &RunControl,
+    trigger: RunTrigger,
+) -> anyhow::Result<RunOutcome> {
+    // Before anything else, stopping drivers would kill those of another running instance
+    let _lock = disc_op::acquire_run_lock(&paths.get(Locations::RunLock))?;
+    data_store
+        .reload()
+        .context("Failed to reload the data store")?;
+    driver::stop_drivers(config, paths).await;
+
+    let urls_to_check = extract::get_urls(config, urls)
+        .await
+        .context("Failed to get URLs to check")?
+        .urls;
+    canonical::migrate(data_store, config, paths)
+        .context("Failed to canonicalize the data store")?;
+    for url in data_store.unreadable_screenshot_hashes() {
+        warn!("Stored screenshot hashes of {url} can't be decoded, forget it to reset them");
+    }
+    let groups = canonical::group(urls_to_check, config);
+    data_store.apply_aliases(&groups);
+    data_store.mark_seen(groups.keys());
+    let markers =
+        disc_op::load_markers(&paths.get(Locations::Markers)).context("Failed to load markers")?;
+    data_store.apply_markers(
+        markers
+            .into_iter()
+            .map(|(url, marker)| (canonical::canonicalize(&url, config), marker))
+            .collect(),
+    );
+
+    let mut source_urls: HashSet<Url> = groups.keys().cloned().collect();
+    let page_datas = data_store.snapshot();
+    let plan = plan::plan_run(groups.into_keys().collect(), &page_datas, config)?;
+    source_urls.retain(|url| !plan.filtered.contains_key(url));
+    let new_urls = plan
+        .to_check
+        .iter()
+        .filter(|url| !page_datas.contains_key(*url))
+        .count();
+    let mut summary = RunSummary {
+        started: chrono::Utc::now(),
+        finished: None,
+        cancelled: false,
+        urls_checked: plan.to_check.len(),
+        new_urls,
+        previously_seen: plan.to_check.len() - new_urls,
+        status_counts: BTreeMap::new(),
+        skipped: plan.filtered,
+        statuses: BTreeMap::new(),
+        extensions_skipped: vec![],
+        trigger,
+        driver_restarts: 0,
+        timed_out: 0,
+        driver_version: config
+            .gecko
+            .remote_url
+            .is_none()
+            .then(|| driver::driver_version(config.browser, paths))
+            .flatten(),
+        config: config.clone(),
+    };
+    disc_op::save_run_summary(&summary, paths).context("Failed to save run summary")?;
+    control.start(plan.to_check.len());
+
+    if plan.to_check.is_empty() {
+        info!("All URLs were checked recently, nothing to do");
+        summary.statuses = source_statuses(
+            &report::classify(data_store.snapshot(), config),
+            &source_urls,
+        );
+        summary.finished = Some(chrono::Utc::now());
+        disc_op::save_run_summary(&summary, paths).context("Failed to save run summary")?;
+        return Ok(RunOutcome::Completed);
+    }
+
+    let checked_urls = plan.to_check.clone();
+    let to_browser = if config.check_mode == CheckMode::Browser {
+        plan.to_check
+    } else {
+        let to_browser = check_over_http(plan.to_check, data_store, config, control).await;
+        data_store.save().context("Failed to save data store")?;
+        to_browser
+    };
+
+    let checked = if to_browser.is_empty() || control.is_cancelled() {
+        Ok((0, 0))
+    } else {
+        let driver = match driver::fire_up_and_setup_the_driver(config, paths).await {
+            Ok((driver, skipped_extensions)) => {
+                summary.extensions_skipped = skipped_extensions;
+                driver
+            }
+            Err(e) => return Err(anyhow::anyhow!(e)),
+        };
+        check_links(driver, to_browser, data_store, config, paths, control).await
+    };
+
+    // Whatever finished before a failure is kept
+    data_store.save().context("Failed to save data store")?;
+
+    if config.keep_local_records && config.max_local_storage_bytes > 0 {
+        if let Err(e) = disc_op::enforce_storage_limit(config.max_local_storage_bytes, paths) {
+            error!("Failed to enforce the local storage limit: {e:?}");
+        }
+    }
+
+    (summary.driver_restarts, summary.timed_out) = checked.context("Failed to check links")?;
+    summary.finished = Some(chrono::Utc::now());
+    summary.cancelled = control.is_cancelled();
+    let entries = report::classify(data_store.snapshot(), config);
+    summary.statuses = source_statuses(&entries, &source_urls);
+    let entries: Vec<ReportEntry> = entries
+        .into_iter()
+        .filter(|entry| checked_urls.contains(&entry.url))
+        .collect();
+    for entry in &entries {
+        *summary.status_counts.entry(entry.status).or_insert(0) += 1;
+    }
+    disc_op::save_run_summary(&summary, paths).context("Failed to save run summary")?;
+    notify::notify(config, &summary, &entries).await;
+    metrics::export(config, &summary, &entries, metrics);
+
+    driver::stop_drivers(config, paths).await;
+
+    if control.is_cancelled() {
+        info!("Link checking cancelled, partial results saved");
+        return Ok(RunOutcome::Cancelled);
+    }
+
+    info!("Link checking completed successfully");
+
+    Ok(RunOutcome::Completed)
+}
+
+/// Everything a run does before it starts the driver, without writing anything.
+pub async fn preview_run(config: &Config, data_store: &DataStore) -> anyhow::Result<RunPreview> {
+    let mut extraction = extract::get_urls(config, None)
+        .await
+        .context("Failed to get URLs to check")?;
+    let groups = canonical::group(std::mem::take(&mut extraction.urls), config);
+
+    plan::preview_run(
+        groups.into_keys().collect(),
+        &extraction,
+        &data_store.snapshot(),
+        config,
+    )
+}
+
+impl Checker {
+    pub fn new(config: Config, paths: Paths) -> Self {
+        Self {
+            config,
+            paths,
+            metrics: LatestMetrics::default(),
+        }
+    }
+
+    /// Leaves the metrics of the runs in `metrics`, shared with a running `metrics::serve`.
+    pub fn with_metrics(mut self, metrics: LatestMetrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// A full run over `urls`, or over the configured source without them: planning,
+    /// the checks, the run summary and the notifications. Results are merged into
+    /// `data_store` and saved.
+    ///
+    /// ```
+    /// use link_rustler::{
+    ///     disc_op,
+    ///     enums::{CheckMode, Locations, RunOutcome, RunTrigger, StorageBackend},
+    ///     structs::{Checker, Config, DataStore, Paths, RunControl},
+    /// };
+    ///
+    /// # #[tokio::main]
+    /// # async fn main() -> anyhow::Result<()> {
+    /// let dir = tempfile::tempdir()?;
+    /// let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+    /// disc_op::init_storage(false, &paths);
+    /// let storage = disc_op::open_storage(
+    ///     StorageBackend::Json,
+    ///     &paths.get(Locations::DataStore),
+    ///     &paths.get(Locations::SqliteStore),
+    /// )?;
+    /// let data_store = DataStore::load(storage)?;
+    ///
+    /// // Local files are checked without a browser
+    /// let page = dir.path().join("index.html");
+    /// std::fs::write(&page, "<title>Home</title>")?;
+    /// let config = Config {
+    ///     check_mode: CheckMode::Http,
+    ///     check_local_links: true,
+    ///     ..Config::default()
+    /// };
+    /// let checker = Checker::new(config, paths);
+    /// let urls = vec![url::Url::from_file_path(&page).unwrap().to_string()];
+    ///
+    /// let control = RunControl::new(|_| {});
+    /// let outcome = checker
+    ///     .run(Some(urls), &data_store, &control, RunTrigger::Manual)
+    ///     .await?;
+    /// assert_eq!(outcome, RunOutcome::Completed);
+    ///
+    /// let summary = checker.report(&data_store)?;
+    /// assert_eq!(summary.first_seen, 1);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn run(
+        &self,
+        urls: Option<Vec<String>>,
+        data_store: &DataStore,
+        control: &RunControl,
+        trigger: RunTrigger,
+    ) -> anyhow::Result<RunOutcome> {
+        link_checker(
+            &self.config,
+            &self.paths,
+            &self.metrics,
+            urls,
+            data_store,
+            control,
+            trigger,
+        )
+        .await
+    }
+
+    /// Starts the browser and checks `urls` in it, without the planning and the run
+    /// summary of `run`. Returns how often the browser was restarted and how many pages
+    /// timed out.
+    ///
+    /// ```no_run
+    /// # use std::collections::HashSet;
+    /// # use link_rustler::structs::{Checker, DataStore, RunControl};
+    /// # async fn example(checker: &Checker, data_store: &DataStore) -> anyhow::Result<()> {
+    /// let urls: HashSet<url::Url> = ["https://example.com", "https://example.org/docs"]
+    ///     .into_iter()
+    ///     .map(|url| url::Url::parse(url).unwrap())
+    ///     .collect();
+    /// let control = RunControl::new(|progress| println!("{} checked", progress.completed));
+    /// let (restarts, timed_out) = checker.check(urls, data_store, &control).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn check(
+        &self,
+        urls: HashSet<Url>,
+        data_store: &DataStore,
+        control: &RunControl,
+    ) -> anyhow::Result<(usize, usize)> {
+        let (driver, _) = driver::fire_up_and_setup_the_driver(&self.config, &self.paths)
+            .await
+            .context("Failed to start the browser")?;
+        let checked =
+            check_links(driver, urls, data_store, &self.config, &self.paths, control).await;
+        data_store.save().context("Failed to save data store")?;
+
+        checked
+    }
+
+    /// Writes the configured reports on everything in `data_store`.
+    pub fn report(&self, data_store: &DataStore) -> anyhow::Result<ReportSummary> {
+        report::gen_post_run_report(data_store.snapshot(), &self.config, &self.paths)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        disc_op,
+        enums::StorageBackend,
+        test_support::{failed_state, state, url},
+    };
+
+    fn open(dir: &std::path::Path) -> DataStore {
+        let storage = disc_op::open_storage(
+            StorageBackend::Json,
+            &dir.join("data_store.json"),
+            &dir.join("data_store.sqlite"),
+        )
+        .unwrap();
+        DataStore::load(storage).unwrap()
+    }
+
+    #[test]
+    fn each_timeout_policy_handles_a_timed_out_page() {
+        let timeout = Some(CustomError::PageLoadTimeout);
+        assert_eq!(
+            apply_timeout_policy(timeout, TimeoutPolicy::CheckAnyway),
+            (None, true)
+        );
+        assert_eq!(
+            apply_timeout_policy(timeout, TimeoutPolicy::MarkError),
+            (timeout, false)
+        );
+        assert_eq!(
+            apply_timeout_policy(timeout, TimeoutPolicy::RetryOnce),
+            (timeout, false)
+        );
+    }
+
+    #[test]
+    fn other_navigation_errors_ignore_the_timeout_policy() {
+        for policy in [
+            TimeoutPolicy::CheckAnyway,
+            TimeoutPolicy::MarkError,
+            TimeoutPolicy::RetryOnce,
+        ] {
+            let insecure = Some(CustomError::InsecureCertificate);
+            assert_eq!(apply_timeout_policy(insecure, policy), (insecure, true));
+            assert_eq!(apply_timeout_policy(None, policy), (None, true));
+        }
+    }
+
+    #[test]
+    fn merging_a_result_adds_to_the_history_of_its_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_store = open(dir.path());
+        let page = url("https://example.com/page");
+        data_store.merge(page.clone(), state("first"), 5);
+        data_store.merge(page.clone(), state("second"), 5);
+
+        let page_datas = data_store.snapshot();
+        assert_eq!(page_datas.len(), 1);
+        let history = &page_datas[&page].history;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].hash, state("first").hash);
+        assert_eq!(history[1].hash, state("second").hash);
+    }
+
+    #[test]
+    fn results_are_saved_every_checkpoint_interval() {
+        let dir = tempfile::tempdir().unwrap();
+        let data_store = open(dir.path());
+        let config = Config {
+            checkpoint_interval: 2,
+            ..Config::default()
+        };
+        let control = RunControl::new(|_| {});
+        let urls: Vec<_> = (1..=3)
+            .map(|n| url(&format!("https://example.com/{n}")))
+            .collect();
+        let mut pass = SweepState::new(0, urls.iter().cloned().collect());
+
+        record_result(
+            urls[0].clone(),
+            state("one"),
+            &data_store,
+            &config,
+            &control,
+            &mut pass,
+        );
+        assert!(open(dir.path()).snapshot().is_empty());
+
+        // A result held back for a retry isn't merged and doesn't count towards a checkpoint
+        let retryable = failed_state(config.retry_errors[0]);
+        record_result(
+            urls[1].clone(),
+            retryable,
+            &data_store,
+            &config,
+            &control,
+            &mut pass,
+        );
+        assert!(open(dir.path()).snapshot().is_empty());
+        assert!(pass.failed.contains_key(&urls[1]));
+
+        record_result(
+            urls[2].clone(),
+            state("three"),
+            &data_store,
+            &config,
+            &control,
+            &mut pass,
+        );
+        let saved = open(dir.path()).snapshot();
+        assert_eq!(saved.keys().collect::<Vec<_>>(), [&urls[0], &urls[2]]);
+        assert_eq!(pass.merged, 2);
+        assert!(pass.pending.is_empty());
+    }
+}
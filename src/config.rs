@@ -1,27 +1,44 @@
-use std::{fs, path::PathBuf, rc::Rc};
+#[cfg(feature = "gui")]
+use std::rc::Rc;
+use std::{fs, path::PathBuf};
 
 use anyhow::Context;
+#[cfg(feature = "gui")]
 use slint::{ComponentHandle, ModelRc, VecModel};
 use tracing::info;
+#[cfg(feature = "gui")]
 use url::Url;
 
 use crate::{
-    get_loc,
-    structs::{AppState, Config},
-    ConfigProperty, Locations, MainWindow, Settings,
+    enums::{Locations, StorageBackend},
+    structs::{Config, Paths},
+};
+#[cfg(feature = "gui")]
+use crate::{
+    enums::{ReportFormat, TimeoutPolicy},
+    structs::AppState,
+    ConfigProperty, MainWindow, Settings,
 };
 
-pub fn load(ui: &MainWindow, app_state: &mut AppState) -> anyhow::Result<Config> {
+#[cfg(feature = "gui")]
+pub fn load(ui: &MainWindow, app_state: &mut AppState, paths: &Paths) -> anyhow::Result<Config> {
     app_state.add_to_config_log("Checking configuration.", ui);
-    let config_path = get_loc(Locations::Config);
+    let config_path = paths.get(Locations::Config);
     let default_config = Config::default();
 
     let config = if config_path.exists() {
         let config_str = fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config file {config_path:?}"))?;
 
-        toml::from_str(&config_str)
-            .with_context(|| format!("Failed to parse config file {config_path:?}"))?
+        let config: Config = toml::from_str(&config_str)
+            .with_context(|| format!("Failed to parse config file {config_path:?}"))?;
+        let _ = config
+            .url_filters()
+            .with_context(|| format!("Invalid config file {config_path:?}"))?;
+        let _ = config
+            .source_filters()
+            .with_context(|| format!("Invalid config file {config_path:?}"))?;
+        config
     } else {
         write_config_file(&default_config, &config_path)?;
 
@@ -41,8 +58,8 @@ pub fn load(ui: &MainWindow, app_state: &mut AppState) -> anyhow::Result<Config>
     Ok(config)
 }
 
-pub fn no_ui_load() -> anyhow::Result<Config> {
-    let config_path = get_loc(Locations::Config);
+pub fn no_ui_load(paths: &Paths) -> anyhow::Result<Config> {
+    let config_path = paths.get(Locations::Config);
     let default_base_path = config_path.parent().unwrap();
 
     let default_config = Config::default();
@@ -51,10 +68,17 @@ pub fn no_ui_load() -> anyhow::Result<Config> {
         let config_str = fs::read_to_string(&config_path)
             .with_context(|| format!("Failed to read config file {config_path:?}"))?;
 
-        toml::from_str(&config_str)
-            .with_context(|| format!("Failed to parse config file {config_path:?}"))?
+        let config: Config = toml::from_str(&config_str)
+            .with_context(|| format!("Failed to parse config file {config_path:?}"))?;
+        let _ = config
+            .url_filters()
+            .with_context(|| format!("Invalid config file {config_path:?}"))?;
+        let _ = config
+            .source_filters()
+            .with_context(|| format!("Invalid config file {config_path:?}"))?;
+        config
     } else {
-        fs::create_dir(default_base_path)
+        fs::create_dir_all(default_base_path)
             .with_context(|| format!("Failed to create data directory {default_base_path:?}"))?;
         write_config_file(&default_config, &config_path)?;
 
@@ -66,6 +90,29 @@ pub fn no_ui_load() -> anyhow::Result<Config> {
     Ok(config)
 }
 
+/// Only `storage_backend`, the data store is opened before the rest of the config is
+/// read. A missing file or setting keeps the JSON data store.
+pub fn storage_backend(paths: &Paths) -> anyhow::Result<StorageBackend> {
+    let config_path = paths.get(Locations::Config);
+    if !config_path.exists() {
+        return Ok(StorageBackend::default());
+    }
+
+    let config_str = fs::read_to_string(&config_path)
+        .with_context(|| format!("Failed to read config file {config_path:?}"))?;
+    let table: toml::Table = toml::from_str(&config_str)
+        .with_context(|| format!("Failed to parse config file {config_path:?}"))?;
+
+    table
+        .get("storage_backend")
+        .map_or(Ok(StorageBackend::default()), |backend| {
+            backend
+                .clone()
+                .try_into()
+                .with_context(|| format!("Invalid storage_backend in {config_path:?}"))
+        })
+}
+
 pub fn write_config_file(config: &Config, config_path: &PathBuf) -> anyhow::Result<()> {
     fs::write(
         config_path,
@@ -76,8 +123,18 @@ pub fn write_config_file(config: &Config, config_path: &PathBuf) -> anyhow::Resu
     Ok(())
 }
 
+#[cfg(feature = "gui")]
 pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
-    let propertys = vec![
+    ui.global::<Settings>()
+        .set_config_propertys(ModelRc::from(Rc::new(VecModel::from(config_properties(
+            config,
+        )))));
+}
+
+/// Every setting the panel shows, each `Key` is one of `CONFIG_KEYS`.
+#[cfg(feature = "gui")]
+fn config_properties(config: &Config) -> Vec<ConfigProperty> {
+    vec![
         ConfigProperty {
             FriendlyName: "Github username".into(),
             Key: "github_username".into(),
@@ -86,10 +143,10 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             Advanced: false,
         },
         ConfigProperty {
-            FriendlyName: "PDF URL".into(),
-            Key: "pdf_url".into(),
+            FriendlyName: "Source URL".into(),
+            Key: "source_url".into(),
             Value: config
-                .pdf_url
+                .source_url
                 .clone()
                 .unwrap_or(Url::parse("https://github.com/").unwrap())
                 .to_string()
@@ -104,6 +161,13 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             DisplaType: "num".into(),
             Advanced: true,
         },
+        ConfigProperty {
+            FriendlyName: "History length".into(),
+            Key: "history_length".into(),
+            Value: config.history_length.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
         ConfigProperty {
             FriendlyName: "Keep local records".into(),
             Key: "keep_local_records".into(),
@@ -111,6 +175,20 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             DisplaType: "bool".into(),
             Advanced: false,
         },
+        ConfigProperty {
+            FriendlyName: "Compress saved page sources".into(),
+            Key: "compress_local_records".into(),
+            Value: config.compress_local_records.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Fail pages missing their marker".into(),
+            Key: "marker_required".into(),
+            Value: config.marker_required.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
         ConfigProperty {
             FriendlyName: "Screenshot diff confidence".into(),
             Key: "screenshot_diff_confidence".into(),
@@ -125,6 +203,29 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             DisplaType: "num".into(),
             Advanced: true,
         },
+        ConfigProperty {
+            FriendlyName: "Screenshot hash algorithm (gradient, mean or dct)".into(),
+            Key: "screenshot_hash_algorithm".into(),
+            Value: format!("{:?}", config.screenshot_hash_algorithm)
+                .to_lowercase()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Screenshot hash size".into(),
+            Key: "screenshot_hash_size".into(),
+            Value: config.screenshot_hash_size.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Preprocess screenshots before hashing".into(),
+            Key: "screenshot_hash_preprocess".into(),
+            Value: config.screenshot_hash_preprocess.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
         ConfigProperty {
             FriendlyName: "Compression length tolerance".into(),
             Key: "compression_length_tolerance".into(),
@@ -132,6 +233,27 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             DisplaType: "num".into(),
             Advanced: true,
         },
+        ConfigProperty {
+            FriendlyName: "Compression length tolerance (%)".into(),
+            Key: "compression_tolerance_percent".into(),
+            Value: config.compression_tolerance_percent.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Flaky above error rate (%)".into(),
+            Key: "flaky_error_percent".into(),
+            Value: config.flaky_error_percent.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Slower above load time factor".into(),
+            Key: "load_time_regression_factor".into(),
+            Value: config.load_time_regression_factor.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
         ConfigProperty {
             FriendlyName: "Page dwell time".into(),
             Key: "page_dwell_time".into(),
@@ -140,14 +262,497 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             Advanced: true,
         },
         ConfigProperty {
-            FriendlyName: "PDF path".into(),
-            Key: "pdf_path".into(),
-            Value: config.pdf_path.clone().unwrap_or("".into()).into(),
+            FriendlyName: "Check pages once ready".into(),
+            Key: "wait_for_ready".into(),
+            Value: config.wait_for_ready.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Ready selector".into(),
+            Key: "ready_selector".into(),
+            Value: config.ready_selector.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Page check timeout".into(),
+            Key: "per_page_check_timeout".into(),
+            Value: config.per_page_check_timeout.as_secs().to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Full page screenshots".into(),
+            Key: "full_page_screenshots".into(),
+            Value: config.full_page_screenshots.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max concurrent tabs".into(),
+            Key: "max_concurrent_tabs".into(),
+            Value: config.max_concurrent_tabs.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Retry attempts".into(),
+            Key: "retry_attempts".into(),
+            Value: config.retry_attempts.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Notification webhook URL".into(),
+            Key: "notify_webhook".into(),
+            Value: config
+                .notify_webhook
+                .as_ref()
+                .map_or(String::new(), Url::to_string)
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Only notify when errors are found".into(),
+            Key: "notify_only_on_errors".into(),
+            Value: config.notify_only_on_errors.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Prometheus metrics file".into(),
+            Key: "metrics_path".into(),
+            Value: config.metrics_path.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Serve metrics in daemon mode on".into(),
+            Key: "metrics_listen".into(),
+            Value: config
+                .metrics_listen
+                .map(|address| address.to_string())
+                .unwrap_or_default()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Proxy URL".into(),
+            Key: "proxy_url".into(),
+            Value: config
+                .proxy
+                .as_ref()
+                .map_or(String::new(), |proxy| proxy.url.to_string())
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Hosts reached without the proxy".into(),
+            Key: "proxy_no_proxy".into(),
+            Value: config
+                .proxy
+                .as_ref()
+                .map_or(String::new(), |proxy| proxy.no_proxy.join(", "))
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Check link anchors".into(),
+            Key: "check_anchors".into(),
+            Value: config.check_anchors.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Check page assets".into(),
+            Key: "check_page_assets".into(),
+            Value: config.check_page_assets.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Max failed assets kept".into(),
+            Key: "max_failed_assets".into(),
+            Value: config.max_failed_assets.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max console errors (empty to never fail)".into(),
+            Key: "max_console_errors".into(),
+            Value: config
+                .max_console_errors
+                .map(|max| max.to_string())
+                .unwrap_or_default()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Ignore #fragments when anchors aren't checked".into(),
+            Key: "strip_fragments".into(),
+            Value: config.strip_fragments.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Lowercase URL hosts".into(),
+            Key: "lowercase_host".into(),
+            Value: config.lowercase_host.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Look up mailto MX records".into(),
+            Key: "check_mailto_mx".into(),
+            Value: config.check_mailto_mx.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "DNS over HTTPS endpoint".into(),
+            Key: "mailto_dns_url".into(),
+            Value: config
+                .mailto_dns_url
+                .as_ref()
+                .map_or(String::new(), Url::to_string)
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Strip scripts and tokens before hashing".into(),
+            Key: "normalize_source".into(),
+            Value: config.normalize_source.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Check local and file:// links".into(),
+            Key: "check_local_links".into(),
+            Value: config.check_local_links.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Respect robots.txt".into(),
+            Key: "respect_robots_txt".into(),
+            Value: config.respect_robots_txt.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "robots.txt user agent".into(),
+            Key: "robots_user_agent".into(),
+            Value: config.robots_user_agent.clone().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max content download (bytes)".into(),
+            Key: "max_content_download".into(),
+            Value: config.max_content_download.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max page source (bytes)".into(),
+            Key: "max_page_source_bytes".into(),
+            Value: config.max_page_source_bytes.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max local record storage (bytes, 0 for no limit)".into(),
+            Key: "max_local_storage_bytes".into(),
+            Value: config.max_local_storage_bytes.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Crawl depth (links followed from the source, 0 for off)".into(),
+            Key: "crawl_depth".into(),
+            Value: config.crawl_depth.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max crawled pages per run".into(),
+            Key: "crawl_max_pages".into(),
+            Value: config.crawl_max_pages.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Detect soft 404s".into(),
+            Key: "detect_soft_404".into(),
+            Value: config.detect_soft_404.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Min URLs per site to probe for soft 404s".into(),
+            Key: "soft_404_min_urls".into(),
+            Value: config.soft_404_min_urls.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Run summaries to keep".into(),
+            Key: "run_history_to_keep".into(),
+            Value: config.run_history_to_keep.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Max browser restarts".into(),
+            Key: "max_driver_restarts".into(),
+            Value: config.max_driver_restarts.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Source path".into(),
+            Key: "source_path".into(),
+            Value: config.source_path.clone().unwrap_or("".into()).into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Source type (pdf, docx, html or markdown, empty to infer)".into(),
+            Key: "source_type".into(),
+            Value: config
+                .source_type
+                .map_or(String::new(), |source_type| {
+                    format!("{source_type:?}").to_lowercase()
+                })
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Sitemap URL (used instead of the source URL)".into(),
+            Key: "sitemap_url".into(),
+            Value: config
+                .sitemap_url
+                .as_ref()
+                .map_or(String::new(), Url::to_string)
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "PDF engine (auto, parser or regex)".into(),
+            Key: "pdf_engine".into(),
+            Value: format!("{:?}", config.pdf_engine).to_lowercase().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Check mode (browser, http or auto)".into(),
+            Key: "check_mode".into(),
+            Value: format!("{:?}", config.check_mode).to_lowercase().into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Slow pages (check_anyway, mark_error or retry_once)".into(),
+            Key: "timeout_policy".into(),
+            Value: match config.timeout_policy {
+                TimeoutPolicy::CheckAnyway => "check_anyway",
+                TimeoutPolicy::MarkError => "mark_error",
+                TimeoutPolicy::RetryOnce => "retry_once",
+            }
+            .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Checkpoint every N results (0 = only at the end)".into(),
+            Key: "checkpoint_interval".into(),
+            Value: config.checkpoint_interval.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Storage backend (json or sqlite, applies after a restart)".into(),
+            Key: "storage_backend".into(),
+            Value: format!("{:?}", config.storage_backend)
+                .to_lowercase()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Scheduled runs (02:30 daily or an interval like 24h)".into(),
+            Key: "schedule".into(),
+            Value: config
+                .schedule
+                .map(|schedule| schedule.to_string())
+                .unwrap_or_default()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Run on schedule".into(),
+            Key: "schedule_enabled".into(),
+            Value: config.schedule_enabled.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Recheck after (hours, 0 = always)".into(),
+            Key: "recheck_after".into(),
+            Value: config
+                .recheck_after
+                .map_or(0, |recheck_after| recheck_after.as_secs() / 3600)
+                .to_string()
+                .into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Resume skips checks newer than (minutes)".into(),
+            Key: "resume_window".into(),
+            Value: (config.resume_window.as_secs() / 60).to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Adaptive screenshot tolerance".into(),
+            Key: "adaptive_tolerance_enabled".into(),
+            Value: config.adaptive_tolerance.enabled.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Adaptive tolerance, standard deviations".into(),
+            Key: "adaptive_tolerance_k".into(),
+            Value: config.adaptive_tolerance.k.to_string().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Adaptive tolerance floor (%)".into(),
+            Key: "adaptive_tolerance_floor".into(),
+            Value: config.adaptive_tolerance.floor.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Adaptive tolerance ceiling (%)".into(),
+            Key: "adaptive_tolerance_ceiling".into(),
+            Value: config.adaptive_tolerance.ceiling.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Adaptive tolerance needs this many checks".into(),
+            Key: "adaptive_tolerance_min_samples".into(),
+            Value: config.adaptive_tolerance.min_samples.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Generate JSON report".into(),
+            Key: "report_json".into(),
+            Value: config
+                .report_formats
+                .contains(&ReportFormat::Json)
+                .to_string()
+                .into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Group the report by domain".into(),
+            Key: "report_group_by_domain".into(),
+            Value: config.report_group_by_domain.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Sortable, filterable report".into(),
+            Key: "report_interactive".into(),
+            Value: config.report_interactive.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Report theme (dark, light or auto)".into(),
+            Key: "report_theme".into(),
+            Value: format!("{:?}", config.report_theme).to_lowercase().into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Custom report CSS file".into(),
+            Key: "report_custom_css_path".into(),
+            Value: config
+                .report_custom_css_path
+                .clone()
+                .unwrap_or_default()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "JUnit XML report path".into(),
+            Key: "junit_path".into(),
+            Value: config.junit_path.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Browser (firefox or chrome)".into(),
+            Key: "browser".into(),
+            Value: format!("{:?}", config.browser).to_lowercase().into(),
             DisplaType: "string".into(),
             Advanced: false,
         },
         ConfigProperty {
-            FriendlyName: "Gecko version".into(),
+            FriendlyName: "Fail when an extension can't be installed".into(),
+            Key: "extensions_required".into(),
+            Value: config.extensions_required.to_string().into(),
+            DisplaType: "bool".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Firefox user-agent".into(),
+            Key: "user_agent".into(),
+            Value: config.user_agent.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Browser locale".into(),
+            Key: "browser_locale".into(),
+            Value: config.browser_locale.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Browser time zone".into(),
+            Key: "browser_timezone".into(),
+            Value: config.browser_timezone.clone().unwrap_or_default().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Chromedriver version".into(),
+            Key: "chromedriver_version".into(),
+            Value: config.chromedriver_version.clone().into(),
+            DisplaType: "string".into(),
+            Advanced: false,
+        },
+        ConfigProperty {
+            FriendlyName: "Gecko version (or latest)".into(),
             Key: "gecko_version".into(),
             Value: config.gecko.version.clone().into(),
             DisplaType: "string".into(),
@@ -188,8 +793,133 @@ pub fn fill_gui_config_panel(ui: &MainWindow, config: &Config) {
             DisplaType: "num".into(),
             Advanced: true,
         },
-    ];
+        ConfigProperty {
+            FriendlyName: "Gecko IP".into(),
+            Key: "gecko_ip".into(),
+            Value: config.gecko.ip.clone().into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Gecko port".into(),
+            Key: "gecko_port".into(),
+            Value: config.gecko.port.to_string().into(),
+            DisplaType: "num".into(),
+            Advanced: true,
+        },
+        ConfigProperty {
+            FriendlyName: "Remote WebDriver URL".into(),
+            Key: "gecko_remote_url".into(),
+            Value: config
+                .gecko
+                .remote_url
+                .as_ref()
+                .map(Url::to_string)
+                .unwrap_or_default()
+                .into(),
+            DisplaType: "string".into(),
+            Advanced: true,
+        },
+    ]
+}
 
-    ui.global::<Settings>()
-        .set_config_propertys(ModelRc::from(Rc::new(VecModel::from(propertys))));
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+    use crate::structs::CONFIG_KEYS;
+
+    /// Older names `Config::update` still takes, the panel only shows the current one.
+    #[cfg(feature = "gui")]
+    const ALIASES: &[&str] = &["pdf_url", "pdf_path"];
+
+    /// Tables only the config file sets, `proxy_url` covers the proxy in the panel.
+    const FILE_ONLY: &[&str] = &["proxy", "login"];
+
+    fn keys() -> BTreeSet<&'static str> {
+        CONFIG_KEYS.iter().map(|(key, _)| *key).collect()
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn every_panel_setting_is_a_config_key_and_applies_as_shown() {
+        let config = Config::default();
+        let saved = toml::to_string(&config).unwrap();
+
+        for property in config_properties(&config) {
+            assert!(keys().contains(property.Key.as_str()), "{}", property.Key);
+
+            let mut updated = config.clone();
+            if let Err(err) = updated.update(&property.Key, &property.Value) {
+                panic!("{} doesn't take what the panel shows: {err}", property.Key);
+            }
+            assert_eq!(
+                toml::to_string(&updated).unwrap(),
+                saved,
+                "{}",
+                property.Key
+            );
+        }
+    }
+
+    #[cfg(feature = "gui")]
+    #[test]
+    fn every_config_key_is_in_the_panel() {
+        let shown: BTreeSet<String> = config_properties(&Config::default())
+            .into_iter()
+            .map(|property| property.Key.to_string())
+            .collect();
+        let missing: Vec<_> = keys()
+            .into_iter()
+            .filter(|key| !shown.contains(*key) && !ALIASES.contains(key))
+            .collect();
+        assert_eq!(missing, Vec::<&str>::new());
+    }
+
+    #[test]
+    fn every_scalar_config_field_has_a_key() {
+        let serde_json::Value::Object(fields) = serde_json::to_value(Config::default()).unwrap()
+        else {
+            panic!("Config isn't a table");
+        };
+
+        let mut missing = vec![];
+        for (field, value) in fields {
+            let nested = match value {
+                serde_json::Value::Object(nested) => nested
+                    .into_iter()
+                    .map(|(name, value)| (format!("{field}_{name}"), value))
+                    .collect(),
+                value => vec![(field, value)],
+            };
+            for (key, value) in nested {
+                let scalar = !matches!(
+                    value,
+                    serde_json::Value::Array(_) | serde_json::Value::Object(_)
+                );
+                if scalar && !keys().contains(key.as_str()) && !FILE_ONLY.contains(&key.as_str()) {
+                    missing.push(key);
+                }
+            }
+        }
+        assert_eq!(missing, Vec::<String>::new());
+    }
+
+    #[test]
+    fn an_invalid_url_filter_fails_the_config_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        let config_path = paths.get(Locations::Config);
+        fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+        let mut config = toml::to_string(&Config::default()).unwrap();
+        config = config.replace(
+            "url_exclude_patterns = []",
+            "url_exclude_patterns = [\"re:(unclosed\"]",
+        );
+        fs::write(&config_path, config).unwrap();
+
+        let err = no_ui_load(&paths).unwrap_err();
+        assert!(format!("{err:#}").contains("re:(unclosed"), "{err:#}");
+    }
 }
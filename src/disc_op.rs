@@ -2,25 +2,32 @@ use std::{
     collections::BTreeMap,
     fs::{self, File, OpenOptions},
     io::{Read, Write},
-    path::PathBuf,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::Duration,
 };
 
 use anyhow::Context;
 use chrono::Utc;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use reqwest::Url;
 use tracing::{error, info, instrument, warn};
 
 use crate::{
-    get_loc,
-    structs::{Config, PageData},
-    utilities::{hash_string, remove_old_files},
-    Locations,
+    driver,
+    enums::{ImportStrategy, Locations, StorageBackend},
+    imaging,
+    structs::{
+        Config, DataStore, DeletionRecord, Eviction, ImportSummary, JsonStore, LocalRecord,
+        PageData, PagesUsage, Paths, RunLock, RunSummary, SqliteStore,
+    },
+    utilities::{entry_selected, hash_string, remove_old_files},
 };
 
 #[instrument]
-pub fn init_storage(clean_start: bool) {
-    let base_config_dir = get_loc(Locations::BaseConfig);
-    let base_data_dir = get_loc(Locations::BaseData);
+pub fn init_storage(clean_start: bool, paths: &Paths) {
+    let base_config_dir = paths.get(Locations::BaseConfig);
+    let base_data_dir = paths.get(Locations::BaseData);
 
     let dirs = vec![base_config_dir, base_data_dir];
 
@@ -40,53 +47,687 @@ pub fn init_storage(clean_start: bool) {
     }
 }
 
-pub fn load_data_store(data_store_path: &PathBuf) -> anyhow::Result<BTreeMap<Url, PageData>> {
-    let path_str = data_store_path.to_string_lossy();
+/// Reads the user maintained `markers.toml`, a table of URL to marker string.
+/// A missing file just means no markers are configured.
+pub fn load_markers(markers_path: &PathBuf) -> anyhow::Result<BTreeMap<Url, String>> {
+    let path_str = markers_path.to_string_lossy();
+
+    if !markers_path.exists() {
+        info!("Markers file does not exist: {path_str}");
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = fs::read_to_string(markers_path)
+        .with_context(|| format!("Failed to read markers file: {path_str}"))?;
+    let raw: BTreeMap<String, String> = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse markers file: {path_str}"))?;
+
+    raw.into_iter()
+        .map(|(url, marker)| {
+            let url = Url::parse(&url)
+                .with_context(|| format!("Invalid URL in markers file {path_str}: {url}"))?;
+            Ok((url, marker))
+        })
+        .collect()
+}
+
+/// The previous version of the data store, replaced by every save.
+const BACKUP_SUFFIX: &str = ".bak";
+
+/// `path` with `suffix` added to its file name, for the files kept next to the data store.
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn read_data_store(path: &Path) -> anyhow::Result<BTreeMap<Url, PageData>> {
+    let path_str = path.to_string_lossy();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open hash file: {path_str}"))?;
+    let mut contents = String::new();
+    let _ = file
+        .read_to_string(&mut contents)
+        .with_context(|| format!("Failed to read hash file: {path_str}"))?;
+    serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse hash file: {path_str}"))
+}
+
+/// Falls back to the backup `save_data_store` keeps when the data store is missing or
+/// can't be read, the checks since that backup are lost then.
+pub fn load_data_store(data_store_path: &Path) -> anyhow::Result<BTreeMap<Url, PageData>> {
+    let backup_path = sibling_path(data_store_path, BACKUP_SUFFIX);
 
     if data_store_path.exists() {
-        let mut file = File::open(data_store_path)
-            .with_context(|| format!("Failed to open hash file: {path_str}"))?;
-        let mut contents = String::new();
-        let _ = file
-            .read_to_string(&mut contents)
-            .with_context(|| format!("Failed to read hash file: {path_str}"))?;
-        let data_store = serde_json::from_str(&contents)
-            .with_context(|| format!("Failed to parse hash file: {path_str}"))?;
-        Ok(data_store)
+        match read_data_store(data_store_path) {
+            Ok(data_store) => Ok(data_store),
+            Err(err) if backup_path.exists() => {
+                error!(
+                    "DATA STORE UNREADABLE, falling back to the backup {backup_path:?}: {err:?}"
+                );
+                read_data_store(&backup_path).map_err(|backup_err| {
+                    err.context(format!("The backup can't be read either: {backup_err:#}"))
+                })
+            }
+            Err(err) => Err(err),
+        }
+    } else if backup_path.exists() {
+        // A save was interrupted between moving the data store aside and replacing it
+        warn!("Data store missing, loading the backup {backup_path:?}");
+        read_data_store(&backup_path)
     } else {
-        info!("Data store path does not exist: {path_str}");
+        info!(
+            "Data store path does not exist: {}",
+            data_store_path.to_string_lossy()
+        );
         Ok(BTreeMap::new())
     }
 }
 
+/// One file per run named after its start time, so saving the same run again
+/// overwrites it and the newest run sorts last.
+pub fn save_run_summary(summary: &RunSummary, paths: &Paths) -> anyhow::Result<()> {
+    let history_dir = paths.get(Locations::RunHistory);
+    fs::create_dir_all(&history_dir)
+        .with_context(|| format!("Failed to create run history directory {history_dir:?}"))?;
+
+    let summary_path = history_dir.join(format!(
+        "run_{}.json",
+        summary.started.format("%Y%m%d_%H%M%S")
+    ));
+    let serialized =
+        serde_json::to_string_pretty(summary).context("Failed to serialize run summary")?;
+    fs::write(&summary_path, serialized)
+        .with_context(|| format!("Failed to write run summary to {summary_path:?}"))?;
+
+    remove_old_files(&history_dir, summary.config.run_history_to_keep);
+    Ok(())
+}
+
+/// Every saved run summary, oldest first.
+fn run_summary_paths(paths: &Paths) -> anyhow::Result<Vec<PathBuf>> {
+    let history_dir = paths.get(Locations::RunHistory);
+    if !history_dir.exists() {
+        return Ok(vec![]);
+    }
+
+    let mut summaries: Vec<PathBuf> = fs::read_dir(&history_dir)
+        .with_context(|| format!("Failed to read run history directory {history_dir:?}"))?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "json")
+        })
+        .collect();
+    summaries.sort();
+    Ok(summaries)
+}
+
+/// The start time a run summary is named after, `20240131_020000` for
+/// `run_20240131_020000.json`.
+fn run_id(path: &Path) -> String {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    stem.strip_prefix("run_").unwrap_or(&stem).to_string()
+}
+
+fn read_run_summary(path: &Path) -> anyhow::Result<RunSummary> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read run summary {path:?}"))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse run summary {path:?}"))
+}
+
+pub fn load_latest_run_summary(paths: &Paths) -> anyhow::Result<Option<RunSummary>> {
+    let Some(latest) = run_summary_paths(paths)?.pop() else {
+        return Ok(None);
+    };
+
+    read_run_summary(&latest).map(Some)
+}
+
+/// The runs `from` and `to` by run ID. Without `to` it's the newest run, without
+/// `from` the run before `to`. Runs saved before per-URL statuses were kept, or that
+/// failed before the end, can't be compared.
+pub fn load_run_pair(
+    from: Option<&str>,
+    to: Option<&str>,
+    paths: &Paths,
+) -> anyhow::Result<(RunSummary, RunSummary)> {
+    let summaries = run_summary_paths(paths)?;
+    let ids: Vec<String> = summaries.iter().map(|path| run_id(path)).collect();
+    let position = |id: &str| {
+        ids.iter().position(|known| known == id).with_context(|| {
+            format!(
+                "There is no run {id}, the saved runs are {}",
+                ids.join(", ")
+            )
+        })
+    };
+
+    let to_index = match to {
+        Some(id) => position(id)?,
+        None => ids.len().checked_sub(1).context("No runs are saved yet")?,
+    };
+    let from_index = match from {
+        Some(id) => position(id)?,
+        None => to_index.checked_sub(1).with_context(|| {
+            format!(
+                "Run {} is the oldest saved, there is nothing before it",
+                ids[to_index]
+            )
+        })?,
+    };
+
+    let comparable = |index: usize| -> anyhow::Result<RunSummary> {
+        let summary = read_run_summary(&summaries[index])?;
+        if summary.statuses.is_empty() {
+            anyhow::bail!(
+                "Run {} has no per-URL statuses, it's from an older version or didn't finish",
+                ids[index]
+            );
+        }
+        Ok(summary)
+    };
+    Ok((comparable(from_index)?, comparable(to_index)?))
+}
+
+/// Written to a temporary file next to the data store first and renamed over it once
+/// complete, a crash mid-save leaves the previous data store as it was. The version it
+/// replaces is kept as a backup.
 #[instrument]
 pub fn save_data_store(
     page_datas: &BTreeMap<Url, PageData>,
-    data_store_path: &PathBuf,
+    data_store_path: &Path,
 ) -> anyhow::Result<(), anyhow::Error> {
-    let mut data_store_file = if data_store_path.exists() {
-        OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(data_store_path)
-            .with_context(|| format!("Failed to open file at {data_store_path:?}"))?
-    } else {
-        File::create(data_store_path)
-            .with_context(|| format!("Failed to create file at {data_store_path:?}"))?
-    };
-
+    // Nothing on disk is touched until the data store is serialized
     let serialized = serde_json::to_string_pretty(&page_datas)
-        .map_err(|e| {
-            std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to serialize HashMap: {e}"),
-            )
-        })
+        .map_err(|e| std::io::Error::other(format!("Failed to serialize HashMap: {e}")))
         .context("Failed to serialize page data")?;
 
+    let temp_path = sibling_path(data_store_path, ".tmp");
+    let mut data_store_file = File::create(&temp_path)
+        .with_context(|| format!("Failed to create file at {temp_path:?}"))?;
     data_store_file
         .write_all(serialized.as_bytes())
-        .with_context(|| "Failed to write serialized data to file")?;
+        .and_then(|()| data_store_file.sync_all())
+        .with_context(|| format!("Failed to write serialized data to {temp_path:?}"))?;
+    drop(data_store_file);
+
+    if data_store_path.exists() {
+        let backup_path = sibling_path(data_store_path, BACKUP_SUFFIX);
+        fs::rename(data_store_path, &backup_path)
+            .with_context(|| format!("Failed to back up {data_store_path:?} to {backup_path:?}"))?;
+    }
+    fs::rename(&temp_path, data_store_path).with_context(|| {
+        format!("Failed to replace {data_store_path:?} with the saved data store")
+    })?;
+
+    Ok(())
+}
+
+/// Where `DataStore` keeps its entries. The entries are all held in memory, a backend
+/// reads them once at the start and writes back what changed.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    fn load_all(&self) -> anyhow::Result<BTreeMap<Url, PageData>>;
+    /// Writes the entry of `url`, the others stay as they are on disk.
+    fn save_page(&self, url: &Url, page_data: &PageData) -> anyhow::Result<()>;
+    /// Replaces everything on disk with `page_datas`.
+    fn save_all(&self, page_datas: &BTreeMap<Url, PageData>) -> anyhow::Result<()>;
+    /// Removes the entries of `urls` from disk.
+    fn prune(&self, urls: &[Url]) -> anyhow::Result<()>;
+    /// Whether `save_page` is cheaper than `save_all`, checkpoints write single entries then.
+    fn incremental(&self) -> bool;
+}
+
+impl JsonStore {
+    pub fn new(path: PathBuf) -> Self {
+        JsonStore {
+            path,
+            file: Mutex::new(()),
+        }
+    }
+
+    fn lock_file(&self) -> MutexGuard<'_, ()> {
+        self.file.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A single entry can't be written on its own, `save_page` and `prune` rewrite the file.
+impl Storage for JsonStore {
+    fn load_all(&self) -> anyhow::Result<BTreeMap<Url, PageData>> {
+        let _file = self.lock_file();
+        load_data_store(&self.path)
+    }
+
+    fn save_page(&self, url: &Url, page_data: &PageData) -> anyhow::Result<()> {
+        let _file = self.lock_file();
+        let mut page_datas = load_data_store(&self.path)?;
+        let _ = page_datas.insert(url.clone(), page_data.clone());
+        save_data_store(&page_datas, &self.path)
+    }
+
+    fn save_all(&self, page_datas: &BTreeMap<Url, PageData>) -> anyhow::Result<()> {
+        let _file = self.lock_file();
+        save_data_store(page_datas, &self.path)
+    }
+
+    /// The backup is replaced as well, it would bring the removed entries back.
+    fn prune(&self, urls: &[Url]) -> anyhow::Result<()> {
+        let _file = self.lock_file();
+        let mut page_datas = load_data_store(&self.path)?;
+        for url in urls {
+            let _ = page_datas.remove(url);
+        }
+        save_data_store(&page_datas, &self.path)?;
+
+        let backup_path = sibling_path(&self.path, BACKUP_SUFFIX);
+        let temp_path = sibling_path(&backup_path, ".tmp");
+        let _ = fs::copy(&self.path, &temp_path)
+            .with_context(|| format!("Failed to copy {:?} to {temp_path:?}", self.path))?;
+        fs::rename(&temp_path, &backup_path)
+            .with_context(|| format!("Failed to replace the backup {backup_path:?}"))
+    }
+
+    fn incremental(&self) -> bool {
+        false
+    }
+}
+
+impl SqliteStore {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let connection = rusqlite::Connection::open(&path)
+            .with_context(|| format!("Failed to open SQLite data store {path:?}"))?;
+        let _ = connection
+            .execute(
+                "CREATE TABLE IF NOT EXISTS page_data (url TEXT PRIMARY KEY NOT NULL, data TEXT NOT NULL)",
+                [],
+            )
+            .with_context(|| format!("Failed to create the page_data table in {path:?}"))?;
+
+        Ok(SqliteStore {
+            connection: Mutex::new(connection),
+            path,
+        })
+    }
+
+    fn connection(&self) -> MutexGuard<'_, rusqlite::Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+const UPSERT_PAGE: &str = "INSERT INTO page_data (url, data) VALUES (?1, ?2) \
+    ON CONFLICT (url) DO UPDATE SET data = excluded.data";
+
+impl Storage for SqliteStore {
+    fn load_all(&self) -> anyhow::Result<BTreeMap<Url, PageData>> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare("SELECT url, data FROM page_data")
+            .with_context(|| format!("Failed to query {:?}", self.path))?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })
+            .with_context(|| format!("Failed to query {:?}", self.path))?;
+
+        rows.map(|row| {
+            let (url, data) = row.with_context(|| format!("Failed to read {:?}", self.path))?;
+            let url = Url::parse(&url)
+                .with_context(|| format!("Invalid URL in {:?}: {url}", self.path))?;
+            let page_data = serde_json::from_str(&data)
+                .with_context(|| format!("Failed to parse the entry of {url}"))?;
+            Ok((url, page_data))
+        })
+        .collect()
+    }
+
+    fn save_page(&self, url: &Url, page_data: &PageData) -> anyhow::Result<()> {
+        let data = serde_json::to_string(page_data).context("Failed to serialize page data")?;
+        let _ = self
+            .connection()
+            .execute(UPSERT_PAGE, (url.as_str(), data))
+            .with_context(|| format!("Failed to save the entry of {url}"))?;
+        Ok(())
+    }
+
+    /// In one transaction, a crash mid-save leaves the previous data store.
+    fn save_all(&self, page_datas: &BTreeMap<Url, PageData>) -> anyhow::Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection
+            .transaction()
+            .context("Failed to start saving the data store")?;
+        let _ = transaction
+            .execute("DELETE FROM page_data", [])
+            .context("Failed to clear the data store")?;
+        {
+            let mut upsert = transaction
+                .prepare(UPSERT_PAGE)
+                .context("Failed to prepare saving the data store")?;
+            for (url, page_data) in page_datas {
+                let data =
+                    serde_json::to_string(page_data).context("Failed to serialize page data")?;
+                let _ = upsert
+                    .execute((url.as_str(), data))
+                    .with_context(|| format!("Failed to save the entry of {url}"))?;
+            }
+        }
+        transaction
+            .commit()
+            .with_context(|| format!("Failed to save the data store to {:?}", self.path))
+    }
+
+    fn prune(&self, urls: &[Url]) -> anyhow::Result<()> {
+        let mut connection = self.connection();
+        let transaction = connection
+            .transaction()
+            .context("Failed to start removing entries")?;
+        for url in urls {
+            let _ = transaction
+                .execute("DELETE FROM page_data WHERE url = ?1", [url.as_str()])
+                .with_context(|| format!("Failed to remove the entry of {url}"))?;
+        }
+        transaction
+            .commit()
+            .with_context(|| format!("Failed to remove entries from {:?}", self.path))
+    }
+
+    fn incremental(&self) -> bool {
+        true
+    }
+}
+
+/// Opens the data store of `backend`. A JSON data store that wasn't moved to SQLite yet
+/// is an error, starting over with an empty one would look like every URL is new.
+pub fn open_storage(
+    backend: StorageBackend,
+    json_path: &Path,
+    sqlite_path: &Path,
+) -> anyhow::Result<Arc<dyn Storage>> {
+    match backend {
+        StorageBackend::Json => Ok(Arc::new(JsonStore::new(json_path.to_path_buf()))),
+        StorageBackend::Sqlite => {
+            if !sqlite_path.exists() && json_path.exists() {
+                anyhow::bail!(
+                    "storage_backend is sqlite but the data store is still {json_path:?}, run the migrate-store command first"
+                );
+            }
+            Ok(Arc::new(SqliteStore::open(sqlite_path.to_path_buf())?))
+        }
+    }
+}
+
+/// Copies the JSON data store into a new SQLite data store at `sqlite_path`, the JSON
+/// file is left as it is. Returns how many entries were copied.
+#[instrument]
+pub fn migrate_to_sqlite(json_path: &Path, sqlite_path: &Path) -> anyhow::Result<usize> {
+    if sqlite_path.exists() {
+        anyhow::bail!("{sqlite_path:?} already exists, remove it to migrate again");
+    }
+    let page_datas = load_data_store(json_path)?;
+
+    // Written under another name first, an interrupted migration leaves no data store
+    // that looks complete
+    let temp_path = sibling_path(sqlite_path, ".tmp");
+    if temp_path.exists() {
+        fs::remove_file(&temp_path)
+            .with_context(|| format!("Failed to remove the leftover {temp_path:?}"))?;
+    }
+    let store = SqliteStore::open(temp_path.clone())?;
+    store.save_all(&page_datas)?;
+    let copied = store.load_all()?.len();
+    drop(store);
+    if copied != page_datas.len() {
+        anyhow::bail!(
+            "Only {copied} of {} entries were copied to {temp_path:?}",
+            page_datas.len()
+        );
+    }
+    fs::rename(&temp_path, sqlite_path)
+        .with_context(|| format!("Failed to move {temp_path:?} to {sqlite_path:?}"))?;
+
+    info!("Copied {copied} entries from {json_path:?} to {sqlite_path:?}");
+    Ok(copied)
+}
+
+/// A lock older than this belongs to a run that crashed, even when its PID has since
+/// been reused by another process.
+const RUN_LOCK_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long a lock file may stay empty or mangled while its owner is still writing it.
+const RUN_LOCK_GRACE: Duration = Duration::from_secs(60);
+
+/// Whether `pid` is another instance of this program. A PID the system handed to an
+/// unrelated process since doesn't hold the lock.
+fn is_link_rustler(pid: u32) -> bool {
+    let Some(name) = driver::process_name(pid) else {
+        return false;
+    };
+    let own_name = std::env::current_exe()
+        .ok()
+        .and_then(|exe| Some(exe.file_stem()?.to_string_lossy().to_string()))
+        .unwrap_or_else(|| env!("CARGO_PKG_NAME").to_string());
+
+    // ps cuts names off after 15 characters
+    name == own_name || (name.len() == 15 && own_name.starts_with(&name))
+}
+
+/// Whether the lock at `path` was left behind, by a process that is gone or a run that
+/// should have finished long ago.
+fn run_lock_stale(path: &Path) -> bool {
+    let contents = fs::read_to_string(path).unwrap_or_default();
+    let parsed = contents.trim().split_once(' ').and_then(|(pid, started)| {
+        let pid: u32 = pid.parse().ok()?;
+        let started = chrono::DateTime::parse_from_rfc3339(started).ok()?;
+        Some((pid, started.with_timezone(&Utc)))
+    });
+
+    match parsed {
+        Some((pid, started)) => {
+            !is_link_rustler(pid)
+                || (Utc::now() - started)
+                    .to_std()
+                    .is_ok_and(|age| age > RUN_LOCK_MAX_AGE)
+        }
+        None => fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_none_or(|age| age > RUN_LOCK_GRACE),
+    }
+}
+
+/// Makes sure only one instance writes the data store at a time. Fails straight away
+/// when another run holds the lock, a stale lock is taken over.
+#[instrument]
+pub fn acquire_run_lock(path: &Path) -> anyhow::Result<RunLock> {
+    for _ in 0..2 {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                let lock = RunLock {
+                    path: path.to_path_buf(),
+                };
+                write!(file, "{} {}", std::process::id(), Utc::now().to_rfc3339())
+                    .with_context(|| format!("Failed to write lock file {path:?}"))?;
+                return Ok(lock);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                if !run_lock_stale(path) {
+                    let owner = fs::read_to_string(path).unwrap_or_default();
+                    anyhow::bail!(
+                        "Another run is in progress (lock {path:?} held by PID {})",
+                        owner.split(' ').next().unwrap_or("unknown")
+                    );
+                }
+                warn!("Removing stale lock file {path:?}");
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale lock file {path:?}"))?;
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to create lock file {path:?}"))
+            }
+        }
+    }
+
+    anyhow::bail!("Another run took the lock at {path:?} while a stale one was removed")
+}
+
+/// Written next to the saved pages, highlights what changed between the two newest
+/// screenshots. Only present when they differ.
+pub const SCREENSHOT_DIFF_FILE: &str = "screenshot_diff.png";
+/// Scaled down copy of the newest screenshot, made when the report is generated.
+pub const THUMBNAIL_FILE: &str = "thumbnail.png";
+
+fn screenshots(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with("screenshot_")
+                        && name.ends_with(".png")
+                        && name != SCREENSHOT_DIFF_FILE
+                })
+        })
+        .collect()
+}
+
+const PAGE_SUFFIX: &str = ".html";
+const COMPRESSED_PAGE_SUFFIX: &str = ".html.gz";
+
+/// Saved page sources, plain and gzipped alike.
+fn page_sources(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with("page_")
+                        && (name.ends_with(PAGE_SUFFIX) || name.ends_with(COMPRESSED_PAGE_SUFFIX))
+                })
+        })
+        .collect()
+}
+
+/// Reads a saved page source whether or not it was compressed.
+pub fn read_page_source(path: &Path) -> anyhow::Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    if !path.to_string_lossy().ends_with(COMPRESSED_PAGE_SUFFIX) {
+        return String::from_utf8(bytes).with_context(|| format!("{path:?} isn't UTF-8"));
+    }
+
+    let mut source = String::new();
+    let _ = GzDecoder::new(bytes.as_slice())
+        .read_to_string(&mut source)
+        .with_context(|| format!("Failed to decompress {path:?}"))?;
+    Ok(source)
+}
+
+/// Every page source and screenshot in `dir` with the time it was saved, read from its
+/// file name. Files whose name doesn't parse are left out.
+pub fn timed_records(dir: &Path) -> Vec<(chrono::DateTime<Utc>, PathBuf)> {
+    page_sources(dir)
+        .into_iter()
+        .chain(screenshots(dir))
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let time = name
+                .strip_prefix("page_")
+                .or_else(|| name.strip_prefix("screenshot_"))?
+                .trim_end_matches(COMPRESSED_PAGE_SUFFIX)
+                .trim_end_matches(PAGE_SUFFIX)
+                .trim_end_matches(".png")
+                .parse()
+                .ok()?;
+            Some((time, path))
+        })
+        .collect()
+}
+
+/// The newest page source saved for `url`, `None` when there is none.
+pub fn latest_page_source(url: &Url, paths: &Paths) -> anyhow::Result<Option<String>> {
+    let dir = paths
+        .get(Locations::PagesSubdir)
+        .join(hash_string(&url.to_string()));
+    page_sources(&dir)
+        .into_iter()
+        .max()
+        .map(|path| read_page_source(&path))
+        .transpose()
+}
+
+fn previous_screenshot(dir: &Path, current: &Path) -> Option<PathBuf> {
+    screenshots(dir)
+        .into_iter()
+        .filter(|path| path.as_path() != current)
+        .max()
+}
+
+/// The file names carry the capture time, so the newest sorts last.
+pub fn latest_screenshot(dir: &Path) -> Option<PathBuf> {
+    screenshots(dir).into_iter().max()
+}
+
+/// Regenerates the thumbnail for `url` unless it's already newer than the screenshot
+/// it was made from. `None` when no screenshot was saved for the URL.
+pub fn refresh_thumbnail(url: &Url, paths: &Paths) -> anyhow::Result<Option<PathBuf>> {
+    let dir = paths
+        .get(Locations::PagesSubdir)
+        .join(hash_string(&url.to_string()));
+    let Some(screenshot_path) = latest_screenshot(&dir) else {
+        return Ok(None);
+    };
+    let thumbnail_path = dir.join(THUMBNAIL_FILE);
+
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    if let (Some(thumbnail_time), Some(screenshot_time)) =
+        (modified(&thumbnail_path), modified(&screenshot_path))
+    {
+        if thumbnail_time >= screenshot_time {
+            return Ok(Some(thumbnail_path));
+        }
+    }
+
+    let screenshot = image::open(&screenshot_path)
+        .with_context(|| format!("Failed to open screenshot: {screenshot_path:?}"))?;
+    imaging::thumbnail(&screenshot)
+        .save(&thumbnail_path)
+        .with_context(|| format!("Failed to save thumbnail: {thumbnail_path:?}"))?;
+
+    Ok(Some(thumbnail_path))
+}
+
+/// Diffs `img` against the previous screenshot in `dir`, does nothing when there isn't
+/// one yet.
+fn save_screenshot_diff(
+    dir: &Path,
+    current: &Path,
+    img: &image::DynamicImage,
+) -> anyhow::Result<()> {
+    let Some(previous_path) = previous_screenshot(dir, current) else {
+        return Ok(());
+    };
+
+    let previous = image::open(&previous_path)
+        .with_context(|| format!("Failed to open screenshot: {previous_path:?}"))?;
+
+    if let Some(diff) = imaging::screenshot_diff(&previous, img) {
+        let diff_path = dir.join(SCREENSHOT_DIFF_FILE);
+        diff.save(&diff_path)
+            .with_context(|| format!("Failed to save screenshot diff: {diff_path:?}"))?;
+    }
 
     Ok(())
 }
@@ -96,34 +737,1166 @@ pub fn save_page_data(
     config: &Config,
     page_source: &str,
     img: &image::DynamicImage,
+    paths: &Paths,
 ) -> anyhow::Result<()> {
     let now = Utc::now();
     let url_hash = hash_string(&url.to_string());
 
-    let save_data_path = get_loc(Locations::PagesSubdir).join(url_hash);
+    let save_data_path = paths.get(Locations::PagesSubdir).join(url_hash);
 
     if !save_data_path.exists() {
         fs::create_dir_all(&save_data_path)
             .with_context(|| format!("Failed to create directory: {:?}", &save_data_path))?;
     }
 
+    // Both describe the screenshots that are about to be superseded, they're stale
+    // either way and mustn't count towards the pages kept.
+    for derived_file in [SCREENSHOT_DIFF_FILE, THUMBNAIL_FILE] {
+        let derived_path = save_data_path.join(derived_file);
+        if derived_path.exists() {
+            fs::remove_file(&derived_path)
+                .with_context(|| format!("Failed to remove file: {derived_path:?}"))?;
+        }
+    }
+
     remove_old_files(&save_data_path, config.num_of_local_pages);
 
-    let page_file_name = format!("page_{now:?}.html");
+    let page_file_name = if config.compress_local_records {
+        format!("page_{now:?}{COMPRESSED_PAGE_SUFFIX}")
+    } else {
+        format!("page_{now:?}{PAGE_SUFFIX}")
+    };
     let screenshot_file_name = format!("screenshot_{now:?}.png");
 
     let page_file_path = save_data_path.join(page_file_name);
     let screenshot_file_path = save_data_path.join(screenshot_file_name);
 
-    File::create(&page_file_path)
-        .with_context(|| format!("Failed to create file: {:?}", &page_file_path))?
-        .write_all(page_source.as_bytes())
-        .with_context(|| format!("Failed to write to file: {:?}", &page_file_path))?;
+    let mut page_file = File::create(&page_file_path)
+        .with_context(|| format!("Failed to create file: {:?}", &page_file_path))?;
+    let written = if config.compress_local_records {
+        let mut encoder = GzEncoder::new(page_file, Compression::default());
+        encoder
+            .write_all(page_source.as_bytes())
+            .and_then(|()| encoder.finish())
+            .map(|_| ())
+    } else {
+        page_file.write_all(page_source.as_bytes())
+    };
+    written.with_context(|| format!("Failed to write to file: {:?}", &page_file_path))?;
 
     img.save(&screenshot_file_path)
         .with_context(|| format!("Failed to save screenshot: {:?}", &screenshot_file_path))?;
 
+    if let Err(err) = save_screenshot_diff(&save_data_path, &screenshot_file_path, img) {
+        warn!("Failed to diff screenshots for {url}: {err:?}");
+    }
+
     info!("Page data saved successfully for URL: {}", url);
 
     Ok(())
 }
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |meta| meta.len())
+}
+
+/// The files directly inside each URL's directory, nothing else is ever written there.
+pub fn measure_pages_dir_size(paths: &Paths) -> anyhow::Result<PagesUsage> {
+    let pages_dir = paths.get(Locations::PagesSubdir);
+    let mut usage = PagesUsage::default();
+    if !pages_dir.exists() {
+        return Ok(usage);
+    }
+
+    for entry in fs::read_dir(&pages_dir)
+        .with_context(|| format!("Failed to read pages directory {pages_dir:?}"))?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+    {
+        let size = fs::read_dir(entry.path())
+            .with_context(|| format!("Failed to read {:?}", entry.path()))?
+            .filter_map(Result::ok)
+            .map(|file| file_size(&file.path()))
+            .sum::<u64>();
+        usage.total += size;
+        let _ = usage
+            .per_url_hash
+            .insert(entry.file_name().to_string_lossy().into_owned(), size);
+    }
+
+    Ok(usage)
+}
+
+/// The capture time shared by the page and screenshot saved together, `None` for the
+/// derived files that always describe the newest record.
+fn record_key(path: &Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    if name == SCREENSHOT_DIFF_FILE || name == THUMBNAIL_FILE {
+        return None;
+    }
+
+    let key = name
+        .strip_prefix("page_")
+        .and_then(|rest| {
+            rest.strip_suffix(COMPRESSED_PAGE_SUFFIX)
+                .or_else(|| rest.strip_suffix(PAGE_SUFFIX))
+        })
+        .or_else(|| name.strip_prefix("screenshot_")?.strip_suffix(".png"))?;
+    Some(key.to_string())
+}
+
+/// Removes the oldest local records across all URLs until they fit in `max_bytes`.
+/// The newest record of every URL is never removed, ties in age go by directory and
+/// capture time so the same state always evicts the same records.
+#[instrument]
+pub fn enforce_storage_limit(max_bytes: u64, paths: &Paths) -> anyhow::Result<Eviction> {
+    let usage = measure_pages_dir_size(paths)?;
+    let mut eviction = Eviction {
+        remaining: usage.total,
+        ..Eviction::default()
+    };
+    if usage.total <= max_bytes {
+        return Ok(eviction);
+    }
+
+    let pages_dir = paths.get(Locations::PagesSubdir);
+    let mut candidates = vec![];
+    for dir in usage.per_url_hash.keys() {
+        let mut records: BTreeMap<String, LocalRecord> = BTreeMap::new();
+        for path in fs::read_dir(pages_dir.join(dir))
+            .with_context(|| format!("Failed to read {dir:?}"))?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+        {
+            let Some(key) = record_key(&path) else {
+                continue;
+            };
+            let modified = fs::metadata(&path)
+                .and_then(|meta| meta.modified())
+                .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+            let record = records.entry(key.clone()).or_insert_with(|| LocalRecord {
+                modified,
+                dir: dir.clone(),
+                key,
+                files: vec![],
+                size: 0,
+            });
+            record.modified = record.modified.max(modified);
+            record.size += file_size(&path);
+            record.files.push(path);
+        }
+
+        let mut records: Vec<LocalRecord> = records.into_values().collect();
+        records.sort_by(|a, b| (a.modified, &a.key).cmp(&(b.modified, &b.key)));
+        let _ = records.pop();
+        candidates.extend(records);
+    }
+    candidates.sort_by(|a, b| (a.modified, &a.dir, &a.key).cmp(&(b.modified, &b.dir, &b.key)));
+
+    for record in candidates {
+        if eviction.remaining <= max_bytes {
+            break;
+        }
+        for file in &record.files {
+            fs::remove_file(file).with_context(|| format!("Failed to remove {file:?}"))?;
+            eviction.files += 1;
+        }
+        eviction.records += 1;
+        eviction.bytes += record.size;
+        eviction.remaining = eviction.remaining.saturating_sub(record.size);
+    }
+
+    info!(
+        "Evicted {} local records ({} files, {} bytes), {} bytes left of the {max_bytes} allowed",
+        eviction.records, eviction.files, eviction.bytes, eviction.remaining
+    );
+    if eviction.remaining > max_bytes {
+        warn!("Local records are still over the limit, only the newest of each URL are left");
+    }
+
+    Ok(eviction)
+}
+
+fn append_audit_record(record: &DeletionRecord, paths: &Paths) -> anyhow::Result<()> {
+    let audit_log_path = paths.get(Locations::AuditLog);
+    let mut audit_log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&audit_log_path)
+        .with_context(|| format!("Failed to open audit log: {audit_log_path:?}"))?;
+
+    let line = serde_json::to_string(record).context("Failed to serialize deletion record")?;
+    writeln!(audit_log, "{line}")
+        .with_context(|| format!("Failed to write to audit log: {audit_log_path:?}"))?;
+
+    Ok(())
+}
+
+/// Removes everything stored about the URLs matching `pattern`, see
+/// `utilities::entry_selected`. A pattern that matches nothing is an error, it's most
+/// likely misspelled.
+#[instrument(skip(data_store, config))]
+pub fn forget(
+    data_store: &DataStore,
+    pattern: &str,
+    dry_run: bool,
+    config: &Config,
+    paths: &Paths,
+) -> anyhow::Result<DeletionRecord> {
+    let urls: Vec<Url> = data_store
+        .snapshot()
+        .into_iter()
+        .filter(|(url, page_data)| entry_selected(pattern, url, page_data, config))
+        .map(|(url, _)| url)
+        .collect();
+    if urls.is_empty() {
+        anyhow::bail!("No URL in the data store matches {pattern}");
+    }
+
+    remove_entries(data_store, urls, pattern, dry_run, paths)
+}
+
+/// Removes everything stored about URLs that haven't been in the source document for
+/// `stale_days` days, see `PageData::last_seen`.
+#[instrument(skip(data_store))]
+pub fn prune(
+    data_store: &DataStore,
+    stale_days: u64,
+    dry_run: bool,
+    paths: &Paths,
+) -> anyhow::Result<DeletionRecord> {
+    let cutoff = i64::try_from(stale_days)
+        .ok()
+        .and_then(chrono::Duration::try_days)
+        .and_then(|stale| Utc::now().checked_sub_signed(stale))
+        .context("Too many days")?;
+
+    let page_datas = data_store.snapshot();
+    // Without a run that recorded the source, every entry would only go by its last check
+    if page_datas
+        .values()
+        .all(|page_data| page_data.last_seen_in_source.is_none())
+    {
+        anyhow::bail!(
+            "No run has recorded which URLs are in the source yet, run the checker first"
+        );
+    }
+
+    let urls: Vec<Url> = page_datas
+        .into_iter()
+        .filter(|(_, page_data)| page_data.last_seen() < cutoff)
+        .map(|(url, _)| url)
+        .collect();
+
+    remove_entries(
+        data_store,
+        urls,
+        &format!("not in source for {stale_days} days"),
+        dry_run,
+        paths,
+    )
+}
+
+/// Name of the data store inside an export archive, local records go under `pages`.
+const EXPORTED_DATA_STORE: &str = "data_store.json";
+const EXPORTED_PAGES_DIR: &str = "pages";
+
+/// Writes the data store, and with `with_records` the local records, to a tar.gz
+/// archive at `out`. Returns how many entries it holds.
+#[instrument(skip(data_store))]
+pub fn export_archive(
+    data_store: &DataStore,
+    out: &Path,
+    with_records: bool,
+    paths: &Paths,
+) -> anyhow::Result<usize> {
+    let page_datas = data_store.snapshot();
+    let serialized =
+        serde_json::to_vec_pretty(&page_datas).context("Failed to serialize page data")?;
+
+    let file = File::create(out).with_context(|| format!("Failed to create {out:?}"))?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(serialized.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp().try_into().unwrap_or_default());
+    builder
+        .append_data(&mut header, EXPORTED_DATA_STORE, serialized.as_slice())
+        .context("Failed to add the data store to the archive")?;
+
+    let pages_dir = paths.get(Locations::PagesSubdir);
+    if with_records && pages_dir.exists() {
+        builder
+            .append_dir_all(EXPORTED_PAGES_DIR, &pages_dir)
+            .context("Failed to add the local records to the archive")?;
+    }
+
+    builder
+        .into_inner()
+        .and_then(GzEncoder::finish)
+        .with_context(|| format!("Failed to write {out:?}"))?;
+
+    info!("Exported {} entries to {out:?}", page_datas.len());
+    Ok(page_datas.len())
+}
+
+/// Combines the local and the imported entry of one URL. Histories are merged by check
+/// time, a state both have is kept once. Everything else comes from the entry
+/// `strategy` picks, aliases and the pages linking to it excepted, those are combined.
+fn merge_imported(
+    local: PageData,
+    imported: PageData,
+    strategy: ImportStrategy,
+    history_length: usize,
+) -> PageData {
+    let mut history = local.history.clone();
+    history.extend(imported.history.iter().cloned());
+    history.sort_by_key(|state| state.check_time);
+    history.dedup_by_key(|state| state.check_time);
+    let excess = history.len().saturating_sub(history_length.max(1));
+    let _ = history.drain(..excess);
+
+    let imported_wins = match strategy {
+        ImportStrategy::Newer => imported.last_checked > local.last_checked,
+        ImportStrategy::KeepLocal => false,
+        ImportStrategy::KeepImported => true,
+    };
+    let (mut merged, other) = if imported_wins {
+        (imported, local)
+    } else {
+        (local, imported)
+    };
+
+    merged.history = history;
+    merged.aliases.extend(other.aliases);
+    merged.linked_from.extend(other.linked_from);
+    merged.last_checked = merged.last_checked.max(other.last_checked);
+    merged.last_seen_in_source = merged.last_seen_in_source.max(other.last_seen_in_source);
+    merged
+}
+
+/// Merges an archive written by `export_archive` into the data store. Local records
+/// from the archive are only added, files that already exist locally are left alone.
+#[instrument(skip(data_store))]
+pub fn import_archive(
+    data_store: &DataStore,
+    archive_path: &Path,
+    strategy: ImportStrategy,
+    history_length: usize,
+    paths: &Paths,
+) -> anyhow::Result<ImportSummary> {
+    let file =
+        File::open(archive_path).with_context(|| format!("Failed to open {archive_path:?}"))?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let data_dir = paths.get(Locations::BaseData);
+
+    let mut summary = ImportSummary::default();
+    let mut imported: Option<BTreeMap<Url, PageData>> = None;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("Failed to read {archive_path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("Failed to read {archive_path:?}"))?;
+        let path = entry
+            .path()
+            .context("Invalid path in the archive")?
+            .into_owned();
+
+        if path == Path::new(EXPORTED_DATA_STORE) {
+            let mut contents = String::new();
+            let _ = entry
+                .read_to_string(&mut contents)
+                .context("Failed to read the data store from the archive")?;
+            imported = Some(
+                serde_json::from_str(&contents)
+                    .context("Failed to parse the data store in the archive")?,
+            );
+        } else if path.starts_with(EXPORTED_PAGES_DIR) && entry.header().entry_type().is_file() {
+            if data_dir.join(&path).exists() {
+                continue;
+            }
+            // unpack_in refuses paths that would end up outside of the data directory
+            if entry
+                .unpack_in(&data_dir)
+                .with_context(|| format!("Failed to extract {path:?}"))?
+            {
+                summary.records += 1;
+            }
+        }
+    }
+
+    let Some(imported) = imported else {
+        anyhow::bail!("{archive_path:?} has no {EXPORTED_DATA_STORE}, was it made by export?");
+    };
+
+    data_store.edit(|page_datas| {
+        for (url, page_data) in imported {
+            match page_datas.remove(&url) {
+                Some(local) => {
+                    let merged = merge_imported(local, page_data, strategy, history_length);
+                    let _ = page_datas.insert(url, merged);
+                    summary.merged += 1;
+                }
+                None => {
+                    let _ = page_datas.insert(url, page_data);
+                    summary.added += 1;
+                }
+            }
+        }
+    });
+    data_store
+        .save()
+        .context("Failed to save data store after the import")?;
+
+    info!(
+        "Imported {archive_path:?}: {} added, {} merged, {} local record files",
+        summary.added, summary.merged, summary.records
+    );
+    Ok(summary)
+}
+
+/// Saved run summaries that have a status for one of `urls`.
+fn run_summaries_listing(urls: &[Url], paths: &Paths) -> anyhow::Result<Vec<PathBuf>> {
+    let mut listing = vec![];
+    for path in run_summary_paths(paths)? {
+        let summary = read_run_summary(&path)?;
+        if urls
+            .iter()
+            .any(|url| summary.statuses.contains_key(url) || summary.skipped.contains_key(url))
+        {
+            listing.push(path);
+        }
+    }
+    Ok(listing)
+}
+
+/// Rewrites the run summary at `path` without `urls`. The counts stay, they describe
+/// the run as it was.
+fn remove_from_run_summary(path: &Path, urls: &[Url]) -> anyhow::Result<()> {
+    let mut summary = read_run_summary(path)?;
+    for url in urls {
+        let _ = summary.statuses.remove(url);
+        let _ = summary.skipped.remove(url);
+    }
+
+    let serialized =
+        serde_json::to_string_pretty(&summary).context("Failed to serialize run summary")?;
+    let temp_path = sibling_path(path, ".tmp");
+    fs::write(&temp_path, serialized)
+        .with_context(|| format!("Failed to write run summary to {temp_path:?}"))?;
+    fs::rename(&temp_path, path).with_context(|| format!("Failed to replace {path:?}"))
+}
+
+/// Deletes the entries, local record directories and report history pages of `urls`,
+/// drops them from the saved run summaries and logs them to the audit log. `reason` is
+/// recorded as the pattern that selected them.
+fn remove_entries(
+    data_store: &DataStore,
+    urls: Vec<Url>,
+    reason: &str,
+    dry_run: bool,
+    paths: &Paths,
+) -> anyhow::Result<DeletionRecord> {
+    let pages_dir = paths.get(Locations::PagesSubdir);
+    let details_dir = paths.get(Locations::ReportDetails);
+
+    let record_dirs: Vec<PathBuf> = urls
+        .iter()
+        .map(|url| pages_dir.join(hash_string(&url.to_string())))
+        .filter(|path| path.exists())
+        .collect();
+    let details_pages: Vec<PathBuf> = urls
+        .iter()
+        .map(|url| details_dir.join(format!("{}.html", hash_string(&url.to_string()))))
+        .filter(|path| path.exists())
+        .collect();
+    let run_summaries = run_summaries_listing(&urls, paths)?;
+
+    let record = DeletionRecord {
+        time: Utc::now(),
+        invocation: std::env::args().collect::<Vec<String>>().join(" "),
+        pattern: reason.to_string(),
+        urls,
+        paths: record_dirs,
+        details_pages,
+        run_summaries,
+    };
+
+    if dry_run {
+        return Ok(record);
+    }
+
+    data_store.remove(&record.urls)?;
+
+    for path in &record.paths {
+        fs::remove_dir_all(path).with_context(|| format!("Failed to remove {path:?}"))?;
+    }
+    for path in &record.details_pages {
+        fs::remove_file(path).with_context(|| format!("Failed to remove {path:?}"))?;
+    }
+    for path in &record.run_summaries {
+        remove_from_run_summary(path, &record.urls)?;
+    }
+
+    append_audit_record(&record, paths)?;
+    info!(
+        "Removed {} URLs and {} local record directories, {reason}",
+        record.urls.len(),
+        record.paths.len()
+    );
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Barrier};
+
+    use super::*;
+    use crate::{
+        enums::{CustomError, ReportStatus},
+        test_support,
+        test_support::page_datas,
+    };
+
+    #[test]
+    fn a_save_keeps_the_previous_data_store_as_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data_store.json");
+
+        save_data_store(&page_datas(&["https://a.example/"]), &path).unwrap();
+        save_data_store(&page_datas(&["https://b.example/"]), &path).unwrap();
+
+        let backup = read_data_store(&sibling_path(&path, BACKUP_SUFFIX)).unwrap();
+        assert!(backup.contains_key(&Url::parse("https://a.example/").unwrap()));
+        assert!(!sibling_path(&path, ".tmp").exists());
+    }
+
+    #[test]
+    fn a_corrupted_data_store_is_recovered_from_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data_store.json");
+        save_data_store(&page_datas(&["https://a.example/"]), &path).unwrap();
+        save_data_store(&page_datas(&["https://b.example/"]), &path).unwrap();
+
+        // Cut off mid write, the way a crash of an older version left it
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &contents[..contents.len() / 2]).unwrap();
+
+        let loaded = load_data_store(&path).unwrap();
+        assert_eq!(
+            loaded.keys().collect::<Vec<_>>(),
+            vec![&Url::parse("https://a.example/").unwrap()]
+        );
+    }
+
+    #[test]
+    fn a_missing_data_store_falls_back_to_the_backup() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data_store.json");
+        save_data_store(&page_datas(&["https://a.example/"]), &path).unwrap();
+        fs::rename(&path, sibling_path(&path, BACKUP_SUFFIX)).unwrap();
+
+        assert_eq!(load_data_store(&path).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn both_files_unreadable_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data_store.json");
+        fs::write(&path, "{").unwrap();
+        fs::write(sibling_path(&path, BACKUP_SUFFIX), "not json").unwrap();
+
+        assert!(load_data_store(&path).is_err());
+        assert!(load_data_store(&dir.path().join("missing.json"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn only_one_of_two_contenders_gets_the_run_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        let barrier = Arc::new(Barrier::new(2));
+
+        let contenders: Vec<_> = (0..2)
+            .map(|_| {
+                let path = path.clone();
+                let barrier = Arc::clone(&barrier);
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    acquire_run_lock(&path)
+                })
+            })
+            .collect();
+        let results: Vec<_> = contenders
+            .into_iter()
+            .map(|contender| contender.join().unwrap())
+            .collect();
+
+        assert_eq!(results.iter().filter(|result| result.is_ok()).count(), 1);
+        let err = results
+            .iter()
+            .find_map(|result| result.as_ref().err())
+            .unwrap();
+        assert!(err.to_string().contains("Another run is in progress"));
+
+        drop(results);
+        assert!(!path.exists());
+        assert!(acquire_run_lock(&path).is_ok());
+    }
+
+    #[test]
+    fn a_lock_of_a_reused_pid_is_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        // PID 1 is always running, and never as this program
+        fs::write(&path, format!("1 {}", Utc::now().to_rfc3339())).unwrap();
+
+        let lock = acquire_run_lock(&path).unwrap();
+        let owner = fs::read_to_string(&lock.path).unwrap();
+        assert!(owner.starts_with(&format!("{} ", std::process::id())));
+    }
+
+    #[test]
+    fn a_lock_of_this_program_is_respected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run.lock");
+        fs::write(
+            &path,
+            format!("{} {}", std::process::id(), Utc::now().to_rfc3339()),
+        )
+        .unwrap();
+
+        assert!(acquire_run_lock(&path).is_err());
+        assert!(path.exists());
+    }
+
+    fn full_entries() -> BTreeMap<Url, PageData> {
+        let mut redirected = test_support::page_data(
+            "https://a.example/",
+            vec![
+                test_support::state("<p>A</p>"),
+                test_support::failed_state(CustomError::Redirected),
+            ],
+        );
+        redirected.marker = Some("Marker".to_string());
+        redirected.ignore.title = true;
+        redirected.reference_state = Some(test_support::state("<p>Approved</p>"));
+        let _ = redirected
+            .aliases
+            .insert(test_support::url("https://a.example/?utm_source=mail"));
+        redirected.last_seen_in_source = Some(Utc::now());
+
+        let mut entries = page_datas(&[
+            "https://b.example/",
+            "https://xn--mnchen-3ya.de/stra%C3%9Fe",
+        ]);
+        let _ = entries.insert(test_support::url("https://a.example/"), redirected);
+        entries
+    }
+
+    fn as_json(page_datas: &BTreeMap<Url, PageData>) -> serde_json::Value {
+        serde_json::to_value(page_datas).unwrap()
+    }
+
+    fn both_backends(dir: &Path) -> [Arc<dyn Storage>; 2] {
+        [
+            Arc::new(JsonStore::new(dir.join("data_store.json"))),
+            Arc::new(SqliteStore::open(dir.join("data_store.sqlite")).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn every_backend_reads_back_what_it_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        for storage in both_backends(dir.path()) {
+            assert!(storage.load_all().unwrap().is_empty(), "{storage:?}");
+
+            let mut entries = full_entries();
+            storage.save_all(&entries).unwrap();
+            assert_eq!(
+                as_json(&storage.load_all().unwrap()),
+                as_json(&entries),
+                "{storage:?}"
+            );
+
+            // One entry replaced and one added, the others stay
+            let a = test_support::url("https://a.example/");
+            entries.get_mut(&a).unwrap().marker = None;
+            storage.save_page(&a, &entries[&a]).unwrap();
+            let added = page_datas(&["https://c.example/"]);
+            let (c, c_data) = added.iter().next().unwrap();
+            storage.save_page(c, c_data).unwrap();
+            let _ = entries.insert(c.clone(), c_data.clone());
+            assert_eq!(
+                as_json(&storage.load_all().unwrap()),
+                as_json(&entries),
+                "{storage:?}"
+            );
+
+            storage
+                .prune(&[a.clone(), test_support::url("https://never.example/")])
+                .unwrap();
+            let _ = entries.remove(&a);
+            assert_eq!(
+                as_json(&storage.load_all().unwrap()),
+                as_json(&entries),
+                "{storage:?}"
+            );
+
+            // A smaller save drops what it doesn't list
+            let fewer = page_datas(&["https://b.example/"]);
+            storage.save_all(&fewer).unwrap();
+            assert_eq!(
+                as_json(&storage.load_all().unwrap()),
+                as_json(&fewer),
+                "{storage:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn json_prunes_and_saves_running_side_by_side_lose_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(JsonStore::new(dir.path().join("data_store.json")));
+        let urls = |prefix: &str| -> Vec<String> {
+            (0..20)
+                .map(|i| format!("https://{prefix}.example/{i}"))
+                .collect()
+        };
+        let entry =
+            |url: &str| test_support::page_data(url, vec![test_support::state("<p>Page</p>")]);
+        let pruned: BTreeMap<Url, PageData> = urls("old")
+            .iter()
+            .map(|url| (test_support::url(url), entry(url)))
+            .collect();
+        storage.save_all(&pruned).unwrap();
+
+        let barrier = Arc::new(Barrier::new(2));
+        let pruning = {
+            let storage = Arc::clone(&storage);
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || {
+                barrier.wait();
+                for url in pruned.keys() {
+                    storage.prune(std::slice::from_ref(url)).unwrap();
+                }
+            })
+        };
+        let saving = {
+            let storage = Arc::clone(&storage);
+            std::thread::spawn(move || {
+                barrier.wait();
+                for url in urls("new") {
+                    storage
+                        .save_page(&test_support::url(&url), &entry(&url))
+                        .unwrap();
+                }
+            })
+        };
+        pruning.join().unwrap();
+        saving.join().unwrap();
+
+        let stored = storage.load_all().unwrap();
+        assert_eq!(stored.keys().map(Url::as_str).collect::<Vec<_>>(), {
+            let mut new = urls("new");
+            new.sort();
+            new
+        },);
+    }
+
+    #[test]
+    fn the_json_data_store_moves_to_sqlite_unchanged() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("data_store.json");
+        let sqlite_path = dir.path().join("data_store.sqlite");
+        save_data_store(&full_entries(), &json_path).unwrap();
+        let json_before = fs::read(&json_path).unwrap();
+
+        assert_eq!(migrate_to_sqlite(&json_path, &sqlite_path).unwrap(), 3);
+        let migrated = SqliteStore::open(sqlite_path.clone())
+            .unwrap()
+            .load_all()
+            .unwrap();
+        assert_eq!(
+            as_json(&migrated),
+            as_json(&load_data_store(&json_path).unwrap())
+        );
+        assert_eq!(fs::read(&json_path).unwrap(), json_before);
+        assert!(!sibling_path(&sqlite_path, ".tmp").exists());
+
+        let err = migrate_to_sqlite(&json_path, &sqlite_path).unwrap_err();
+        assert!(err.to_string().contains("already exists"), "{err:?}");
+    }
+
+    #[test]
+    fn the_markers_file_maps_urls_to_markers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("markers.toml");
+        assert!(load_markers(&path).unwrap().is_empty());
+
+        fs::write(
+            &path,
+            "\"https://example.com/\" = \"Welcome\"\n\"https://example.com/docs?page=2\" = \"Page 2\"\n",
+        )
+        .unwrap();
+        assert_eq!(
+            load_markers(&path).unwrap(),
+            BTreeMap::from([
+                (
+                    test_support::url("https://example.com/"),
+                    "Welcome".to_string()
+                ),
+                (
+                    test_support::url("https://example.com/docs?page=2"),
+                    "Page 2".to_string()
+                ),
+            ])
+        );
+
+        fs::write(&path, "\"not a url\" = \"Welcome\"\n").unwrap();
+        let err = load_markers(&path).unwrap_err();
+        assert!(format!("{err:#}").contains("not a url"), "{err:#}");
+    }
+
+    #[test]
+    fn a_run_summary_reads_back_as_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        assert!(load_latest_run_summary(&paths).unwrap().is_none());
+
+        let mut summary = test_support::run_summary();
+        summary.urls_checked = 2;
+        summary.new_urls = 1;
+        summary.previously_seen = 1;
+        summary.driver_version = Some("geckodriver 0.34.0".to_string());
+        summary.status_counts =
+            BTreeMap::from([(ReportStatus::Valid, 1), (ReportStatus::Error, 1)]);
+        summary.statuses = BTreeMap::from([
+            (test_support::url("https://a.example/"), ReportStatus::Valid),
+            (test_support::url("https://b.example/"), ReportStatus::Error),
+        ]);
+        save_run_summary(&summary, &paths).unwrap();
+
+        let loaded = load_latest_run_summary(&paths).unwrap().unwrap();
+        assert_eq!(
+            serde_json::to_value(&loaded).unwrap(),
+            serde_json::to_value(&summary).unwrap()
+        );
+    }
+
+    #[test]
+    fn a_screenshot_diff_is_saved_once_there_is_a_previous_screenshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        let config = Config::default();
+        let url = test_support::url("https://example.com/");
+        let diff_path = paths
+            .get(Locations::PagesSubdir)
+            .join(hash_string(&url.to_string()))
+            .join(SCREENSHOT_DIFF_FILE);
+        let screenshot = |shade| {
+            image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                16,
+                16,
+                image::Rgba([shade, shade, shade, 255]),
+            ))
+        };
+
+        save_page_data(&url, &config, "<html></html>", &screenshot(200), &paths).unwrap();
+        assert!(!diff_path.exists());
+
+        save_page_data(&url, &config, "<html></html>", &screenshot(20), &paths).unwrap();
+        assert!(diff_path.exists());
+    }
+
+    #[test]
+    fn a_page_that_cannot_be_saved_returns_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        let pages = paths.get(Locations::PagesSubdir);
+        fs::create_dir_all(pages.parent().unwrap()).unwrap();
+        // A file where the pages directory should be
+        fs::write(&pages, "").unwrap();
+        let img = image::DynamicImage::new_rgba8(1, 1);
+
+        let result = save_page_data(
+            &test_support::url("https://example.com/"),
+            &Config::default(),
+            "<html></html>",
+            &img,
+            &paths,
+        );
+        assert!(format!("{:#}", result.unwrap_err()).contains("Failed to create directory"));
+    }
+
+    /// A page and screenshot of 50 bytes each, captured `age` seconds before the others.
+    fn write_record(paths: &Paths, url_hash: &str, key: &str, age: u64) {
+        let dir = paths.get(Locations::PagesSubdir).join(url_hash);
+        fs::create_dir_all(&dir).unwrap();
+        let modified =
+            std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(10_000 - age);
+        for name in [
+            format!("page_{key}{PAGE_SUFFIX}"),
+            format!("screenshot_{key}.png"),
+        ] {
+            let mut file = File::create(dir.join(name)).unwrap();
+            file.write_all(&[0; 50]).unwrap();
+            file.set_modified(modified).unwrap();
+        }
+    }
+
+    fn records_left(paths: &Paths, url_hash: &str) -> Vec<String> {
+        let mut left: Vec<String> = fs::read_dir(paths.get(Locations::PagesSubdir).join(url_hash))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        left.sort();
+        left
+    }
+
+    #[test]
+    fn local_records_are_measured_per_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        assert_eq!(measure_pages_dir_size(&paths).unwrap().total, 0);
+
+        write_record(&paths, "a", "1", 0);
+        write_record(&paths, "a", "2", 0);
+        write_record(&paths, "b", "1", 0);
+
+        let usage = measure_pages_dir_size(&paths).unwrap();
+        assert_eq!(usage.total, 300);
+        assert_eq!(
+            usage.per_url_hash,
+            BTreeMap::from([("a".to_string(), 200), ("b".to_string(), 100)])
+        );
+    }
+
+    #[test]
+    fn the_oldest_records_are_evicted_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        write_record(&paths, "a", "1", 40);
+        write_record(&paths, "b", "1", 30);
+        write_record(&paths, "a", "2", 20);
+        write_record(&paths, "b", "2", 10);
+        fs::write(
+            paths
+                .get(Locations::PagesSubdir)
+                .join("a")
+                .join(THUMBNAIL_FILE),
+            "",
+        )
+        .unwrap();
+
+        let eviction = enforce_storage_limit(250, &paths).unwrap();
+        assert_eq!((eviction.records, eviction.files), (2, 4));
+        assert_eq!((eviction.bytes, eviction.remaining), (200, 200));
+        assert_eq!(
+            records_left(&paths, "a"),
+            ["page_2.html", "screenshot_2.png", THUMBNAIL_FILE]
+        );
+        assert_eq!(
+            records_left(&paths, "b"),
+            ["page_2.html", "screenshot_2.png"]
+        );
+
+        // The newest record of each URL stays, however low the limit
+        let eviction = enforce_storage_limit(0, &paths).unwrap();
+        assert_eq!((eviction.records, eviction.remaining), (0, 200));
+        assert_eq!(
+            records_left(&paths, "b"),
+            ["page_2.html", "screenshot_2.png"]
+        );
+    }
+
+    #[test]
+    fn only_the_newest_run_summaries_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+
+        for hour in 0..4 {
+            let mut summary = test_support::run_summary();
+            summary.config.run_history_to_keep = 2;
+            summary.started += chrono::Duration::hours(hour);
+            summary.urls_checked = usize::try_from(hour).unwrap();
+            save_run_summary(&summary, &paths).unwrap();
+        }
+
+        let kept: Vec<String> = run_summary_paths(&paths)
+            .unwrap()
+            .iter()
+            .map(|path| run_id(path))
+            .collect();
+        assert_eq!(kept, ["20231115_001320", "20231115_011320"]);
+        let latest = load_latest_run_summary(&paths).unwrap().unwrap();
+        assert_eq!(latest.urls_checked, 3);
+    }
+
+    #[test]
+    fn sqlite_is_not_opened_empty_over_an_unmigrated_json_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("data_store.json");
+        let sqlite_path = dir.path().join("data_store.sqlite");
+
+        // A fresh install starts with an empty SQLite data store
+        let storage = open_storage(StorageBackend::Sqlite, &json_path, &sqlite_path).unwrap();
+        assert!(storage.incremental());
+        drop(storage);
+        fs::remove_file(&sqlite_path).unwrap();
+
+        save_data_store(&full_entries(), &json_path).unwrap();
+        let err = open_storage(StorageBackend::Sqlite, &json_path, &sqlite_path).unwrap_err();
+        assert!(err.to_string().contains("migrate-store"), "{err:?}");
+        assert!(!sqlite_path.exists());
+
+        let _ = migrate_to_sqlite(&json_path, &sqlite_path).unwrap();
+        let storage = open_storage(StorageBackend::Sqlite, &json_path, &sqlite_path).unwrap();
+        assert_eq!(storage.load_all().unwrap().len(), 3);
+    }
+
+    /// A data store of `urls` in `dir`, saved twice so a backup with them exists, with a
+    /// local record, a history page and a run summary status for each.
+    fn forgettable(dir: &Path, urls: &[&str]) -> (Paths, DataStore) {
+        let paths = Paths::new(dir.join("config"), dir.join("data"));
+        init_storage(false, &paths);
+        let data_store_path = paths.get(Locations::DataStore);
+        let entries = page_datas(urls);
+        save_data_store(&entries, &data_store_path).unwrap();
+        save_data_store(&entries, &data_store_path).unwrap();
+
+        let mut summary = test_support::run_summary();
+        for url in entries.keys() {
+            let url_hash = hash_string(&url.to_string());
+            let record_dir = paths.get(Locations::PagesSubdir).join(&url_hash);
+            fs::create_dir_all(&record_dir).unwrap();
+            fs::write(record_dir.join("page.html"), "<p>Page</p>").unwrap();
+            let details_dir = paths.get(Locations::ReportDetails);
+            fs::create_dir_all(&details_dir).unwrap();
+            fs::write(details_dir.join(format!("{url_hash}.html")), "History").unwrap();
+            let _ = summary.statuses.insert(url.clone(), ReportStatus::Valid);
+        }
+        save_run_summary(&summary, &paths).unwrap();
+
+        let storage = open_storage(
+            StorageBackend::Json,
+            &data_store_path,
+            &paths.get(Locations::SqliteStore),
+        )
+        .unwrap();
+        (paths, DataStore::load(storage).unwrap())
+    }
+
+    #[test]
+    fn forget_removes_every_trace_of_a_url() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) =
+            forgettable(dir.path(), &["https://a.example/", "https://b.example/"]);
+        let a = test_support::url("https://a.example/");
+        let a_hash = hash_string(&a.to_string());
+
+        let record = forget(
+            &data_store,
+            "https://a.example/",
+            false,
+            &Config::default(),
+            &paths,
+        )
+        .unwrap();
+        assert_eq!(record.urls, vec![a.clone()]);
+        assert_eq!(record.run_summaries.len(), 1);
+
+        let data_store_path = paths.get(Locations::DataStore);
+        for path in [
+            data_store_path.clone(),
+            sibling_path(&data_store_path, BACKUP_SUFFIX),
+        ] {
+            let stored = read_data_store(&path).unwrap();
+            assert!(!stored.contains_key(&a), "{path:?}");
+            assert!(stored.contains_key(&test_support::url("https://b.example/")));
+        }
+        assert!(!data_store.snapshot().contains_key(&a));
+        assert!(!paths.get(Locations::PagesSubdir).join(&a_hash).exists());
+        assert!(!paths
+            .get(Locations::ReportDetails)
+            .join(format!("{a_hash}.html"))
+            .exists());
+
+        let summary = load_latest_run_summary(&paths).unwrap().unwrap();
+        assert!(!summary.statuses.contains_key(&a));
+        assert_eq!(summary.statuses.len(), 1);
+
+        let audit_log = fs::read_to_string(paths.get(Locations::AuditLog)).unwrap();
+        assert_eq!(audit_log.lines().count(), 1);
+        let logged: DeletionRecord = serde_json::from_str(audit_log.trim()).unwrap();
+        assert_eq!(logged.urls, vec![a]);
+        assert_eq!(logged.pattern, "https://a.example/");
+    }
+
+    #[test]
+    fn a_dry_run_forgets_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) = forgettable(dir.path(), &["https://a.example/"]);
+
+        let record = forget(&data_store, "*a.example*", true, &Config::default(), &paths).unwrap();
+        assert_eq!(record.paths.len(), 1);
+        assert_eq!(record.details_pages.len(), 1);
+        assert_eq!(data_store.snapshot().len(), 1);
+        assert!(record.paths[0].exists());
+        assert!(!paths.get(Locations::AuditLog).exists());
+    }
+
+    #[test]
+    fn forget_selects_a_url_the_way_it_is_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) =
+            forgettable(dir.path(), &["https://a.example/", "https://b.example/"]);
+        let b = test_support::url("https://b.example/");
+        data_store.edit(|page_datas| {
+            let _ = page_datas
+                .get_mut(&b)
+                .unwrap()
+                .aliases
+                .insert(test_support::url("https://old.example/b"));
+        });
+        let config = Config {
+            strip_query_params: vec!["utm_*".to_string()],
+            ..Config::default()
+        };
+
+        // Stored without the tracking parameter
+        let record = forget(
+            &data_store,
+            "https://a.example/?utm_source=mail",
+            false,
+            &config,
+            &paths,
+        )
+        .unwrap();
+        assert_eq!(record.urls, vec![test_support::url("https://a.example/")]);
+
+        // Merged into b.example
+        let record = forget(&data_store, "https://old.example/b", false, &config, &paths).unwrap();
+        assert_eq!(record.urls, vec![b]);
+    }
+
+    #[test]
+    fn forgetting_a_pattern_that_matches_nothing_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) = forgettable(dir.path(), &["https://a.example/"]);
+
+        let err = forget(
+            &data_store,
+            "https://typo.example/",
+            false,
+            &Config::default(),
+            &paths,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("typo.example"), "{err:?}");
+        assert_eq!(data_store.snapshot().len(), 1);
+    }
+}
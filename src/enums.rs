@@ -1,6 +1,151 @@
+use std::path::PathBuf;
+
+use clap::{Subcommand, ValueEnum};
 use serde::{Deserialize, Serialize};
 
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Remove everything stored about a URL, a domain or a pattern
+    Forget {
+        pattern: String,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Accept the latest state of a URL, a domain or a pattern as its baseline
+    Approve {
+        #[arg(required_unless_present = "all_valid", conflicts_with = "all_valid")]
+        pattern: Option<String>,
+        /// Approve every URL the report currently lists as valid
+        #[arg(long)]
+        all_valid: bool,
+    },
+    /// Go back to judging a URL, a domain or a pattern against its history
+    ClearBaseline { pattern: String },
+    /// Stop a URL, a domain or a pattern from failing on differences that are expected
+    Ignore {
+        pattern: String,
+        /// Changes to the page source and its compressed length
+        #[arg(long)]
+        page_hash: bool,
+        #[arg(long)]
+        screenshot: bool,
+        #[arg(long)]
+        title: bool,
+        /// Don't check it at all, it's listed in the Ignored table
+        #[arg(long)]
+        skip: bool,
+        /// Remove every ignore rule instead
+        #[arg(long, conflicts_with_all = ["page_hash", "screenshot", "title", "skip"])]
+        clear: bool,
+    },
+    /// Print the newest page source saved for a URL
+    Source { url: String },
+    /// Remove everything stored about URLs that left the source document
+    Prune {
+        /// Only URLs that haven't been in the source for this many days
+        #[arg(long)]
+        stale_days: u64,
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Write the data store to an archive another machine can import
+    Export {
+        #[arg(long)]
+        out: PathBuf,
+        /// Include the saved pages and screenshots
+        #[arg(long)]
+        with_records: bool,
+    },
+    /// Merge an archive written by export into the data store
+    Import {
+        archive: PathBuf,
+        #[arg(long, value_enum, default_value_t = ImportStrategy::Newer)]
+        strategy: ImportStrategy,
+    },
+    /// Report only what changed between two runs, by default the last two
+    Diff {
+        /// Run ID, the start time like 20240131_020000, defaults to the run before `to`
+        #[arg(long)]
+        from: Option<String>,
+        /// Run ID, defaults to the newest run
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Copy the JSON data store into a new SQLite data store
+    MigrateStore,
+}
+
+/// Which entry's marker, baseline and ignore rules win when both data stores have a
+/// URL. Histories are always merged.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, ValueEnum)]
+pub enum ImportStrategy {
+    /// The entry checked most recently
+    Newer,
+    KeepLocal,
+    KeepImported,
+}
+
+/// Why `Config::update` refused a value, the message is shown next to the setting.
+#[derive(Debug)]
+pub enum ConfigUpdateError {
+    UnknownKey {
+        key: String,
+        valid_keys: Vec<&'static str>,
+    },
+    InvalidValue {
+        key: String,
+        expected: &'static str,
+        reason: String,
+    },
+}
+
+impl std::fmt::Display for ConfigUpdateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigUpdateError::UnknownKey { key, valid_keys } => write!(
+                f,
+                "Unknown setting {key}, expected one of: {}",
+                valid_keys.join(", ")
+            ),
+            ConfigUpdateError::InvalidValue {
+                key,
+                expected,
+                reason,
+            } => write!(f, "Invalid value for {key}, expected {expected}: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigUpdateError {}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum RunOutcome {
+    Completed,
+    Cancelled,
+}
+
+/// What started a run, kept in its run summary.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum RunTrigger {
+    /// The run button, `--headless` or anything else a person started
+    #[default]
+    Manual,
+    Scheduled,
+}
+
+/// When the scheduler starts a run, written as `HH:MM` or a humantime interval, see
+/// `schedule`.
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(try_from = "String", into = "String")]
+pub enum Schedule {
+    /// Counted from when the app started or the schedule last changed
+    Every(std::time::Duration),
+    /// Every day at this local time
+    Daily(chrono::NaiveTime),
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
 pub enum LinkType {
     Generic,
     Content,
@@ -10,13 +155,6 @@ pub enum LinkType {
     InternalError,
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
-pub struct Validity {
-    pub valid: Option<Vec<ValidReason>>,
-    pub invalid: Option<Vec<InvalidReason>>,
-    pub error: Option<CustomError>,
-}
-
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 pub enum ValidReason {
     CompressionExact,
@@ -27,12 +165,17 @@ pub enum ValidReason {
     Title,
     Marker,
     Type,
+    AnchorFound,
+    EmailSyntax,
+    MxRecords,
+    TextHash,
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
 pub enum InvalidReason {
     Compression,
     PageHash,
+    TextHash,
     ScreenshotHash,
     Title,
     Type,
@@ -53,14 +196,198 @@ pub enum CustomError {
     Marker,
     Warning,
     WebDriverError,
+    HttpClientError,
+    HttpServerError,
+    DnsError,
+    TlsError,
+    ConnectionError,
+    DisallowedByRobots,
+    CustomValidatorFailed,
+    CustomValidatorError,
+    AnchorNotFound,
+    PageLoadTimeout,
+    MissingCredentials,
+    ContentDownloadFailed,
+    LocalFileMissing,
+    InvalidEmailSyntax,
+    NoMxRecords,
+    /// Looks like the page the site serves for a path that doesn't exist
+    Soft404,
+    /// Logged more JavaScript errors than `max_console_errors`
+    ConsoleErrors,
+    /// Still busy after `per_page_check_timeout`, the tab was closed unchecked
+    CheckTimedOut,
+    /// The tab of the page couldn't be switched to
+    WindowLost,
+    /// The driver failed to return the title
+    TitleUnavailable,
+    /// The driver failed to return the page source
+    SourceUnavailable,
+    /// No screenshot could be taken or decoded
+    ScreenshotFailed,
+}
+
+/// How `State::errors` is stored, a list now and a single optional error before.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StoredErrors {
+    Many(Vec<CustomError>),
+    Single(Option<CustomError>),
+}
+
+/// Differences between the requested and final URL that don't count as a redirect.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RedirectRule {
+    /// http:// ending up on https://, the reverse is still reported.
+    SchemeUpgrade,
+    TrailingSlash,
+    WwwPrefix,
+    /// Any final URL matching the pattern, see `utilities::url_matches`.
+    Pattern(String),
+}
+
+/// A compiled entry of `url_include_patterns` or `url_exclude_patterns`.
+#[derive(Debug, Clone)]
+pub enum UrlFilter {
+    Glob(String),
+    Regex(regex::Regex),
+}
+
+/// Where the data store is kept, see `disc_op::Storage`.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// One file, rewritten on every save
+    #[default]
+    Json,
+    /// One row per URL, checkpoints only write the URLs checked since the last one
+    Sqlite,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceType {
+    Pdf,
+    Docx,
+    Html,
+    Markdown,
+}
+
+/// What the `<loc>` entries of a sitemap point at.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SitemapKind {
+    Urls,
+    Index,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Browser {
+    #[default]
+    Firefox,
+    Chrome,
+}
+
+/// A value in `firefox_prefs`, typed the way Firefox expects it.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone)]
+#[serde(untagged)]
+pub enum FirefoxPref {
+    Bool(bool),
+    Int(i64),
+    String(String),
+}
+
+/// What happens to a page that took longer than `gecko.page_load_timeout` to load.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutPolicy {
+    /// Check whatever had loaded by then
+    #[default]
+    CheckAnyway,
+    /// Record PageLoadTimeout without looking at the content
+    MarkError,
+    /// Like MarkError, but the page gets one retry regardless of `retry_attempts`
+    RetryOnce,
+}
+
+/// Perceptual hash screenshots are compared by, see `utilities::hash_img`.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ScreenshotHashAlgorithm {
+    /// Compares neighbouring pixels, what screenshots were always hashed with
+    #[default]
+    Gradient,
+    /// Compares every pixel to the average
+    Mean,
+    /// Mean hash of the low frequencies, the most tolerant of small shifts
+    Dct,
+}
+
+/// Colors of the HTML report, see `report::stylesheet`.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportTheme {
+    #[default]
+    Dark,
+    /// Also what gets printed
+    Light,
+    /// Follows the color scheme of the browser viewing the report
+    Auto,
+}
+
+/// How Generic pages are checked.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckMode {
+    /// Load every page in the browser
+    #[default]
+    Browser,
+    /// Only a GET request, the browser is never started
+    Http,
+    /// A GET request first, pages whose result doesn't match what is stored go to
+    /// the browser as well
+    Auto,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PdfEngine {
+    /// The parser, falling back to the regex when the document can't be parsed
+    #[default]
+    Auto,
+    Parser,
+    Regex,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Html,
+    Csv,
+    Json,
+}
+
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportStatus {
+    Error,
+    Unknown,
+    HashOnly,
+    Valid,
+    FirstSeen,
+    Ignored,
+    NotInSource,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Arch {
     X86,
     X64,
     Arm64,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OS {
     Windows,
     Linux,
@@ -75,6 +402,17 @@ pub enum Locations {
     ExtensionsDir,
     PagesSubdir,
     GeckodriverBinary,
+    ChromedriverBinary,
+    DriverPid,
     LogPrefix,
     LogDir,
+    AuditLog,
+    ReportCsv,
+    ReportJson,
+    ReportDetails,
+    ReportDiff,
+    Markers,
+    RunHistory,
+    RunLock,
+    SqliteStore,
 }
@@ -0,0 +1,556 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use anyhow::Context;
+use reqwest::Url;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    enums::SourceType,
+    pdf, sitemap,
+    structs::{Config, LinkExtraction, ProxyConfig},
+    utilities::http_client,
+};
+
+#[instrument(skip(proxy))]
+pub async fn get_source_github(url: Url, proxy: Option<&ProxyConfig>) -> anyhow::Result<Vec<u8>> {
+    let split_path = url.path().split('/').collect::<Vec<&str>>();
+
+    let repo_owner = split_path[1];
+    let repo_name = split_path[2];
+    let branch = split_path[4];
+    let file_path = split_path[5..].join("/");
+
+    let raw_url = format!("https://github.com/{repo_owner}/{repo_name}/raw/{branch}/{file_path}");
+
+    get_source_url(&raw_url, proxy).await
+}
+
+#[instrument(skip(proxy))]
+pub async fn get_source_url(url: &str, proxy: Option<&ProxyConfig>) -> anyhow::Result<Vec<u8>> {
+    let client = http_client(proxy)
+        .build()
+        .context("Failed to create HTTP client")?;
+
+    let source = client
+        .get(url)
+        .send()
+        .await
+        .context("Failed to download source document")?
+        .bytes()
+        .await
+        .context("Failed to read source document content")?
+        .to_vec();
+
+    info!("Source document fetched successfully from: {}", url);
+
+    Ok(source)
+}
+
+pub fn source_contents(source_path: &str) -> anyhow::Result<Vec<u8>> {
+    let path = Path::new(source_path);
+    let mut buf = Vec::new();
+
+    let mut file =
+        File::open(path).context(format!("Failed to open source document: {source_path}"))?;
+
+    let _ = file
+        .read_to_end(&mut buf)
+        .context(format!("Failed to read source document: {source_path}"))?;
+
+    info!("Source document read successfully from: {}", source_path);
+    Ok(buf)
+}
+
+/// Guesses from the extension, anything unrecognised is treated as a PDF like before.
+pub fn infer_source_type(location: &str) -> SourceType {
+    let extension = Path::new(location)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+
+    match extension.as_deref() {
+        Some("docx") => SourceType::Docx,
+        Some("html" | "htm") => SourceType::Html,
+        Some("md" | "markdown") => SourceType::Markdown,
+        _ => SourceType::Pdf,
+    }
+}
+
+/// Undoes the character references that show up in attribute values.
+pub fn unescape_entities(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn record_link(extraction: &mut LinkExtraction, link: &str, base: Option<&Url>) {
+    if link.len() > pdf::MAX_URI_LEN {
+        extraction.too_long += 1;
+        return;
+    }
+
+    let parsed = match base {
+        Some(base) => base.join(link),
+        None => Url::parse(link),
+    };
+
+    match parsed {
+        Ok(url) => {
+            let _ = extraction.urls.insert(url);
+        }
+        Err(_) => extraction.unparsable += 1,
+    }
+}
+
+/// Hyperlinks in a DOCX live in the relationship parts, one per document part that
+/// can hold links (body, headers, footers, footnotes).
+pub fn docx_links(docx: &[u8]) -> anyhow::Result<LinkExtraction> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(docx)).context("Failed to open DOCX")?;
+    let rels_parts: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.starts_with("word/_rels/") && name.ends_with(".xml.rels"))
+        .map(String::from)
+        .collect();
+
+    let relationship = regex::Regex::new(r"<Relationship\s[^>]*>").unwrap();
+    let attribute = regex::Regex::new(r#"(\w+)\s*=\s*"([^"]*)""#).unwrap();
+    let mut extraction = LinkExtraction::default();
+
+    for part in rels_parts {
+        let mut contents = String::new();
+        let _ = archive
+            .by_name(&part)
+            .with_context(|| format!("Failed to open DOCX part: {part}"))?
+            .read_to_string(&mut contents)
+            .with_context(|| format!("Failed to read DOCX part: {part}"))?;
+
+        for element in relationship.find_iter(&contents) {
+            let mut is_hyperlink = false;
+            let mut is_external = false;
+            let mut target = None;
+
+            for captures in attribute.captures_iter(element.as_str()) {
+                match &captures[1] {
+                    "Type" => is_hyperlink = captures[2].ends_with("/hyperlink"),
+                    "TargetMode" => is_external = &captures[2] == "External",
+                    "Target" => target = Some(unescape_entities(&captures[2])),
+                    _ => {}
+                }
+            }
+
+            if let (true, true, Some(target)) = (is_hyperlink, is_external, target) {
+                record_link(&mut extraction, target.trim(), None);
+            }
+        }
+    }
+
+    Ok(extraction)
+}
+
+/// Relative links are resolved against `base`, without one they can't be checked and
+/// are counted as unparsable.
+pub fn html_links(html: &[u8], base: Option<&Url>) -> LinkExtraction {
+    let html = String::from_utf8_lossy(html);
+    let href = regex::Regex::new(
+        r#"(?is)<(?:a|area)\b[^>]*?\bhref\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#,
+    )
+    .unwrap();
+    let mut extraction = LinkExtraction::default();
+
+    for captures in href.captures_iter(&html) {
+        let Some(value) = captures
+            .get(1)
+            .or_else(|| captures.get(2))
+            .or_else(|| captures.get(3))
+        else {
+            continue;
+        };
+
+        let link = unescape_entities(value.as_str().trim());
+        if link.is_empty() || link.starts_with('#') || link.starts_with("javascript:") {
+            continue;
+        }
+
+        record_link(&mut extraction, &link, base);
+    }
+
+    extraction
+}
+
+/// Inline links and images, reference definitions and autolinks. Fenced code blocks
+/// are skipped, their contents are examples rather than links.
+pub fn markdown_links(markdown: &[u8], base: Option<&Url>) -> LinkExtraction {
+    let markdown = String::from_utf8_lossy(markdown);
+    let fence =
+        regex::Regex::new(r"(?ms)^ {0,3}```.*?^ {0,3}```[^\n]*$|^ {0,3}~~~.*?^ {0,3}~~~[^\n]*$")
+            .unwrap();
+    let link = regex::Regex::new(concat!(
+        r"\]\(\s*<([^>\n]*)>",
+        r"|\]\(\s*((?:[^()\s]|\([^()\s]*\))+)",
+        r"|(?m)^ {0,3}\[[^\]\n]+\]:\s*<?([^\s>]+)",
+        r"|<([a-zA-Z][a-zA-Z0-9+.-]*:[^\s<>]+)>",
+    ))
+    .unwrap();
+    let markdown = fence.replace_all(&markdown, "");
+    let mut extraction = LinkExtraction::default();
+
+    for captures in link.captures_iter(&markdown) {
+        let Some(value) = (1..=4).find_map(|group| captures.get(group)) else {
+            continue;
+        };
+
+        let link = value.as_str().trim();
+        if link.is_empty() || link.starts_with('#') || link.starts_with("mailto:") {
+            continue;
+        }
+
+        record_link(&mut extraction, link, base);
+    }
+
+    extraction
+}
+
+pub fn extract(
+    source: &[u8],
+    source_type: SourceType,
+    base: Option<&Url>,
+    config: &Config,
+) -> anyhow::Result<LinkExtraction> {
+    let extraction = match source_type {
+        SourceType::Pdf => pdf::extract_links(source, config.pdf_engine),
+        SourceType::Docx => docx_links(source)?,
+        SourceType::Html => html_links(source, base),
+        SourceType::Markdown => markdown_links(source, base),
+    };
+
+    info!(
+        "Extracted {} links from the {source_type:?} source",
+        extraction.urls.len()
+    );
+    Ok(extraction)
+}
+
+/// Takes the URLs from the first of: `given_urls`, `source_path`, `sitemap_url` and
+/// `source_url`. The others are ignored, even when they are set.
+pub async fn get_urls(
+    config: &Config,
+    given_urls: Option<Vec<String>>,
+) -> anyhow::Result<LinkExtraction> {
+    let extraction = if let Some(given_urls) = given_urls {
+        LinkExtraction {
+            urls: given_urls
+                .iter()
+                .map(|url| Url::parse(url))
+                .filter_map(Result::ok)
+                .collect(),
+            ..LinkExtraction::default()
+        }
+    } else if let Some(source_path) = &config.source_path {
+        let source_type = config
+            .source_type
+            .unwrap_or_else(|| infer_source_type(source_path));
+        let source = source_contents(source_path)?;
+        extract(&source, source_type, None, config)?
+    } else if let Some(sitemap_url) = &config.sitemap_url {
+        sitemap::sitemap_links(sitemap_url, config.proxy.as_ref()).await?
+    } else {
+        let source_url = config
+            .source_url
+            .clone()
+            .context("Neither a source path nor a source URL is configured")?;
+        let source_type = config
+            .source_type
+            .unwrap_or_else(|| infer_source_type(source_url.path()));
+        let source = if source_url.host_str() == Some("github.com") {
+            get_source_github(source_url.clone(), config.proxy.as_ref())
+                .await
+                .context("Failed to fetch source document from GitHub")?
+        } else {
+            get_source_url(source_url.as_str(), config.proxy.as_ref())
+                .await
+                .context("Failed to fetch source document")?
+        };
+        extract(&source, source_type, Some(&source_url), config)?
+    };
+
+    if extraction.undecodable + extraction.too_long + extraction.unparsable > 0 {
+        warn!(
+            "Skipped link candidates, undecodable: {}, too long or unterminated: {}, unparsable: {}",
+            extraction.undecodable, extraction.too_long, extraction.unparsable
+        );
+    }
+    if extraction.urls.is_empty() {
+        anyhow::bail!("No links found in source document");
+    }
+
+    info!("Total number of links: {:?}", extraction.urls.len());
+
+    Ok(extraction)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::*;
+    use crate::test_support::url;
+
+    fn urls(extraction: &LinkExtraction) -> Vec<&str> {
+        let mut urls: Vec<&str> = extraction.urls.iter().map(Url::as_str).collect();
+        urls.sort_unstable();
+        urls
+    }
+
+    fn docx(parts: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for (name, contents) in parts {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    const HYPERLINK: &str =
+        "http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink";
+
+    #[test]
+    fn docx_external_hyperlinks_of_every_part_are_read() {
+        let document_rels = format!(
+            r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">
+  <Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/>
+  <Relationship Id="rId2" Type="{HYPERLINK}" Target="https://example.com/search?q=a&amp;page=2" TargetMode="External"/>
+  <Relationship Id="rId3" TargetMode="External" Target=" https://example.com/reordered " Type="{HYPERLINK}"/>
+  <Relationship Id="rId4" Type="{HYPERLINK}" Target="bookmark"/>
+  <Relationship Id="rId5" Type="{HYPERLINK}" Target="not a url" TargetMode="External"/>
+</Relationships>"#
+        );
+        let footer_rels = format!(
+            r#"<Relationships><Relationship Id="rId1" Type="{HYPERLINK}" Target="https://example.com/footer" TargetMode="External"/></Relationships>"#
+        );
+        let docx = docx(&[
+            ("[Content_Types].xml", "<Types/>"),
+            ("word/document.xml", "<w:document/>"),
+            ("word/_rels/document.xml.rels", &document_rels),
+            ("word/_rels/footer1.xml.rels", &footer_rels),
+            ("_rels/.rels", &footer_rels.replace("footer", "package")),
+        ]);
+
+        let extraction = docx_links(&docx).unwrap();
+        assert_eq!(
+            urls(&extraction),
+            [
+                "https://example.com/footer",
+                "https://example.com/reordered",
+                "https://example.com/search?q=a&page=2",
+            ]
+        );
+        assert_eq!(extraction.unparsable, 1);
+    }
+
+    #[test]
+    fn a_docx_that_is_not_a_zip_is_an_error() {
+        let err = docx_links(b"%PDF-1.7").unwrap_err();
+        assert!(err.to_string().contains("Failed to open DOCX"), "{err:?}");
+    }
+
+    const HTML: &str = r##"<!DOCTYPE html>
+<html><body>
+  <a href="https://example.com/double">Double</a>
+  <A class="x" HREF='https://example.com/single'>Single</A>
+  <a href=https://example.com/unquoted>Unquoted</a>
+  <map><area shape="rect" href="/area" alt=""></map>
+  <a
+     href="docs/relative?a=1&amp;b=2">Across lines</a>
+  <a href="#top">Anchor</a>
+  <a href="javascript:void(0)">Script</a>
+  <a name="no-href">Name only</a>
+  <link href="https://example.com/stylesheet.css" rel="stylesheet">
+</body></html>"##;
+
+    #[test]
+    fn html_links_resolve_against_the_base() {
+        let base = url("https://example.com/intranet/index.html");
+        assert_eq!(
+            urls(&html_links(HTML.as_bytes(), Some(&base))),
+            [
+                "https://example.com/area",
+                "https://example.com/double",
+                "https://example.com/intranet/docs/relative?a=1&b=2",
+                "https://example.com/single",
+                "https://example.com/unquoted",
+            ]
+        );
+    }
+
+    #[test]
+    fn relative_html_links_without_a_base_are_unparsable() {
+        let extraction = html_links(HTML.as_bytes(), None);
+        assert_eq!(extraction.urls.len(), 3);
+        assert_eq!(extraction.unparsable, 2);
+    }
+
+    const MARKDOWN: &str = r##"# Links
+
+An [inline](https://example.com/inline) link, one [with a title](https://example.com/titled "Title")
+and one in [angle brackets](<https://example.com/with space>).
+![An image](https://example.com/image.png) and [parens](https://en.wikipedia.org/wiki/Rust_(language)).
+An autolink <https://example.com/auto>, a [relative](guide/setup.md) link and [an anchor](#links).
+Mail <mailto:someone@example.com> is not checked.
+
+[reference]: https://example.com/reference "Title"
+   [indented]: <https://example.com/indented>
+
+```toml
+source_url = "https://example.com/in-a-fence"
+[not](https://example.com/fenced)
+```
+
+~~~
+<https://example.com/tilde-fence>
+~~~
+"##;
+
+    #[test]
+    fn markdown_links_of_every_form_are_read() {
+        let base = url("https://example.com/docs/readme.md");
+        assert_eq!(
+            urls(&markdown_links(MARKDOWN.as_bytes(), Some(&base))),
+            [
+                "https://en.wikipedia.org/wiki/Rust_(language)",
+                "https://example.com/auto",
+                "https://example.com/docs/guide/setup.md",
+                "https://example.com/image.png",
+                "https://example.com/indented",
+                "https://example.com/inline",
+                "https://example.com/reference",
+                "https://example.com/titled",
+                "https://example.com/with%20space",
+            ]
+        );
+    }
+
+    /// Answers every request with `body`, the returned URL ends in `path`.
+    async fn serve(path: &str, body: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut head = vec![];
+                let mut buffer = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    head.extend_from_slice(&buffer[..read]);
+                }
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        }));
+        url(&format!("http://{address}{path}"))
+    }
+
+    #[tokio::test]
+    async fn the_first_configured_source_is_the_one_read() {
+        let mut source_file = tempfile::Builder::new().suffix(".md").tempfile().unwrap();
+        source_file
+            .write_all(b"[A link](https://example.com/from-source-path)")
+            .unwrap();
+        let mut config = Config {
+            source_path: Some(source_file.path().to_string_lossy().into_owned()),
+            sitemap_url: Some(
+                serve(
+                    "/sitemap.xml",
+                    "<urlset><url><loc>https://example.com/from-sitemap</loc></url></urlset>",
+                )
+                .await,
+            ),
+            source_url: Some(
+                serve(
+                    "/links.html",
+                    r#"<a href="https://example.com/from-source-url">A link</a>"#,
+                )
+                .await,
+            ),
+            ..Config::default()
+        };
+        let given = || Some(vec!["https://example.com/given".to_string()]);
+
+        assert_eq!(
+            urls(&get_urls(&config, given()).await.unwrap()),
+            ["https://example.com/given"]
+        );
+        assert_eq!(
+            urls(&get_urls(&config, None).await.unwrap()),
+            ["https://example.com/from-source-path"]
+        );
+        config.source_path = None;
+        assert_eq!(
+            urls(&get_urls(&config, None).await.unwrap()),
+            ["https://example.com/from-sitemap"]
+        );
+        config.sitemap_url = None;
+        assert_eq!(
+            urls(&get_urls(&config, None).await.unwrap()),
+            ["https://example.com/from-source-url"]
+        );
+        config.source_url = None;
+        let err = get_urls(&config, None).await.unwrap_err();
+        assert!(err.to_string().contains("Neither a source path"), "{err:?}");
+    }
+
+    #[tokio::test]
+    async fn a_malformed_sitemap_is_an_error_not_an_empty_run() {
+        let config = Config {
+            sitemap_url: Some(
+                serve(
+                    "/sitemap.xml",
+                    "<urlset><url><loc>https://example.com/page</loc></url>",
+                )
+                .await,
+            ),
+            source_url: Some(url("https://example.com/never-read.pdf")),
+            ..Config::default()
+        };
+
+        let err = get_urls(&config, None).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("its urlset element is never closed"),
+            "{err:?}"
+        );
+    }
+
+    #[test]
+    fn the_source_type_follows_the_extension() {
+        for (location, source_type) in [
+            ("links.docx", SourceType::Docx),
+            ("/intranet/Links.HTM", SourceType::Html),
+            ("page.html", SourceType::Html),
+            ("README.md", SourceType::Markdown),
+            ("notes.markdown", SourceType::Markdown),
+            ("links.pdf", SourceType::Pdf),
+            ("no-extension", SourceType::Pdf),
+        ] {
+            assert_eq!(infer_source_type(location), source_type, "{location}");
+        }
+    }
+}
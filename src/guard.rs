@@ -0,0 +1,170 @@
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    panic::{self, AssertUnwindSafe},
+};
+
+#[cfg(feature = "gui")]
+use slint::ComponentHandle;
+use tracing::error;
+
+use crate::{driver, structs::Paths};
+#[cfg(feature = "gui")]
+use crate::{Globals, MainWindow};
+
+/// The message a panic was raised with.
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|s| (*s).to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "Unknown panic payload".to_string())
+}
+
+/// Logs every panic with its location and a backtrace to the tracing file, the details
+/// handed to the UI only carry the message.
+pub fn install_panic_hook() {
+    panic::set_hook(Box::new(|info| {
+        let location = info
+            .location()
+            .map_or("unknown location".to_string(), |l| l.to_string());
+
+        error!(
+            "Panic at {location}: {}\n\n{}",
+            panic_message(info.payload()),
+            Backtrace::force_capture()
+        );
+    }));
+}
+
+/// What the UI shows for a panic in `callback`, `payload` being what it unwound with.
+pub fn panic_details(callback: &str, payload: &(dyn Any + Send)) -> String {
+    format!(
+        "Callback: {callback}\nPanic: {}\n\nThe log file has the location and backtrace.",
+        panic_message(payload)
+    )
+}
+
+/// `panic_details` for a task that didn't finish, it panicked or was cancelled.
+pub fn task_failure_details(callback: &str, err: tokio::task::JoinError) -> String {
+    if err.is_panic() {
+        panic_details(callback, &*err.into_panic())
+    } else {
+        format!("Callback: {callback}\n{err}")
+    }
+}
+
+/// Runs `body`, if it panics the formatted report is passed to `on_panic` instead of
+/// unwinding into the event loop.
+pub fn recover<R>(
+    callback: &str,
+    body: impl FnOnce() -> R,
+    on_panic: impl FnOnce(String),
+) -> Option<R> {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(result) => Some(result),
+        Err(payload) => {
+            on_panic(panic_details(callback, &*payload));
+            None
+        }
+    }
+}
+
+/// Stops what a failed callback may have left running, the browser driver for one.
+pub fn clean_up_after_failure(paths: &Paths) {
+    error!("UI callback failed, cleaning up external resources");
+    driver::kill_drivers(paths);
+}
+
+/// Central handler for anything that went wrong inside a UI callback.
+#[cfg(feature = "gui")]
+pub fn report_failure(ui: &MainWindow, details: String, paths: &Paths) {
+    clean_up_after_failure(paths);
+
+    ui.global::<Globals>().set_error_details(details.into());
+}
+
+#[cfg(feature = "gui")]
+pub fn guarded<R>(
+    callback: &str,
+    ui: &MainWindow,
+    paths: &Paths,
+    body: impl FnOnce() -> R,
+) -> Option<R> {
+    recover(callback, body, |details| report_failure(ui, details, paths))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[test]
+    fn a_callback_that_returns_is_left_alone() {
+        let reported = RefCell::new(None);
+
+        let result = recover(
+            "sum",
+            || 1 + 1,
+            |details| *reported.borrow_mut() = Some(details),
+        );
+
+        assert_eq!(result, Some(2));
+        assert_eq!(reported.into_inner(), None);
+    }
+
+    #[test]
+    fn a_panic_is_reported_with_its_callback_and_message() {
+        let reported = RefCell::new(None);
+
+        let result: Option<()> = recover(
+            "save_config",
+            || panic!("Config of {} rejected", "example.com"),
+            |details| *reported.borrow_mut() = Some(details),
+        );
+
+        assert_eq!(result, None);
+        assert_eq!(
+            reported.into_inner().unwrap(),
+            "Callback: save_config\nPanic: Config of example.com rejected\n\n\
+             The log file has the location and backtrace."
+        );
+        assert!(panic_details("static", &"Plain message").contains("\nPanic: Plain message\n"));
+        assert!(panic_details("other", &42).contains("\nPanic: Unknown panic payload\n"));
+    }
+
+    #[tokio::test]
+    async fn a_panicked_task_is_reported_with_its_message() {
+        let err = tokio::spawn(async { panic!("Run crashed") })
+            .await
+            .unwrap_err();
+
+        let details = task_failure_details("run_link_checker", err);
+        assert!(details.starts_with("Callback: run_link_checker\nPanic: Run crashed\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn a_failed_callback_kills_the_driver() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        let pid_file = paths.get(crate::enums::Locations::DriverPid);
+        std::fs::create_dir_all(pid_file.parent().unwrap()).unwrap();
+        let mut driver = std::process::Command::new("sleep")
+            .arg("30")
+            .spawn()
+            .unwrap();
+        std::fs::write(&pid_file, format!("{} sleep", driver.id())).unwrap();
+
+        let result: Option<()> = recover(
+            "run_link_checker",
+            || panic!("Run crashed"),
+            |_| clean_up_after_failure(&paths),
+        );
+
+        assert_eq!(result, None);
+        assert!(!driver.wait().unwrap().success());
+        assert!(!pid_file.exists());
+    }
+}
@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    enums::{CustomError, LinkType},
+    normalize,
+    structs::{Config, PageData, State},
+    utilities,
+};
+
+const HTTP_CHECK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Checks a page with a plain GET, redirects are followed and the body stands in for
+/// the page source. There is no screenshot, title or visible text.
+#[instrument(skip(config))]
+pub async fn check(url: &Url, config: &Config) -> State {
+    let error_state = |error| {
+        let mut state = State::new("", None, None, LinkType::Generic, vec![error]);
+        state.http_only = true;
+        state
+    };
+
+    let credentials = match utilities::credentials_for(url, &config.auth) {
+        Ok(credentials) => credentials,
+        Err(err) => {
+            warn!("{err:#}");
+            return error_state(CustomError::MissingCredentials);
+        }
+    };
+    let client = match utilities::http_client(config.proxy.as_ref())
+        .timeout(HTTP_CHECK_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => {
+            warn!("Failed to create HTTP client: {err:?}");
+            return error_state(CustomError::ConnectionError);
+        }
+    };
+    let mut request = client.get(url.clone());
+    if let Some(credentials) = &credentials {
+        request = request.basic_auth(&credentials.username, Some(&credentials.password));
+    }
+
+    let mut response = match request.send().await {
+        Ok(response) => response,
+        Err(err) => {
+            info!("Request to {url} failed: {err:?}");
+            let error = if err.is_timeout() {
+                CustomError::PageLoadTimeout
+            } else {
+                utilities::network_error(&err).unwrap_or(CustomError::ConnectionError)
+            };
+            return error_state(error);
+        }
+    };
+    let status_code = response.status().as_u16();
+    let final_url = utilities::without_credentials(response.url());
+
+    // Same cap as content downloads, a page that big is hashed by its start
+    let max_bytes = usize::try_from(config.max_content_download).unwrap_or(usize::MAX);
+    let mut body = Vec::new();
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                let room = max_bytes.saturating_sub(body.len());
+                body.extend_from_slice(&chunk[..chunk.len().min(room)]);
+                if chunk.len() > room {
+                    break;
+                }
+            }
+            Ok(None) => break,
+            Err(err) => {
+                warn!("Failed to read the body of {url}: {err:?}");
+                return error_state(CustomError::ConnectionError);
+            }
+        }
+    }
+
+    let mut error = utilities::status_error(status_code);
+    if !utilities::is_allowed_redirect(url, &final_url, &config.redirect_allowlist) {
+        error = Some(CustomError::Redirected);
+    }
+
+    let body = String::from_utf8_lossy(&body);
+    let filters = config.source_filters().unwrap_or_else(|err| {
+        warn!("Ignoring source filters: {err:#}");
+        vec![]
+    });
+    let source = normalize::normalize(&body, &filters, config.normalize_source);
+
+    let mut state = State::new(
+        &source,
+        None,
+        None,
+        LinkType::Generic,
+        error.into_iter().collect(),
+    );
+    state.status_code = Some(status_code);
+    state.final_url = Some(final_url);
+    state.content_length = Some(body.len() as u64);
+    state.filter_version = Some(normalize::filter_version(config));
+    state.http_only = true;
+    state
+}
+
+/// In auto mode a page only goes to the browser when the HTTP check doesn't agree
+/// with what is stored: it failed, the last browser check failed or landed somewhere
+/// else, or the body changed since the previous HTTP check.
+pub fn needs_browser(http_state: &State, page_data: Option<&PageData>) -> bool {
+    let Some(page_data) = page_data else {
+        return true;
+    };
+    if !http_state.errors.is_empty() {
+        return true;
+    }
+
+    let Some(browser_state) = page_data
+        .history
+        .iter()
+        .rev()
+        .find(|state| !state.http_only && state.link_type == LinkType::Generic)
+    else {
+        return true;
+    };
+    if !browser_state.errors.is_empty()
+        || browser_state.status_code != http_state.status_code
+        || browser_state.final_url != http_state.final_url
+    {
+        return true;
+    }
+
+    page_data
+        .history
+        .iter()
+        .rev()
+        .find(|state| state.http_only)
+        .is_some_and(|previous| previous.hash != http_state.hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::*;
+    use crate::{structs::ProxyConfig, test_support::url};
+
+    /// A proxy that answers every request with `status` itself.
+    async fn proxy_answering(status: &'static str) -> Config {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = [0; 1024];
+                let _ = socket.read(&mut request).await.unwrap();
+                let response =
+                    format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+        }));
+
+        Config {
+            proxy: Some(ProxyConfig {
+                url: url(&format!("http://{address}")),
+                no_proxy: vec![],
+            }),
+            ..Config::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn each_config_goes_through_its_own_proxy() {
+        let first = proxy_answering("200 OK").await;
+        let second = proxy_answering("503 Service Unavailable").await;
+        let page = url("http://checked.invalid/page");
+
+        let (through_first, through_second) =
+            tokio::join!(check(&page, &first), check(&page, &second));
+        assert_eq!(through_first.status_code, Some(200));
+        assert_eq!(through_second.status_code, Some(503));
+        assert_eq!(check(&page, &first).await.status_code, Some(200));
+    }
+}
@@ -0,0 +1,139 @@
+use image::{imageops::FilterType, DynamicImage, Rgba, RgbaImage};
+
+/// Width of the screenshot thumbnails shown in the report.
+const THUMBNAIL_WIDTH: u32 = 160;
+
+/// Side length in pixels of the blocks compared by `screenshot_diff`.
+const BLOCK_SIZE: u32 = 8;
+/// Largest per-channel difference still treated as the same pixel, absorbs compression noise.
+const CHANNEL_TOLERANCE: u8 = 24;
+
+fn pixels_differ(previous: Option<&Rgba<u8>>, current: Option<&Rgba<u8>>) -> bool {
+    match (previous, current) {
+        (Some(previous), Some(current)) => previous
+            .0
+            .iter()
+            .zip(current.0.iter())
+            .any(|(a, b)| a.abs_diff(*b) > CHANNEL_TOLERANCE),
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+fn block_changed(previous: &RgbaImage, current: &RgbaImage, block_x: u32, block_y: u32) -> bool {
+    (block_y..block_y + BLOCK_SIZE).any(|y| {
+        (block_x..block_x + BLOCK_SIZE).any(|x| {
+            pixels_differ(
+                previous.get_pixel_checked(x, y),
+                current.get_pixel_checked(x, y),
+            )
+        })
+    })
+}
+
+/// Renders `current` faded out with every block that changed since `previous` tinted
+/// red. Images of different sizes are compared over the larger area, anything only one
+/// of them covers counts as changed. Returns `None` when nothing changed.
+pub fn screenshot_diff(previous: &DynamicImage, current: &DynamicImage) -> Option<RgbaImage> {
+    let previous = previous.to_rgba8();
+    let current = current.to_rgba8();
+
+    let width = previous.width().max(current.width());
+    let height = previous.height().max(current.height());
+
+    let mut diff = RgbaImage::from_fn(width, height, |x, y| {
+        let Rgba([r, g, b, _]) = current
+            .get_pixel_checked(x, y)
+            .copied()
+            .unwrap_or(Rgba([0, 0, 0, 255]));
+        let gray = ((u16::from(r) + u16::from(g) + u16::from(b)) / 3) as u8;
+        let faded = 255 - (255 - gray) / 3;
+        Rgba([faded, faded, faded, 255])
+    });
+
+    let mut any_changed = false;
+    for block_y in (0..height).step_by(BLOCK_SIZE as usize) {
+        for block_x in (0..width).step_by(BLOCK_SIZE as usize) {
+            if !block_changed(&previous, &current, block_x, block_y) {
+                continue;
+            }
+            any_changed = true;
+
+            for y in block_y..(block_y + BLOCK_SIZE).min(height) {
+                for x in block_x..(block_x + BLOCK_SIZE).min(width) {
+                    let pixel = diff.get_pixel_mut(x, y);
+                    pixel.0[1] /= 3;
+                    pixel.0[2] /= 3;
+                    pixel.0[0] = 255;
+                }
+            }
+        }
+    }
+
+    any_changed.then_some(diff)
+}
+
+/// Scales `img` down to `THUMBNAIL_WIDTH` keeping the aspect ratio, narrower images are
+/// left as they are.
+pub fn thumbnail(img: &DynamicImage) -> DynamicImage {
+    if img.width() <= THUMBNAIL_WIDTH {
+        return img.clone();
+    }
+
+    img.resize(THUMBNAIL_WIDTH, u32::MAX, FilterType::Triangle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, shade: u8) -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            width,
+            height,
+            Rgba([shade, shade, shade, 255]),
+        ))
+    }
+
+    fn is_red(pixel: &Rgba<u8>) -> bool {
+        pixel.0[0] == 255 && pixel.0[1] < 255 && pixel.0[2] < 255
+    }
+
+    #[test]
+    fn unchanged_screenshots_have_no_diff() {
+        assert!(screenshot_diff(&solid(16, 16, 200), &solid(16, 16, 200)).is_none());
+        // Compression noise stays under the channel tolerance
+        let noisy = solid(16, 16, 200 + CHANNEL_TOLERANCE);
+        assert!(screenshot_diff(&solid(16, 16, 200), &noisy).is_none());
+    }
+
+    #[test]
+    fn only_the_blocks_that_changed_are_tinted() {
+        let previous = solid(16, 16, 200);
+        let mut current = previous.to_rgba8();
+        current.put_pixel(10, 3, Rgba([0, 0, 0, 255]));
+
+        let diff = screenshot_diff(&previous, &DynamicImage::ImageRgba8(current)).unwrap();
+        assert_eq!(diff.dimensions(), (16, 16));
+        for (x, y, pixel) in diff.enumerate_pixels() {
+            let in_changed_block = (8..16).contains(&x) && y < 8;
+            assert_eq!(is_red(pixel), in_changed_block, "pixel at {x},{y}");
+        }
+    }
+
+    #[test]
+    fn area_only_one_screenshot_covers_counts_as_changed() {
+        let diff = screenshot_diff(&solid(8, 8, 200), &solid(8, 16, 200)).unwrap();
+        assert_eq!(diff.dimensions(), (8, 16));
+        assert!(!is_red(diff.get_pixel(0, 0)));
+        assert!(is_red(diff.get_pixel(0, 8)));
+    }
+
+    #[test]
+    fn thumbnails_keep_the_aspect_ratio_and_never_upscale() {
+        let wide = thumbnail(&solid(THUMBNAIL_WIDTH * 2, 100, 200));
+        assert_eq!((wide.width(), wide.height()), (THUMBNAIL_WIDTH, 50));
+        let narrow = thumbnail(&solid(40, 30, 200));
+        assert_eq!((narrow.width(), narrow.height()), (40, 30));
+    }
+}
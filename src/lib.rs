@@ -0,0 +1,52 @@
+//! Checks a list of links in a real browser and reports the ones that broke or changed.
+//!
+//! The binary is a thin layer over this library: URLs come from `extract`, the `Checker`
+//! loads them and `report` turns the data store into reports. Everything on disk lives
+//! under a [`structs::Paths`], nothing here looks up the user's directories by itself.
+//! Without the default `gui` feature the library doesn't pull in Slint.
+
+#![warn(
+    clippy::all,
+    unsafe_code,
+    unused_extern_crates,
+    // slint forces these to be disabled :(
+    // unused_results
+    // unused_import_braces,
+    // unused_qualifications,
+    // clippy::pedantic,
+    // missing_debug_implementations,
+    // trivial_casts,
+    // trivial_numeric_casts,
+)]
+
+#[cfg(feature = "gui")]
+slint::include_modules!();
+
+pub mod baseline;
+pub mod canonical;
+pub mod checker;
+pub mod config;
+pub mod disc_op;
+pub mod driver;
+pub mod enums;
+pub mod extract;
+pub mod guard;
+pub mod http_check;
+pub mod imaging;
+pub mod mailto;
+pub mod metrics;
+pub mod normalize;
+pub mod notify;
+pub mod pdf;
+pub mod plan;
+pub mod report;
+pub mod robots;
+pub mod schedule;
+pub mod sitemap;
+pub mod soft_404;
+pub mod structs;
+#[cfg(test)]
+mod test_support;
+#[cfg(feature = "gui")]
+pub mod update;
+pub mod utilities;
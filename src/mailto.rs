@@ -0,0 +1,203 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use reqwest::Url;
+use serde_json::Value;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    enums::{CustomError, LinkType},
+    structs::{Config, ProxyConfig, State},
+    utilities::{http_client, percent_decode},
+};
+
+/// Longest a single MX lookup may take before the address is only checked for syntax.
+const MX_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// DNS record type of MX records.
+const MX_RECORD_TYPE: u64 = 15;
+
+/// Every address of a mailto link: the comma separated path plus any `to`, `cc` and
+/// `bcc` query parameters. Other parameters like `subject` are ignored.
+fn addresses(url: &Url) -> Vec<String> {
+    let mut addresses: Vec<String> = url
+        .path()
+        .split(',')
+        .map(|address| percent_decode(address).trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect();
+
+    for (key, value) in url.query_pairs() {
+        if matches!(key.to_lowercase().as_str(), "to" | "cc" | "bcc") {
+            addresses.extend(
+                value
+                    .split(',')
+                    .map(|address| address.trim().to_string())
+                    .filter(|address| !address.is_empty()),
+            );
+        }
+    }
+
+    addresses
+}
+
+fn valid_local_part(local: &str) -> bool {
+    let allowed = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-/=?^_`{|}~".contains(c);
+
+    !local.is_empty()
+        && local.len() <= 64
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+        && local.chars().all(|c| c == '.' || allowed(c))
+}
+
+fn valid_domain(domain: &str) -> bool {
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    };
+
+    domain.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(valid_label)
+        && labels
+            .last()
+            .is_some_and(|tld| tld.len() >= 2 && !tld.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// A pragmatic subset of RFC 5322, no quoted local parts or IP literals.
+fn valid_syntax(address: &str) -> bool {
+    address
+        .rsplit_once('@')
+        .is_some_and(|(local, domain)| valid_local_part(local) && valid_domain(domain))
+}
+
+/// `Some(true)` when `domain` has a usable MX record, `None` when the lookup didn't
+/// give an answer in time. A null MX (`0 .`) means the domain takes no mail.
+#[instrument(skip(dns_url, proxy))]
+async fn has_mx_records(domain: &str, dns_url: &Url, proxy: Option<&ProxyConfig>) -> Option<bool> {
+    let client = http_client(proxy).timeout(MX_TIMEOUT).build().ok()?;
+    let response = client
+        .get(dns_url.clone())
+        .query(&[("name", domain), ("type", "MX")])
+        .header(reqwest::header::ACCEPT, "application/dns-json")
+        .send()
+        .await;
+
+    let body = match response {
+        Ok(response) => response.text().await,
+        Err(err) => Err(err),
+    };
+    let json: Value = match body.map(|body| serde_json::from_str(&body)) {
+        Ok(Ok(json)) => json,
+        Ok(Err(err)) => {
+            warn!("Unexpected DNS response for {domain}: {err:?}");
+            return None;
+        }
+        Err(err) => {
+            warn!("MX lookup for {domain} failed: {err:?}");
+            return None;
+        }
+    };
+
+    // 3 is NXDOMAIN, anything else but 0 is a resolver problem rather than an answer
+    match json["Status"].as_u64() {
+        Some(0) => {}
+        Some(3) => return Some(false),
+        status => {
+            warn!("MX lookup for {domain} failed with status {status:?}");
+            return None;
+        }
+    }
+
+    let found = json["Answer"].as_array().is_some_and(|answers| {
+        answers.iter().any(|answer| {
+            answer["type"].as_u64() == Some(MX_RECORD_TYPE)
+                && answer["data"]
+                    .as_str()
+                    .and_then(|data| data.split_whitespace().nth(1))
+                    .is_some_and(|exchange| exchange != ".")
+        })
+    });
+    Some(found)
+}
+
+/// Every address has to be well formed, and with `check_mailto_mx` every domain needs
+/// an MX record. Domains whose lookup fails are only checked for syntax, so is every
+/// domain while no `mailto_dns_url` is set.
+pub async fn check(url: &Url, config: &Config) -> State {
+    let error_state = |error| State::new("", None, None, LinkType::Mailto, vec![error]);
+
+    let addresses = addresses(url);
+    if addresses.is_empty() {
+        info!("{url} has no address");
+        return error_state(CustomError::InvalidEmailSyntax);
+    }
+    if let Some(address) = addresses.iter().find(|address| !valid_syntax(address)) {
+        info!("Invalid email address {address:?} in {url}");
+        return error_state(CustomError::InvalidEmailSyntax);
+    }
+
+    let mut mx_verified = false;
+    let dns_url = config
+        .mailto_dns_url
+        .as_ref()
+        .filter(|_| config.check_mailto_mx);
+    if config.check_mailto_mx && dns_url.is_none() {
+        warn!("check_mailto_mx is set without a mailto_dns_url, only checking the syntax of {url}");
+    }
+    if let Some(dns_url) = dns_url {
+        let domains: BTreeSet<String> = addresses
+            .iter()
+            .filter_map(|address| address.rsplit_once('@'))
+            .map(|(_, domain)| domain.to_lowercase())
+            .collect();
+
+        mx_verified = true;
+        for domain in domains {
+            match has_mx_records(&domain, dns_url, config.proxy.as_ref()).await {
+                Some(true) => {}
+                Some(false) => {
+                    info!("{domain} has no MX records");
+                    return error_state(CustomError::NoMxRecords);
+                }
+                None => mx_verified = false,
+            }
+        }
+    }
+
+    let mut state = State::new(&addresses.join(","), None, None, LinkType::Mailto, vec![]);
+    state.mx_verified = mx_verified;
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::url;
+
+    #[tokio::test]
+    async fn mx_lookups_need_a_dns_endpoint() {
+        let config = Config {
+            check_mailto_mx: true,
+            ..Config::default()
+        };
+
+        // .invalid never resolves, a lookup would flag it
+        let state = check(&url("mailto:someone@example.invalid"), &config).await;
+
+        assert!(state.errors.is_empty());
+        assert!(!state.mx_verified);
+    }
+
+    #[tokio::test]
+    async fn malformed_addresses_fail_without_a_lookup() {
+        let state = check(&url("mailto:someone@@example.com"), &Config::default()).await;
+
+        assert_eq!(state.errors, vec![CustomError::InvalidEmailSyntax]);
+    }
+}
@@ -12,309 +12,500 @@
     // trivial_numeric_casts,
 )]
 
-use std::{
-    cell::RefCell,
-    collections::{BTreeMap, HashSet},
-    path::Path,
-    rc::Rc,
-    sync::OnceLock,
-    time::Duration,
-};
+use std::{cell::RefCell, io::Write, rc::Rc, sync::Arc};
 
 use anyhow::Context;
 use clap::Parser;
-use utilities::{get_loc, init_tracing};
-use directories::ProjectDirs;
-use driver::new_tab;
-use reqwest::Url;
+use link_rustler::{
+    baseline, canonical,
+    checker::preview_run,
+    config, disc_op, driver,
+    enums::{Command, Locations, ReportFormat, RunOutcome, RunTrigger, StorageBackend},
+    guard, metrics, report, schedule,
+    structs::{
+        AppState, Args, Checker, Config, DataStore, DeletionRecord, DriverStatus, IgnoreRules,
+        LatestMetrics, Paths, ReportSummary, RunControl,
+    },
+    update, utilities,
+    utilities::init_tracing,
+    Globals, MainWindow, Settings, UpdateCheck,
+};
 use slint::ComponentHandle;
-use thirtyfour::WebDriver;
-use tokio::time::{sleep, Instant};
-use tracing::{error, info, instrument, warn};
-
-slint::include_modules!();
-
-use enums::{CustomError, LinkType, Locations};
-use structs::{ActivePages, AppState, Args, Config, PageData, State};
-
-mod utilities;
-mod config;
-mod disc_op;
-mod driver;
-mod enums;
-mod pdf;
-mod report;
-mod structs;
-mod update;
-
-#[instrument]
-fn check_link_type(url: &Url) -> anyhow::Result<LinkType> {
-    let url_string = url.to_string();
-
-    let link_type = if Path::new(&url_string)
-        .extension()
-        .map_or(false, |ext| ext.eq_ignore_ascii_case("docx"))
-    {
-        LinkType::Content
-    } else if url_string.starts_with("http") {
-        LinkType::Generic
-    } else if url_string.contains("/User") || url_string.starts_with("file://") {
-        LinkType::Local
-    } else if url_string.starts_with("mailto:") {
-        LinkType::Mailto
-    } else {
-        LinkType::Unknown
-    };
+use tokio::{sync::watch, time::Instant};
+use tracing::{error, info, warn};
+use url::Url;
+
+/// Logs what `driver::download_driver` found and only lets a check run with a driver.
+fn show_driver_status(
+    ui: &MainWindow,
+    app_state: &RefCell<AppState>,
+    status: anyhow::Result<Option<DriverStatus>>,
+) {
+    match status {
+        Ok(None) => {
+            app_state
+                .borrow_mut()
+                .add_to_geckodriver_update_log("Remote driver configured.", ui);
+            ui.global::<Globals>().set_link_check_can_run(true);
+            info!("Remote driver configured.")
+        }
+        Ok(Some(status)) => {
+            let message = match &status.newer_version {
+                Some(newer) => format!(
+                    "Using browser driver {}, {newer} is available.",
+                    status.version
+                ),
+                None => format!("Browser driver {} is up to date.", status.version),
+            };
+            ui.global::<UpdateCheck>()
+                .set_geckodriver_update_button_text(
+                    match &status.newer_version {
+                        Some(newer) => format!("{newer} available"),
+                        None => "Up to date".to_string(),
+                    }
+                    .into(),
+                );
+            app_state
+                .borrow_mut()
+                .add_to_geckodriver_update_log(&message, ui);
+            if let Some(warning) = &status.warning {
+                app_state
+                    .borrow_mut()
+                    .add_to_geckodriver_update_log(warning, ui);
+            }
+            ui.global::<Globals>().set_link_check_can_run(true);
+            info!("{message}")
+        }
+        Err(e) => {
+            app_state
+                .borrow_mut()
+                .add_to_geckodriver_update_log(&format!("{e:#}"), ui);
+            ui.global::<Globals>().set_link_check_can_run(false);
+            error!("{e:?}")
+        }
+    }
+}
 
-    Ok(link_type)
+fn show_storage_usage(ui: &MainWindow, paths: &Paths) {
+    let usage = match disc_op::measure_pages_dir_size(paths) {
+        Ok(usage) => utilities::format_bytes(usage.total),
+        Err(e) => {
+            warn!("Failed to measure local records: {e:?}");
+            "Unknown".to_string()
+        }
+    };
+    ui.global::<Settings>()
+        .set_local_storage_usage(usage.into());
 }
 
-fn title_check(title: &str) -> Result<(), CustomError> {
-    if title.contains("404") || title.contains("Not Found") {
-        return Err(CustomError::PageNotFound);
+/// Opens `location` in the system file manager, creating it first if it doesn't exist yet.
+fn open_folder(location: Locations, paths: &Paths) {
+    let path = paths.get(location);
+    if let Err(e) = std::fs::create_dir_all(&path) {
+        warn!("Failed to create {path:?}: {e:?}");
     }
-    if title.contains("Warning") {
-        return Err(CustomError::Warning);
+
+    match open::that(&path) {
+        Ok(()) => info!("Opened {path:?}"),
+        Err(e) => error!("Failed to open {path:?}: {e:?}"),
     }
-    if title.contains("Error") || title.contains("Unable to") || title.contains("Problem") {
-        return Err(CustomError::PageError);
+}
+
+/// What `forget` and `prune` removed, or would remove on a dry run.
+fn print_deletion(
+    record: &DeletionRecord,
+    dry_run: bool,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+
+    for url in &record.urls {
+        writeln!(output, "{verb} data store entry: {url}")?;
+    }
+    for path in &record.paths {
+        writeln!(output, "{verb} local records: {path:?}")?;
     }
+    writeln!(
+        output,
+        "{verb} {} entries and {} local record directories.",
+        record.urls.len(),
+        record.paths.len()
+    )?;
 
     Ok(())
 }
 
-async fn check_link(
-    driver: &WebDriver,
-    url: &Url,
-    marker: Option<&String>,
-    config: &Config,
-    linktype: LinkType,
-) -> State {
-    match linktype {
-        LinkType::Generic => {
-            if let Err(err) = driver.switch_to_named_window(url.as_str()).await {
-                warn!("Failed to switch to window: {err:?}");
-            }
-
-            let title = driver.title().await.unwrap_or_default();
-            let raw_page_source = driver.source().await.unwrap_or_default();
-
-            // Remove unique value from cookies extension
-            let rx_filter = regex::Regex::new(r" [a-z]* idc0_343").unwrap();
-            let page_source = rx_filter.replace_all(&raw_page_source, "");
-            let new_ss = driver.screenshot_as_png().await.unwrap_or_default();
-            let img = image::load_from_memory(&new_ss).unwrap_or_default();
+fn run_command(
+    command: Command,
+    data_store: &DataStore,
+    paths: &Paths,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let _lock = if matches!(command, Command::Source { .. } | Command::Diff { .. }) {
+        None
+    } else {
+        let lock = disc_op::acquire_run_lock(&paths.get(Locations::RunLock))?;
+        data_store
+            .reload()
+            .context("Failed to reload the data store")?;
+        Some(lock)
+    };
 
-            if config.keep_local_records {
-                if let Err(err) = disc_op::save_page_data(url, config, &page_source, &img) {
-                    panic!("Failed to save page data: {err:?}"); // TODO: Replace with proper error handling
+    match command {
+        Command::Forget { pattern, dry_run } => {
+            let config = config::no_ui_load(paths)?;
+            let record = disc_op::forget(data_store, &pattern, dry_run, &config, paths)?;
+            print_deletion(&record, dry_run, output)?;
+        }
+        Command::Source { url } => {
+            let config = config::no_ui_load(paths)?;
+            let url = Url::parse(&url).with_context(|| format!("Invalid URL: {url}"))?;
+            let url = canonical::canonicalize(&url, &config);
+
+            match disc_op::latest_page_source(&url, paths)? {
+                Some(source) => writeln!(output, "{source}")?,
+                None => anyhow::bail!("No page source is saved for {url}"),
+            }
+        }
+        Command::Prune {
+            stale_days,
+            dry_run,
+        } => {
+            let record = disc_op::prune(data_store, stale_days, dry_run, paths)?;
+            print_deletion(&record, dry_run, output)?;
+        }
+        Command::Export { out, with_records } => {
+            let exported = disc_op::export_archive(data_store, &out, with_records, paths)?;
+            writeln!(output, "Exported {exported} entries to {out:?}.")?;
+        }
+        Command::Import { archive, strategy } => {
+            let config = config::no_ui_load(paths)?;
+            let summary = disc_op::import_archive(
+                data_store,
+                &archive,
+                strategy,
+                config.history_length,
+                paths,
+            )?;
+            writeln!(
+                output,
+                "Added {} entries, merged {} and extracted {} local record files.",
+                summary.added, summary.merged, summary.records
+            )?;
+        }
+        Command::Diff { from, to } => {
+            let config = config::no_ui_load(paths)?;
+            let (from, to) = disc_op::load_run_pair(from.as_deref(), to.as_deref(), paths)?;
+            let (diff, path) =
+                report::gen_diff_report(&from, &to, data_store.snapshot(), &config, paths)?;
+
+            for (verb, urls) in [
+                ("Newly broken", &diff.newly_broken),
+                ("Recovered", &diff.recovered),
+                ("New", &diff.new_urls),
+                ("Removed", &diff.removed_urls),
+            ] {
+                for url in urls {
+                    writeln!(output, "{verb}: {url}")?;
                 }
             }
+            writeln!(
+                output,
+                "{} newly broken, {} recovered, {} new, {} removed, {} filtered out of one run.",
+                diff.newly_broken.len(),
+                diff.recovered.len(),
+                diff.new_urls.len(),
+                diff.removed_urls.len(),
+                diff.filtered_in_one.len()
+            )?;
+            writeln!(output, "Written to {path:?}")?;
+        }
+        Command::MigrateStore => {
+            let sqlite_path = paths.get(Locations::SqliteStore);
+            let copied =
+                disc_op::migrate_to_sqlite(&paths.get(Locations::DataStore), &sqlite_path)?;
+            writeln!(output, "Copied {copied} entries to {sqlite_path:?}.")?;
+            writeln!(
+                output,
+                "Set storage_backend = \"sqlite\" in {:?} to use it.",
+                paths.get(Locations::Config)
+            )?;
+        }
+        Command::Approve { pattern, all_valid } => {
+            let approved = if all_valid {
+                let config = config::no_ui_load(paths)?;
+                baseline::approve_all_valid(data_store, &config)?
+            } else {
+                baseline::approve(data_store, &pattern.unwrap_or_default())?
+            };
 
-            let mut error = None;
-            if utilities::hash_img(&img) == *"AAAAAAAAAAA" {
-                error = Some(CustomError::BadScreenshot);
+            for url in &approved {
+                writeln!(output, "Approved baseline: {url}")?;
             }
+            writeln!(output, "Approved {} baselines.", approved.len())?;
+        }
+        Command::ClearBaseline { pattern } => {
+            let cleared = baseline::clear(data_store, &pattern)?;
 
-            if let Some(marker) = marker {
-                if !page_source.contains(marker) {
-                    error = Some(CustomError::MarkerNotFound);
-                }
+            for url in &cleared {
+                writeln!(output, "Cleared baseline: {url}")?;
             }
-
-            if let Err(e) = title_check(&title) {
-                error = Some(e);
+            writeln!(output, "Cleared {} baselines.", cleared.len())?;
+        }
+        Command::Ignore {
+            pattern,
+            page_hash,
+            screenshot,
+            title,
+            skip,
+            clear,
+        } => {
+            let rules = IgnoreRules {
+                page_hash,
+                screenshot,
+                title,
+                skip,
+            };
+            if !clear && rules.is_empty() {
+                anyhow::bail!(
+                    "Nothing to ignore, pass --page-hash, --screenshot, --title, --skip or --clear"
+                );
             }
 
-            if url.as_str() != driver.current_url().await.unwrap().as_str() {
-                error = Some(CustomError::Redirected);
+            let changed = baseline::ignore(data_store, &pattern, (!clear).then_some(rules))?;
+            let verb = if clear {
+                "Cleared ignore rules"
+            } else {
+                "Ignoring"
+            };
+            for url in &changed {
+                writeln!(output, "{verb}: {url}")?;
             }
-
-            State::new(
-                &page_source,
-                Some(img),
-                Some(title),
-                LinkType::Generic,
-                error,
-            )
+            writeln!(output, "Updated {} URLs.", changed.len())?;
         }
+    }
 
-        LinkType::Content => {
-            let content = utilities::download_file(url.to_string()).await.unwrap_or_default();
-            State::new(&content, None, None, LinkType::Content, None)
-        }
+    Ok(())
+}
 
-        LinkType::Local => State::new(
-            "",
-            None,
-            None,
-            LinkType::Local,
-            Some(CustomError::LinkTypeLocal),
-        ),
-        LinkType::Mailto => State::new(
-            "",
-            None,
-            None,
-            LinkType::Mailto,
-            Some(CustomError::LinkTypeMailto),
-        ),
-        LinkType::Unknown => State::new(
-            "",
-            None,
-            None,
-            LinkType::Unknown,
-            Some(CustomError::UnknownLinkType),
-        ),
-        LinkType::InternalError => State::new(
-            "",
-            None,
-            None,
-            LinkType::InternalError,
-            Some(CustomError::WebDriverError),
-        ),
+/// What a run would check, for `--dry-run`. Nothing but the plan goes to `output`, it's
+/// meant to be piped.
+async fn print_dry_run(
+    source_path: Option<String>,
+    data_store: &DataStore,
+    paths: &Paths,
+    output: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut config = config::no_ui_load(paths)?;
+    if source_path.is_some() {
+        config.source_path = source_path;
     }
+    writeln!(output, "{}", preview_run(&config, data_store).await?)?;
+    Ok(())
 }
 
-async fn check_links(
-    mut driver: WebDriver,
-    urls: HashSet<Url>,
-    page_datas: BTreeMap<Url, PageData>,
+/// A run and its report without the GUI, for `--headless` and `--daemon`.
+async fn run_and_report(
     config: &Config,
-) -> anyhow::Result<Vec<(Url, State)>> {
-    let mut url_in_waiting: Vec<ActivePages> = Vec::new();
-    let mut results = Vec::new();
-
-    for url in &urls {
-        let linktype = match check_link_type(url) {
-            Ok(linktype) => linktype,
-            Err(e) => {
-                error!("Failed to check link type: {e:?}");
-                LinkType::InternalError
-            }
-        };
+    data_store: &DataStore,
+    trigger: RunTrigger,
+    paths: &Paths,
+    metrics: &LatestMetrics,
+) -> anyhow::Result<ReportSummary> {
+    let checker = Checker::new(config.clone(), paths.clone()).with_metrics(metrics.clone());
+    let start = Instant::now();
+    let control = RunControl::new(|progress| {
+        info!(
+            "Progress: {}/{} checked, {} errors, loading {}",
+            progress.completed, progress.total, progress.errors, progress.current_url
+        );
+    });
+    checker.run(None, data_store, &control, trigger).await?;
+    let duration = start.elapsed();
+    info!(
+        "Finished in {} minutes {} seconds.",
+        duration.as_secs() / 60,
+        duration.as_secs() % 60
+    );
+
+    let summary = checker
+        .report(data_store)
+        .context("Failed to generate the report")?;
+    println!("Report: {summary}");
+    for path in &summary.written {
+        println!("Written to {path:?}");
+    }
 
-        if linktype == LinkType::Generic {
-            info!("Loading link: {}", url.as_str());
-            driver = new_tab(driver, url.as_str()).await?;
-            url_in_waiting.push(ActivePages {
-                url: url.clone(),
-                time_added: Instant::now(),
-                linktype,
-            });
+    Ok(summary)
+}
 
-            // Removing links significantly decreases ram usage
-            if !url_in_waiting.is_empty()
-                && url_in_waiting[0].time_added + config.page_dwell_time < Instant::now()
-            {
-                let url = url_in_waiting.remove(0).url;
-                info!("Removing {} from waiting list", url.as_str());
+/// Fails when any URL is in the error or unknown table, the ones the JUnit report
+/// lists as failures, unless `no_fail` is set.
+fn fail_on_broken_links(summary: &ReportSummary, no_fail: bool) -> anyhow::Result<()> {
+    if !no_fail && summary.error + summary.unknown > 0 {
+        anyhow::bail!(
+            "{} links landed in the error table and {} in the unknown table",
+            summary.error,
+            summary.unknown
+        );
+    }
 
-                let marker = page_datas
-                    .get(&url)
-                    .map(|page_data| page_data.marker())
-                    .unwrap_or_default();
+    Ok(())
+}
 
-                let state = check_link(&driver, &url, marker, config, linktype).await;
-                driver::safely_close_window(&driver, &url).await?;
-                results.push((url, state));
-            }
-        } else {
-            results.push((
-                url.clone(),
-                check_link(&driver, url, None, config, linktype).await,
-            ));
-        }
+/// See `fail_on_broken_links` for when it fails.
+async fn run_headless(
+    source_path: Option<String>,
+    resume: bool,
+    junit_out: Option<String>,
+    no_fail: bool,
+    data_store: &DataStore,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    let mut config = config::no_ui_load(paths)?;
+    if source_path.is_some() {
+        config.source_path = source_path;
     }
-
-    for ActivePages {
-        url,
-        time_added,
-        linktype,
-    } in url_in_waiting.drain(..)
-    {
-        sleep(config.page_dwell_time.saturating_sub(time_added.elapsed())).await;
-        info!("Removing {} from waiting list", url.as_str());
-
-        let marker = page_datas
-            .get(&url)
-            .map(|page_data| page_data.marker())
-            .unwrap_or_default();
-
-        let state = check_link(&driver, &url, marker, config, linktype).await;
-        driver::safely_close_window(&driver, &url).await?;
-        results.push((url, state));
+    if junit_out.is_some() {
+        config.junit_path = junit_out;
     }
-
-    driver.quit().await?;
-    Ok(results)
+    config.resume = resume;
+
+    let summary = run_and_report(
+        &config,
+        data_store,
+        RunTrigger::Manual,
+        paths,
+        &LatestMetrics::default(),
+    )
+    .await?;
+    fail_on_broken_links(&summary, no_fail)
 }
 
-#[instrument(skip(config))]
-async fn link_checker(config: &Config, urls: Option<Vec<String>>) -> anyhow::Result<()> {
-    driver::stop_geckos().await;
-
-    let datastore_path = get_loc(Locations::DataStore);
-    let mut page_datas =
-        disc_op::load_data_store(&datastore_path).context("Failed to load data store")?;
-
-    let urls_to_check = pdf::get_urls(config.pdf_path.clone(), config.pdf_url.clone(), urls)
-        .await
-        .context("Failed to get URLs to check")?;
-
-    let driver = match driver::fire_up_and_setup_the_gecko(config).await {
-        Ok(driver) => driver,
-        Err(e) => return Err(anyhow::anyhow!(e)),
+/// Runs on the config's schedule until stopped. The config is read again for every
+/// run, a changed schedule applies from the run after the change.
+async fn run_daemon(
+    source_path: Option<String>,
+    data_store: &DataStore,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    let config = config::no_ui_load(paths)?;
+    let Some(schedule) = config.active_schedule() else {
+        anyhow::bail!("No schedule to follow, set schedule in the config and enable it");
     };
-
-    let results = check_links(driver, urls_to_check, page_datas.clone(), config)
-        .await
-        .context("Failed to check links")?;
-
-    for (url, state) in results {
-        if let std::collections::btree_map::Entry::Vacant(e) = page_datas.entry(url.clone()) {
-            let _ = e.insert(PageData::new(
-                state,
-                utilities::hash_string(&url.to_string()),
-                None,
-            ));
-        } else if let Some(page_data) = page_datas.get_mut(&url) {
-            page_data.update(state);
-        }
+    info!("Running as a daemon, schedule {schedule}");
+    let latest_metrics = LatestMetrics::default();
+    if let Some(address) = config.metrics_listen {
+        let listener = metrics::bind(address).await?;
+        drop(tokio::spawn(metrics::serve(
+            listener,
+            latest_metrics.clone(),
+        )));
     }
 
-    let datastore_path = get_loc(Locations::DataStore);
-    disc_op::save_data_store(&page_datas, &datastore_path).context("Failed to save data store")?;
+    let (schedule_tx, mut schedule_rx) = watch::channel(Some(schedule));
+    let mut after = chrono::Local::now();
+    while let Some(trigger) = schedule::wait_for_trigger(&mut schedule_rx, after, &|_| {}).await {
+        match config::no_ui_load(paths) {
+            Ok(mut config) => {
+                if source_path.is_some() {
+                    config.source_path = source_path.clone();
+                }
+                if let Err(e) = run_and_report(
+                    &config,
+                    data_store,
+                    RunTrigger::Scheduled,
+                    paths,
+                    &latest_metrics,
+                )
+                .await
+                {
+                    error!("Scheduled run failed: {e:?}");
+                }
 
-    driver::stop_geckos().await;
+                if config.active_schedule().is_none() {
+                    info!("The schedule was turned off in the config, stopping");
+                    return Ok(());
+                }
+                let _ = schedule_tx.send_replace(config.active_schedule());
+            }
+            Err(e) => error!("Skipping the scheduled run, the config can't be loaded: {e:?}"),
+        }
 
-    info!("Link checking completed successfully");
+        // Triggers that passed during the run are skipped
+        after = trigger.max(chrono::Local::now());
+    }
 
     Ok(())
 }
 
-static PROJECT_NS: OnceLock<Option<ProjectDirs>> = OnceLock::new();
-static ARCHITECTURE: OnceLock<&str> = OnceLock::new();
-static OPERATING_SYSTEM: OnceLock<&str> = OnceLock::new();
-
 #[tokio::main]
 async fn main() -> Result<(), anyhow::Error> {
-    println!("Hello, world!");
-    let _guard = init_tracing();
+    let paths = Paths::from_project_dirs()?;
+    let _guard = init_tracing(&paths);
+    guard::install_panic_hook();
 
     let args = Args::parse();
+    disc_op::init_storage(args.clean_start, &paths);
+    // The migration reads the JSON data store whichever backend is configured
+    let backend = if matches!(args.command, Some(Command::MigrateStore)) {
+        StorageBackend::Json
+    } else {
+        config::storage_backend(&paths)?
+    };
+    let storage = disc_op::open_storage(
+        backend,
+        &paths.get(Locations::DataStore),
+        &paths.get(Locations::SqliteStore),
+    )?;
+    let data_store = DataStore::load(storage).context("Failed to load data store")?;
+
+    if let Some(command) = args.command {
+        return run_command(command, &data_store, &paths, &mut std::io::stdout());
+    }
+
+    if args.dry_run {
+        return print_dry_run(
+            args.source_path,
+            &data_store,
+            &paths,
+            &mut std::io::stdout(),
+        )
+        .await;
+    }
+
+    if args.headless {
+        return run_headless(
+            args.source_path,
+            args.resume,
+            args.junit_out,
+            args.no_fail,
+            &data_store,
+            &paths,
+        )
+        .await;
+    }
+
+    if args.daemon {
+        return run_daemon(args.source_path, &data_store, &paths).await;
+    }
 
     let ui = MainWindow::new()?;
     let app_state = Rc::new(RefCell::new(AppState::new()));
 
-    disc_op::init_storage(args.clean_start);
-    let config = match config::load(&ui, &mut app_state.borrow_mut()) {
+    let config = match config::load(&ui, &mut app_state.borrow_mut(), &paths) {
         Ok(config) => Rc::new(RefCell::new(config)),
         Err(e) => return Err(anyhow::anyhow!(e.to_string())),
     };
 
+    show_storage_usage(&ui, &paths);
+    // Settings changes reach the scheduler through this, no restart needed
+    let (schedule_tx, mut schedule_rx) = watch::channel(config.borrow().active_schedule());
+
     let ui_weak = ui.as_weak();
     ui.global::<UpdateCheck>().on_self_check_update({
         let app_state = app_state.clone();
+        let paths = paths.clone();
 
         if args.check_for_update {
             update::helper(&ui, &mut app_state.borrow_mut());
@@ -327,7 +518,9 @@ async fn main() -> Result<(), anyhow::Error> {
 
         move || {
             if let Some(ui) = ui_weak.upgrade() {
-                update::helper(&ui, &mut app_state.borrow_mut());
+                guard::guarded("self_check_update", &ui, &paths, || {
+                    update::helper(&ui, &mut app_state.borrow_mut());
+                });
             }
         }
     });
@@ -335,31 +528,57 @@ async fn main() -> Result<(), anyhow::Error> {
     let ui_weak = ui.as_weak();
     ui.global::<UpdateCheck>().on_geckodriver_check_update({
         let app_state = app_state.clone();
+        let paths = paths.clone();
 
         let rc_config = Rc::clone(&config);
-        let config_gecko = rc_config.borrow().gecko.clone();
-        match driver::download_gecko( &config_gecko).await {
-            Ok(()) => {
-                app_state
-                    .borrow_mut()
-                    .add_to_geckodriver_update_log("Geckodriver is up to date.", &ui);
-                ui.global::<Globals>().set_link_check_can_run(true);
-                info!("Geckodriver is up to date.")
-            }
-            Err(e) => {
-                app_state
-                    .borrow_mut()
-                    .add_to_geckodriver_update_log(&e.to_string(),  &ui);
-                ui.global::<Globals>().set_link_check_can_run(false);
-                error!("{e:?}")
-            }
-        }
+        let driver_config = rc_config.borrow().clone();
+        show_driver_status(
+            &ui,
+            &app_state,
+            driver::download_driver(&driver_config, &paths).await,
+        );
+        let runtime = tokio::runtime::Handle::current();
 
         move || {
             if let Some(ui) = ui_weak.upgrade() {
-                app_state
-                    .borrow_mut()
-                    .add_to_geckodriver_update_log("Not yet implemented, go to https://github.com/mozilla/geckodriver/releases/latest to check :)", &ui);
+                guard::guarded("geckodriver_check_update", &ui, &paths, || {
+                    // The binary is replaced in place, a running check still uses it
+                    if ui.get_link_checker_running() {
+                        app_state.borrow_mut().add_to_geckodriver_update_log(
+                            "Wait for the link check to finish before updating the driver.",
+                            &ui,
+                        );
+                        return;
+                    }
+
+                    app_state
+                        .borrow_mut()
+                        .add_to_geckodriver_update_log("Checking for a newer driver...", &ui);
+                    ui.global::<Globals>().set_link_check_can_run(false);
+                    let driver_config = rc_config.borrow().clone();
+                    let driver_paths = paths.clone();
+                    let runtime = runtime.clone();
+                    let app_state = app_state.clone();
+                    let ui_weak = ui.as_weak();
+                    let spawned = slint::spawn_local(async move {
+                        let status = runtime
+                            .spawn(async move {
+                                driver::download_driver(&driver_config, &driver_paths).await
+                            })
+                            .await
+                            .unwrap_or_else(|e| {
+                                Err(anyhow::anyhow!("The driver update failed: {e}"))
+                            });
+                        if let Some(ui) = ui_weak.upgrade() {
+                            show_driver_status(&ui, &app_state, status);
+                        }
+                    });
+
+                    if let Err(e) = spawned {
+                        error!("Failed to start the driver update: {e:?}");
+                        ui.global::<Globals>().set_link_check_can_run(true);
+                    }
+                });
             }
         }
     });
@@ -367,18 +586,34 @@ async fn main() -> Result<(), anyhow::Error> {
     let ui_weak = ui.as_weak();
     ui.global::<Settings>().on_update_config_value({
         let rc_config = Rc::clone(&config);
+        let paths = paths.clone();
 
         move |key, value| {
             if let Some(ui) = ui_weak.upgrade() {
-                ui.global::<Settings>().set_config_saved(false);
-                info!("value: {:?}", value);
-                match rc_config.borrow_mut().update(&key, &value) {
-                    Ok(()) => "".to_string().into(),
-                    Err(e) => {
-                        error!("{e:?}");
-                        e.to_string().to_uppercase().into()
+                guard::guarded("update_config_value", &ui, &paths, || {
+                    ui.global::<Settings>().set_config_saved(false);
+                    // The proxy URL may carry credentials
+                    if key != "proxy_url" {
+                        info!("value: {:?}", value);
                     }
-                }
+                    let updated = rc_config.borrow_mut().update(&key, &value);
+                    match updated {
+                        Ok(()) => {
+                            let schedule = rc_config.borrow().active_schedule();
+                            let _ = schedule_tx.send_if_modified(|current| {
+                                let modified = *current != schedule;
+                                *current = schedule;
+                                modified
+                            });
+                            "".to_string().into()
+                        }
+                        Err(e) => {
+                            error!("{e:?}");
+                            e.to_string().to_uppercase().into()
+                        }
+                    }
+                })
+                .unwrap_or_else(|| "INTERNAL ERROR, SEE LOG".to_string().into())
             } else {
                 "Unreachable?".to_string().into()
             }
@@ -389,91 +624,270 @@ async fn main() -> Result<(), anyhow::Error> {
     ui.global::<Settings>().on_write_config({
         let rc_config = Rc::clone(&config);
         let app_state = app_state.clone();
+        let paths = paths.clone();
 
         move || {
             if let Some(ui) = ui_weak.upgrade() {
-                let config = rc_config.borrow();
-
-                match config::write_config_file(&config, &get_loc(Locations::Config)) {
-                    Ok(()) => {
-                        ui.global::<Settings>().set_config_saved(true);
-                        app_state
-                            .borrow_mut()
-                            .add_to_config_log("Config saved successfully.", &ui);
+                guard::guarded("write_config", &ui, &paths, || {
+                    let config = rc_config.borrow();
+
+                    match config::write_config_file(&config, &paths.get(Locations::Config)) {
+                        Ok(()) => {
+                            ui.global::<Settings>().set_config_saved(true);
+                            app_state
+                                .borrow_mut()
+                                .add_to_config_log("Config saved successfully.", &ui);
+                        }
+                        Err(e) => {
+                            error!("{e:?}");
+                            app_state
+                                .borrow_mut()
+                                .add_to_config_log("Failed to save config.", &ui);
+                        }
                     }
-                    Err(e) => {
-                        error!("{e:?}");
-                        app_state
-                            .borrow_mut()
-                            .add_to_config_log("Failed to save config.", &ui);
-                    }
-                }
 
-                ui.global::<Settings>().set_config_saved(true);
-                app_state
-                    .borrow_mut()
-                    .add_to_config_log("Saved loaded successfully.", &ui);
+                    ui.global::<Settings>().set_config_saved(true);
+                    app_state
+                        .borrow_mut()
+                        .add_to_config_log("Saved loaded successfully.", &ui);
+                });
             }
         }
     });
 
-    let ui_weak = ui.as_weak();
-    ui.on_run_link_checker({
+    let control = Arc::new(RunControl::new({
+        let ui_weak = ui.as_weak();
+        move |progress| {
+            let progress = progress.clone();
+            let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                let globals = ui.global::<Globals>();
+                globals.set_progress_url(progress.current_url.into());
+                globals.set_progress_current(progress.completed as i32);
+                globals.set_progress_total(progress.total as i32);
+                globals.set_progress_errors(progress.errors as i32);
+            });
+        }
+    }));
+
+    ui.global::<Globals>().on_open_data_folder({
+        let paths = paths.clone();
+        move || open_folder(Locations::BaseData, &paths)
+    });
+    ui.global::<Globals>().on_open_config_folder({
+        let paths = paths.clone();
+        move || open_folder(Locations::BaseConfig, &paths)
+    });
+
+    ui.on_cancel_link_checker({
+        let control = Arc::clone(&control);
         move || {
-            info!("Running link checker");
+            info!("Cancelling link checker");
+            control.cancel();
+        }
+    });
+
+    let ui_weak = ui.as_weak();
+    // Runs go onto the runtime main is already running on, the UI only awaits the result
+    let runtime = tokio::runtime::Handle::current();
+    let start_run = Rc::new({
+        let data_store = data_store.clone();
+        let app_state = app_state.clone();
+        let rc_config = Rc::clone(&config);
+        let paths = paths.clone();
+        let runtime = runtime.clone();
+        move |trigger: RunTrigger| {
+            info!("Running link checker, {trigger:?}");
             if let Some(ui) = ui_weak.upgrade() {
                 let start = Instant::now();
-                slint::spawn_local(async move {
-                    ui.set_link_checker_running(true);
-                    sleep(Duration::from_secs(10)).await;
-                    let tokio_runtime = tokio::runtime::Runtime::new().unwrap();
-                    let result = tokio_runtime
+                // The run gets its own copy, changes made while it runs apply to the next one
+                let checker = Checker::new(rc_config.borrow().clone(), paths.clone());
+                if !ui.global::<Settings>().get_config_saved() {
+                    warn!("Running with unsaved config changes");
+                    app_state.borrow_mut().add_to_config_log(
+                        "This run uses the unsaved changes, save them to keep them.",
+                        &ui,
+                    );
+                }
+                let data_store = data_store.clone();
+                let control = Arc::clone(&control);
+                control.reset();
+                let runtime = runtime.clone();
+                let paths = paths.clone();
+                // Set before the run starts so a scheduled run right behind it sees it
+                ui.set_link_checker_running(true);
+                let spawned = slint::spawn_local(async move {
+                    let run_control = Arc::clone(&control);
+                    let result = runtime
                         .spawn(async move {
-                            // TODO: Use config without having to reload it
-                            let config = config::no_ui_load().unwrap();
-
-                            match link_checker(&config, None).await {
-                                Ok(()) => {
-                                    info!("Link checking completed successfully");
-                                }
-                                Err(e) => {
-                                    anyhow::bail!("{e:?}")
-                                }
-                            }
-
-                            Ok(())
+                            checker.run(None, &data_store, &run_control, trigger).await
                         })
-                        .await
-                        .unwrap();
-                    result.unwrap();
+                        .await;
+
+                    match result {
+                        Ok(Ok(RunOutcome::Completed)) => {
+                            info!("Link checking completed successfully");
+                        }
+                        Ok(Ok(RunOutcome::Cancelled)) => {
+                            info!("Link checking was cancelled by the user");
+                        }
+                        Ok(Err(e)) => {
+                            error!("{e:?}");
+                            guard::report_failure(
+                                &ui,
+                                format!("Link checking failed: {e:?}"),
+                                &paths,
+                            );
+                        }
+                        Err(e) => {
+                            guard::report_failure(
+                                &ui,
+                                guard::task_failure_details("run_link_checker", e),
+                                &paths,
+                            );
+                        }
+                    }
 
-                    std::mem::forget(tokio_runtime);
+                    control.reset();
                     let duration = start.elapsed();
                     info!(
                         "Finished in {} minutes {} seconds.",
                         duration.as_secs() / 60,
                         duration.as_secs() % 60
                     );
+                    show_storage_usage(&ui, &paths);
                     ui.set_link_checker_running(false);
-                })
-                .unwrap();
+                });
+
+                if let Err(e) = spawned {
+                    error!("Failed to start the link checker: {e:?}");
+                    if let Some(ui) = ui_weak.upgrade() {
+                        ui.set_link_checker_running(false);
+                    }
+                }
+            }
+        }
+    });
+    ui.on_run_link_checker({
+        let start_run = Rc::clone(&start_run);
+        move || start_run(RunTrigger::Manual)
+    });
+    ui.on_scheduled_run(move || start_run(RunTrigger::Scheduled));
+
+    drop(runtime.spawn({
+        let ui_weak = ui.as_weak();
+        async move {
+            let show_next = |next: Option<chrono::DateTime<chrono::Local>>| {
+                let text = next.map_or_else(String::new, |next| {
+                    format!("Next scheduled run {}", next.format("%a %e %b %H:%M"))
+                });
+                let _ = ui_weak.upgrade_in_event_loop(move |ui| {
+                    ui.global::<Globals>().set_next_scheduled_run(text.into());
+                });
+            };
+
+            let mut after = chrono::Local::now();
+            while let Some(trigger) =
+                schedule::wait_for_trigger(&mut schedule_rx, after, &show_next).await
+            {
+                let _ = ui_weak.upgrade_in_event_loop(|ui| {
+                    if ui.get_link_checker_running() {
+                        info!("Skipping the scheduled run, a run is already in progress");
+                    } else if !ui.global::<Globals>().get_link_check_can_run() {
+                        warn!("Skipping the scheduled run, the browser driver isn't ready");
+                    } else {
+                        ui.invoke_scheduled_run();
+                    }
+                });
+                after = trigger;
+            }
+        }
+    }));
+
+    let ui_weak = ui.as_weak();
+    ui.on_preview_run({
+        let data_store = data_store.clone();
+        let rc_config = Rc::clone(&config);
+        let paths = paths.clone();
+        let runtime = runtime.clone();
+        move || {
+            let Some(ui) = ui_weak.upgrade() else {
+                return;
+            };
+            let config = rc_config.borrow().clone();
+            let data_store = data_store.clone();
+            let runtime = runtime.clone();
+            let paths = paths.clone();
+            ui.global::<Globals>()
+                .set_run_preview("Looking for URLs...".into());
+            let spawned = slint::spawn_local(async move {
+                let result = runtime
+                    .spawn(async move { preview_run(&config, &data_store).await })
+                    .await;
+
+                let preview = match result {
+                    Ok(Ok(preview)) => preview.to_string(),
+                    Ok(Err(e)) => {
+                        error!("Failed to preview the run: {e:?}");
+                        format!("Failed to preview the run: {e:#}")
+                    }
+                    Err(e) => {
+                        guard::report_failure(
+                            &ui,
+                            guard::task_failure_details("preview_run", e),
+                            &paths,
+                        );
+                        return;
+                    }
+                };
+                ui.global::<Globals>().set_run_preview(preview.into());
+            });
+
+            if let Err(e) = spawned {
+                error!("Failed to start the preview: {e:?}");
             }
         }
     });
 
+    let ui_weak = ui.as_weak();
     ui.on_gen_report({
         let rc_config = Rc::clone(&config);
+        let data_store = data_store.clone();
+        let app_state = app_state.clone();
         move || {
-            let config = rc_config.borrow();
-            report::gen_post_run_report(&config);
-            let report_path = get_loc(Locations::Report);
-            match open::that(&report_path) {
-                Ok(()) => {
-                    info!("Report opened successfully");
-                }
-                Err(e) => {
-                    info!("Failed to auto open report, error: {e:?}. Report path: {report_path:?}");
-                }
+            if let Some(ui) = ui_weak.upgrade() {
+                guard::guarded("gen_report", &ui, &paths, || {
+                    let config = rc_config.borrow();
+                    let summary = match report::gen_post_run_report(
+                        data_store.snapshot(),
+                        &config,
+                        &paths,
+                    ) {
+                        Ok(summary) => summary,
+                        Err(e) => {
+                            error!("Failed to generate the report: {e:?}");
+                            app_state
+                                .borrow_mut()
+                                .add_to_config_log(&format!("Report failed: {e:#}"), &ui);
+                            return;
+                        }
+                    };
+                    app_state
+                        .borrow_mut()
+                        .add_to_config_log(&format!("Report generated: {summary}"), &ui);
+                    if !config.report_formats.contains(&ReportFormat::Html) {
+                        return;
+                    }
+
+                    let report_path = paths.get(Locations::Report);
+                    match open::that(&report_path) {
+                        Ok(()) => {
+                            info!("Report opened successfully");
+                        }
+                        Err(e) => {
+                            info!("Failed to auto open report, error: {e:?}. Report path: {report_path:?}");
+                        }
+                    }
+                });
             }
         }
     });
@@ -481,3 +895,177 @@ async fn main() -> Result<(), anyhow::Error> {
     ui.run().unwrap();
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use link_rustler::{enums::LinkType, structs::State, utilities::hash_string};
+
+    use super::*;
+
+    fn open(dir: &std::path::Path) -> (Paths, DataStore) {
+        let paths = Paths::new(dir.join("config"), dir.join("data"));
+        disc_op::init_storage(false, &paths);
+        let storage = disc_op::open_storage(
+            StorageBackend::Json,
+            &paths.get(Locations::DataStore),
+            &paths.get(Locations::SqliteStore),
+        )
+        .unwrap();
+        (paths, DataStore::load(storage).unwrap())
+    }
+
+    #[test]
+    fn a_headless_run_fails_on_errors_and_unknowns() {
+        let fine = ReportSummary {
+            valid: 3,
+            hash_only: 1,
+            first_seen: 2,
+            ..ReportSummary::default()
+        };
+        assert!(fail_on_broken_links(&fine, false).is_ok());
+
+        let broken = ReportSummary {
+            error: 2,
+            unknown: 1,
+            ..fine
+        };
+        assert_eq!(
+            fail_on_broken_links(&broken, false)
+                .unwrap_err()
+                .to_string(),
+            "2 links landed in the error table and 1 in the unknown table"
+        );
+        assert!(fail_on_broken_links(&broken, true).is_ok());
+    }
+
+    #[test]
+    fn source_prints_nothing_but_the_page_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) = open(dir.path());
+        let record_dir = paths
+            .get(Locations::PagesSubdir)
+            .join(hash_string(&"https://example.com/page".to_string()));
+        std::fs::create_dir_all(&record_dir).unwrap();
+        std::fs::write(
+            record_dir.join("page_2024-01-31T02:00:00Z.html"),
+            "<p>Older</p>",
+        )
+        .unwrap();
+        std::fs::write(
+            record_dir.join("page_2024-02-29T02:00:00Z.html"),
+            "<p>Saved</p>",
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        run_command(
+            Command::Source {
+                url: "https://example.com/page".to_string(),
+            },
+            &data_store,
+            &paths,
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "<p>Saved</p>\n");
+
+        let mut output = vec![];
+        let err = run_command(
+            Command::Source {
+                url: "https://example.com/other".to_string(),
+            },
+            &data_store,
+            &paths,
+            &mut output,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("No page source"), "{err:?}");
+        assert!(output.is_empty());
+    }
+
+    fn run(command: Command, data_store: &DataStore, paths: &Paths) -> String {
+        let mut output = vec![];
+        run_command(command, data_store, paths, &mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn forget_and_prune_list_what_they_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) = open(dir.path());
+        let urls = [
+            Url::parse("https://a.example/").unwrap(),
+            Url::parse("https://b.example/").unwrap(),
+        ];
+        for url in &urls {
+            let state = State::new("<p>Page</p>", None, None, LinkType::Generic, vec![]);
+            data_store.merge(url.clone(), state, 5);
+        }
+        data_store.mark_seen(&urls);
+        data_store.save().unwrap();
+        let forget = |dry_run| Command::Forget {
+            pattern: "https://a.example/".to_string(),
+            dry_run,
+        };
+
+        assert_eq!(
+            run(forget(true), &data_store, &paths),
+            "\
+Would remove data store entry: https://a.example/
+Would remove 1 entries and 0 local record directories.
+"
+        );
+        assert_eq!(
+            run(forget(false), &data_store, &paths),
+            "\
+Removed data store entry: https://a.example/
+Removed 1 entries and 0 local record directories.
+"
+        );
+        assert_eq!(
+            run(
+                Command::Prune {
+                    stale_days: 0,
+                    dry_run: false,
+                },
+                &data_store,
+                &paths,
+            ),
+            "\
+Removed data store entry: https://b.example/
+Removed 1 entries and 0 local record directories.
+"
+        );
+    }
+
+    #[tokio::test]
+    async fn a_dry_run_prints_only_the_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        let (paths, data_store) = open(dir.path());
+        let source = dir.path().join("links.md");
+        std::fs::write(
+            &source,
+            "[B](https://b.example/) and [A](https://a.example/docs), A again: <https://a.example/docs>",
+        )
+        .unwrap();
+
+        let mut output = vec![];
+        print_dry_run(
+            Some(source.to_string_lossy().to_string()),
+            &data_store,
+            &paths,
+            &mut output,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "\
+Generic: 2 URLs, 2 new, 0 known
+  new   https://a.example/docs
+  new   https://b.example/
+2 URLs to check, 0 filtered out, 0 checked recently
+"
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use anyhow::Context;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+use tracing::{error, info, instrument, warn};
+
+use crate::{
+    enums::{ReportStatus, RunTrigger},
+    structs::{Config, LatestMetrics, ReportEntry, RunSummary},
+};
+
+/// Every report table, in the order the metrics list them. Tables without URLs are
+/// still written so a dashboard never sees a series disappear.
+const STATUSES: [ReportStatus; 7] = [
+    ReportStatus::Error,
+    ReportStatus::Unknown,
+    ReportStatus::HashOnly,
+    ReportStatus::Valid,
+    ReportStatus::FirstSeen,
+    ReportStatus::Ignored,
+    ReportStatus::NotInSource,
+];
+
+/// The `status` label, the same names the JSON report uses.
+fn status_label(status: ReportStatus) -> &'static str {
+    match status {
+        ReportStatus::Error => "error",
+        ReportStatus::Unknown => "unknown",
+        ReportStatus::HashOnly => "hash_only",
+        ReportStatus::Valid => "valid",
+        ReportStatus::FirstSeen => "first_seen",
+        ReportStatus::Ignored => "ignored",
+        ReportStatus::NotInSource => "not_in_source",
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', r#"\""#)
+        .replace('\n', r"\n")
+}
+
+fn write_family(out: &mut String, name: &str, help: &str, samples: &[(String, String)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+/// The run in the OpenMetrics text format. Names and labels are what dashboards and
+/// alerts are built on, keep them stable:
+///
+/// - `link_rustler_urls{status}` URLs of the run per report table, `status` being
+///   error, unknown, hash_only, valid, first_seen, ignored or not_in_source
+/// - `link_rustler_domain_errors{domain}` URLs in the error table per host, only
+///   hosts with errors are listed
+/// - `link_rustler_urls_checked` URLs the run set out to check
+/// - `link_rustler_run_duration_seconds` from start to finish, 0 for a run that didn't
+///   finish
+/// - `link_rustler_run_finished_timestamp_seconds` when the run ended, Unix time
+/// - `link_rustler_run_cancelled` 1 when the run was stopped early
+/// - `link_rustler_run_scheduled` 1 when the scheduler started the run
+/// - `link_rustler_driver_restarts` browser driver restarts during the run
+/// - `link_rustler_pages_timed_out` pages closed after `per_page_check_timeout`
+///
+/// Before the first run every family is there without samples.
+fn render(summary: Option<&RunSummary>, entries: &[ReportEntry]) -> String {
+    let mut domain_errors: BTreeMap<&str, usize> = BTreeMap::new();
+    for entry in entries
+        .iter()
+        .filter(|entry| entry.status == ReportStatus::Error)
+    {
+        *domain_errors
+            .entry(entry.url.host_str().unwrap_or_default())
+            .or_insert(0) += 1;
+    }
+
+    let mut out = String::new();
+    write_family(
+        &mut out,
+        "link_rustler_urls",
+        "URLs of the last run per report table.",
+        &summary.map_or_else(Vec::new, |summary| {
+            STATUSES
+                .iter()
+                .map(|status| {
+                    (
+                        format!("{{status=\"{}\"}}", status_label(*status)),
+                        summary
+                            .status_counts
+                            .get(status)
+                            .copied()
+                            .unwrap_or(0)
+                            .to_string(),
+                    )
+                })
+                .collect()
+        }),
+    );
+    write_family(
+        &mut out,
+        "link_rustler_domain_errors",
+        "URLs of the last run in the error table per host.",
+        &domain_errors
+            .into_iter()
+            .map(|(domain, count)| {
+                (
+                    format!("{{domain=\"{}\"}}", escape_label(domain)),
+                    count.to_string(),
+                )
+            })
+            .collect::<Vec<_>>(),
+    );
+    let duration = summary.map(|summary| {
+        summary.finished.map_or(0, |finished| {
+            (finished - summary.started).num_milliseconds().max(0)
+        })
+    });
+    let plain = [
+        (
+            "link_rustler_urls_checked",
+            "URLs the last run set out to check.",
+            summary.map(|summary| summary.urls_checked.to_string()),
+        ),
+        (
+            "link_rustler_run_duration_seconds",
+            "Duration of the last run, 0 when it didn't finish.",
+            duration.map(|duration| format!("{:.3}", duration as f64 / 1000.0)),
+        ),
+        (
+            "link_rustler_run_finished_timestamp_seconds",
+            "When the last run ended.",
+            summary.map(|summary| {
+                summary
+                    .finished
+                    .map_or(0, |finished| finished.timestamp())
+                    .to_string()
+            }),
+        ),
+        (
+            "link_rustler_run_cancelled",
+            "Whether the last run was cancelled.",
+            summary.map(|summary| u8::from(summary.cancelled).to_string()),
+        ),
+        (
+            "link_rustler_run_scheduled",
+            "Whether the scheduler started the last run.",
+            summary.map(|summary| u8::from(summary.trigger == RunTrigger::Scheduled).to_string()),
+        ),
+        (
+            "link_rustler_driver_restarts",
+            "Browser driver restarts during the last run.",
+            summary.map(|summary| summary.driver_restarts.to_string()),
+        ),
+        (
+            "link_rustler_pages_timed_out",
+            "Pages of the last run closed unchecked after the page timeout.",
+            summary.map(|summary| summary.timed_out.to_string()),
+        ),
+    ];
+    for (name, help, value) in plain {
+        let samples: Vec<(String, String)> = value
+            .map(|value| (String::new(), value))
+            .into_iter()
+            .collect();
+        write_family(&mut out, name, help, &samples);
+    }
+    out.push_str("# EOF\n");
+
+    out
+}
+
+/// Written next to its final path and renamed over it, the textfile collector never
+/// reads half a file.
+fn write_textfile(path: &Path, metrics: &str) -> anyhow::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp_path = path.with_file_name(temp_name);
+
+    fs::write(&temp_path, metrics)
+        .with_context(|| format!("Failed to write metrics to {temp_path:?}"))?;
+    fs::rename(&temp_path, path)
+        .with_context(|| format!("Failed to move the metrics into place at {path:?}"))
+}
+
+/// Keeps the metrics of the run in `latest` for `serve` and writes them to
+/// `config.metrics_path`. Failures are logged, a run never fails because of its metrics.
+#[instrument(skip_all)]
+pub fn export(
+    config: &Config,
+    summary: &RunSummary,
+    entries: &[ReportEntry],
+    latest: &LatestMetrics,
+) {
+    let metrics = render(Some(summary), entries);
+
+    if let Some(path) = &config.metrics_path {
+        match write_textfile(Path::new(path), &metrics) {
+            Ok(()) => info!("Metrics written to {path}"),
+            Err(e) => error!("{e:?}"),
+        }
+    }
+
+    *latest.0.lock().unwrap_or_else(PoisonError::into_inner) = metrics;
+}
+
+/// Holds the families without samples until the first run, a scrape before it still
+/// sees every series it knows.
+impl Default for LatestMetrics {
+    fn default() -> Self {
+        LatestMetrics(Arc::new(Mutex::new(render(None, &[]))))
+    }
+}
+
+/// Bound before `serve` is spawned so an address that is taken stops `--daemon` at start.
+pub async fn bind(address: SocketAddr) -> anyhow::Result<TcpListener> {
+    let listener = TcpListener::bind(address)
+        .await
+        .with_context(|| format!("Failed to listen for metrics scrapes on {address}"))?;
+    info!("Serving metrics on http://{address}/metrics");
+    Ok(listener)
+}
+
+/// Answers `GET /metrics` with what is in `latest`, until the process ends. A bare
+/// HTTP/1.1 responder is all a scraper needs.
+#[instrument(skip_all)]
+pub async fn serve(listener: TcpListener, latest: LatestMetrics) {
+    loop {
+        let (mut stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept a metrics connection: {e:?}");
+                continue;
+            }
+        };
+
+        let latest = latest.clone();
+        drop(tokio::spawn(async move {
+            // The request line is all that is looked at, it fits the first read
+            let mut request = [0; 1024];
+            let read = stream.read(&mut request).await.unwrap_or(0);
+            let request = String::from_utf8_lossy(&request[..read]);
+            let path = request.split_whitespace().nth(1).unwrap_or_default();
+
+            let response = if path == "/metrics" {
+                let metrics = latest
+                    .0
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .clone();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/openmetrics-text; \
+                    version=1.0.0; charset=utf-8\r\nContent-Length: {}\r\n\
+                    Connection: close\r\n\r\n{metrics}",
+                    metrics.len()
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                warn!("Failed to answer the metrics scrape from {peer}: {e:?}");
+            }
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        enums::CustomError,
+        report,
+        test_support::{failed_state, page_data, run_summary, state, url},
+    };
+
+    #[test]
+    fn renders_the_documented_exposition_lines() {
+        let mut summary = run_summary();
+        summary.urls_checked = 7;
+        summary.status_counts =
+            BTreeMap::from([(ReportStatus::Error, 3), (ReportStatus::Valid, 4)]);
+        summary.trigger = RunTrigger::Scheduled;
+        summary.driver_restarts = 1;
+        let page_datas = [
+            "https://a.example/one",
+            "https://a.example/two",
+            "https://b.example/",
+        ]
+        .into_iter()
+        .map(|page| {
+            let history = vec![failed_state(CustomError::ConnectionError)];
+            (url(page), page_data(page, history))
+        })
+        .chain([(
+            url("https://c.example/"),
+            page_data("https://c.example/", vec![state("<p>fine</p>")]),
+        )])
+        .collect();
+        let entries = report::classify(page_datas, &Config::default());
+
+        let expected = "\
+# HELP link_rustler_urls URLs of the last run per report table.
+# TYPE link_rustler_urls gauge
+link_rustler_urls{status=\"error\"} 3
+link_rustler_urls{status=\"unknown\"} 0
+link_rustler_urls{status=\"hash_only\"} 0
+link_rustler_urls{status=\"valid\"} 4
+link_rustler_urls{status=\"first_seen\"} 0
+link_rustler_urls{status=\"ignored\"} 0
+link_rustler_urls{status=\"not_in_source\"} 0
+# HELP link_rustler_domain_errors URLs of the last run in the error table per host.
+# TYPE link_rustler_domain_errors gauge
+link_rustler_domain_errors{domain=\"a.example\"} 2
+link_rustler_domain_errors{domain=\"b.example\"} 1
+# HELP link_rustler_urls_checked URLs the last run set out to check.
+# TYPE link_rustler_urls_checked gauge
+link_rustler_urls_checked 7
+# HELP link_rustler_run_duration_seconds Duration of the last run, 0 when it didn't finish.
+# TYPE link_rustler_run_duration_seconds gauge
+link_rustler_run_duration_seconds 90.500
+# HELP link_rustler_run_finished_timestamp_seconds When the last run ended.
+# TYPE link_rustler_run_finished_timestamp_seconds gauge
+link_rustler_run_finished_timestamp_seconds 1700000090
+# HELP link_rustler_run_cancelled Whether the last run was cancelled.
+# TYPE link_rustler_run_cancelled gauge
+link_rustler_run_cancelled 0
+# HELP link_rustler_run_scheduled Whether the scheduler started the last run.
+# TYPE link_rustler_run_scheduled gauge
+link_rustler_run_scheduled 1
+# HELP link_rustler_driver_restarts Browser driver restarts during the last run.
+# TYPE link_rustler_driver_restarts gauge
+link_rustler_driver_restarts 1
+# HELP link_rustler_pages_timed_out Pages of the last run closed unchecked after the page timeout.
+# TYPE link_rustler_pages_timed_out gauge
+link_rustler_pages_timed_out 0
+# EOF
+";
+        assert_eq!(render(Some(&summary), &entries), expected);
+    }
+
+    #[test]
+    fn an_unfinished_run_has_no_duration() {
+        let mut summary = run_summary();
+        summary.finished = None;
+        summary.cancelled = true;
+
+        let metrics = render(Some(&summary), &[]);
+        assert!(metrics.contains("\nlink_rustler_run_duration_seconds 0.000\n"));
+        assert!(metrics.contains("\nlink_rustler_run_finished_timestamp_seconds 0\n"));
+        assert!(metrics.contains("\nlink_rustler_run_cancelled 1\n"));
+        assert!(!metrics.contains("link_rustler_domain_errors{"));
+    }
+
+    #[tokio::test]
+    async fn a_scrape_before_the_first_run_gets_every_family() {
+        let listener = bind("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(tokio::spawn(serve(listener, LatestMetrics::default())));
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let families: Vec<&str> = body
+            .lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .collect();
+        assert_eq!(families.len(), 9, "{body}");
+        assert!(families.contains(&"link_rustler_urls gauge"));
+        assert!(body.lines().all(|line| line.starts_with('#')), "{body}");
+        assert!(body.ends_with("\n# EOF\n"));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape_label("a\"b\\c\nd"), r#"a\"b\\c\nd"#);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{borrow::Cow, sync::OnceLock};
+
+use regex::Regex;
+
+use crate::{
+    structs::{Config, SourceFilter},
+    utilities::hash_string,
+};
+
+/// Bump whenever `BUILTIN_RULES` changes, hashes made with other rules aren't comparable.
+const BUILTIN_VERSION: u32 = 1;
+
+/// Script and style bodies plus the usual carriers of per-request tokens.
+const BUILTIN_RULES: [(&str, &str); 4] = [
+    (r"(?is)<script\b([^>]*)>.*?</script>", "<script$1></script>"),
+    (r"(?is)<style\b([^>]*)>.*?</style>", "<style$1></style>"),
+    (
+        r"(?i)<(?:meta|input)\b[^>]*(?:csrf|xsrf|token|nonce|session)[^>]*>",
+        "",
+    ),
+    (
+        r#"(?i)\s(nonce|[\w-]*(?:csrf|xsrf|session_?id)[\w-]*)\s*=\s*(?:"[^"]*"|'[^']*'|[^\s>]+)"#,
+        r#" $1="""#,
+    ),
+];
+
+fn builtin_rules() -> &'static [(Regex, &'static str)] {
+    static RULES: OnceLock<Vec<(Regex, &'static str)>> = OnceLock::new();
+    RULES.get_or_init(|| {
+        BUILTIN_RULES
+            .iter()
+            .map(|(pattern, replacement)| {
+                (
+                    Regex::new(pattern).expect("valid builtin rule"),
+                    *replacement,
+                )
+            })
+            .collect()
+    })
+}
+
+/// Applies the configured `source_filters` and, with `normalize_source`, the builtin
+/// rules to a page source before it's hashed.
+pub fn normalize<'a>(source: &'a str, filters: &[(Regex, String)], builtin: bool) -> Cow<'a, str> {
+    let mut source = Cow::Borrowed(source);
+
+    for (pattern, replacement) in filters {
+        if let Cow::Owned(replaced) = pattern.replace_all(&source, replacement.as_str()) {
+            source = Cow::Owned(replaced);
+        }
+    }
+    if builtin {
+        for (pattern, replacement) in builtin_rules() {
+            if let Cow::Owned(replaced) = pattern.replace_all(&source, *replacement) {
+                source = Cow::Owned(replaced);
+            }
+        }
+    }
+
+    source
+}
+
+/// Elements whose content never shows up as text on the page.
+const HIDDEN_ELEMENTS: [&str; 5] = ["script", "style", "noscript", "template", "head"];
+
+fn text_rules() -> &'static [Regex; 3] {
+    static RULES: OnceLock<[Regex; 3]> = OnceLock::new();
+    RULES.get_or_init(|| {
+        let hidden = HIDDEN_ELEMENTS.join("|");
+        [
+            Regex::new(r"(?s)<!--.*?-->").expect("valid comment rule"),
+            Regex::new(&format!(r"(?is)<({hidden})\b[^>]*>.*?</({hidden})\s*>"))
+                .expect("valid hidden element rule"),
+            Regex::new(r"(?s)<[^>]*>").expect("valid tag rule"),
+        ]
+    })
+}
+
+/// Decodes the entities that commonly show up in text, others are left as they are.
+fn decode_entities(text: &str) -> String {
+    [
+        ("&nbsp;", " "),
+        ("&lt;", "<"),
+        ("&gt;", ">"),
+        ("&quot;", "\""),
+        ("&#39;", "'"),
+        ("&apos;", "'"),
+        ("&amp;", "&"),
+    ]
+    .iter()
+    .fold(text.to_string(), |text, (entity, decoded)| {
+        text.replace(entity, decoded)
+    })
+}
+
+/// The text a reader sees: comments, hidden elements and tags are dropped and every
+/// run of whitespace collapses to a single space, so markup churn doesn't change it.
+pub fn visible_text(source: &str) -> String {
+    let [comments, hidden, tags] = text_rules();
+    let text = comments.replace_all(source, "");
+    let text = hidden.replace_all(&text, " ");
+    let text = tags.replace_all(&text, " ");
+
+    decode_entities(&text)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn version_of(filters: &[SourceFilter], builtin: bool) -> String {
+    let mut description = filters
+        .iter()
+        .map(|filter| format!("{}\u{0}{}", filter.pattern, filter.replacement))
+        .collect::<Vec<String>>()
+        .join("\u{1}");
+    if builtin {
+        description.push_str(&format!("\u{2}builtin-{BUILTIN_VERSION}"));
+    }
+
+    hash_string(&description)[..12].to_string()
+}
+
+/// Identifies the normalization a page hash was made with, recorded in every state.
+pub fn filter_version(config: &Config) -> String {
+    version_of(&config.source_filters, config.normalize_source)
+}
+
+/// Version of the states from before it was recorded, they were all made with the one
+/// filter that's now the default.
+pub fn legacy_filter_version() -> String {
+    version_of(&Config::default().source_filters, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same page as served twice, only the per-request values differ.
+    fn noisy_page(token: &str, time: &str) -> String {
+        format!(
+            r#"<html class="js idc0_343"><head>
+<meta name="csrf-token" content="{token}">
+<script nonce="{token}">window.renderedAt = "{time}";</script>
+<style nonce='{token}'>.banner::after {{ content: "{time}"; }}</style>
+</head><body data-sessionid={token}>
+<form><input type="hidden" name="authenticity_token" value="{token}"><button>Go</button></form>
+<p data-csrf-token="{token}">Opening hours: 9 to 5</p>
+</body></html>"#
+        )
+    }
+
+    fn page_hash(source: &str, config: &Config) -> String {
+        let filters = config.source_filters().unwrap();
+        hash_string(&normalize(source, &filters, config.normalize_source).into_owned())
+    }
+
+    fn builtin() -> Config {
+        Config {
+            normalize_source: true,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn tokens_and_timestamps_do_not_change_the_hash() {
+        let first = noisy_page("a1b2c3", "2024-05-01T12:00:00Z");
+        let second = noisy_page("z9y8x7", "2024-05-02T08:30:00Z");
+
+        assert_eq!(
+            page_hash(&first, &builtin()),
+            page_hash(&second, &builtin())
+        );
+        assert_ne!(
+            page_hash(&first, &Config::default()),
+            page_hash(&second, &Config::default())
+        );
+        // What the page says still counts
+        let changed = first.replace("9 to 5", "10 to 4");
+        assert_ne!(
+            page_hash(&first, &builtin()),
+            page_hash(&changed, &builtin())
+        );
+    }
+
+    #[test]
+    fn the_builtin_rules_keep_the_markup_around_the_noise() {
+        let normalized = normalize(&noisy_page("a1b2c3", "12:00"), &[], true).into_owned();
+        assert_eq!(
+            normalized,
+            r#"<html class="js idc0_343"><head>
+
+<script nonce=""></script>
+<style nonce=""></style>
+</head><body data-sessionid="">
+<form><button>Go</button></form>
+<p data-csrf-token="">Opening hours: 9 to 5</p>
+</body></html>"#
+        );
+    }
+
+    #[test]
+    fn configured_filters_apply_before_the_builtin_rules() {
+        let config = Config {
+            source_filters: vec![SourceFilter {
+                pattern: r"Generated at \d{2}:\d{2}".to_string(),
+                replacement: "Generated".to_string(),
+            }],
+            ..Config::default()
+        };
+        assert_eq!(
+            page_hash("<p>Generated at 12:00</p>", &config),
+            page_hash("<p>Generated at 13:45</p>", &config)
+        );
+
+        // The default filter drops the classes the cookies extension adds
+        assert_eq!(
+            page_hash(
+                r#"<body class="page cookies idc0_343">"#,
+                &Config::default()
+            ),
+            page_hash(r#"<body class="page">"#, &Config::default())
+        );
+    }
+
+    fn text_hash(source: &str) -> String {
+        hash_string(&visible_text(source))
+    }
+
+    #[test]
+    fn the_same_text_in_other_markup_hashes_equal() {
+        let before = r#"<html><head><title>Opening hours</title>
+<script src="/bundle.3f9a1c.js"></script></head>
+<body><div class="wrapper"><h1>Opening hours</h1><p>Monday&nbsp;to Friday, 9 to 5</p></div>
+<!-- rendered by node 7 --></body></html>"#;
+        let after = r#"<html><head><title>Opening hours</title>
+<script src="/bundle.b72e04.js"></script><style>h1 { color: red; }</style></head>
+<body><main>
+  <h1 class="title">Opening   hours</h1>
+  <section><p class="hours">Monday&nbsp;to Friday,
+    9 to 5</p></section>
+</main><noscript>Enable JavaScript</noscript><template><p>Hidden</p></template></body></html>"#;
+
+        assert_eq!(
+            visible_text(before),
+            "Opening hours Monday to Friday, 9 to 5"
+        );
+        assert_eq!(text_hash(before), text_hash(after));
+        assert_eq!(
+            text_hash("<p>Tom &amp; Jerry</p>"),
+            text_hash("<div>\n  Tom & Jerry\n</div>")
+        );
+    }
+
+    #[test]
+    fn different_text_hashes_differ() {
+        assert_ne!(
+            text_hash("<p>Open 9 to 5</p>"),
+            text_hash("<p>Open 10 to 4</p>")
+        );
+        assert_ne!(text_hash("<p>Open</p>"), text_hash("<p>Closed</p>"));
+        assert_eq!(visible_text("<p>&lt;b&gt; is bold</p>"), "<b> is bold");
+    }
+
+    #[test]
+    fn the_filter_version_follows_the_filters() {
+        assert_eq!(filter_version(&Config::default()), legacy_filter_version());
+        assert_ne!(filter_version(&builtin()), legacy_filter_version());
+        let changed = Config {
+            source_filters: vec![],
+            ..Config::default()
+        };
+        assert_ne!(filter_version(&changed), legacy_filter_version());
+        // Recorded in stored states, it may only change along with the rules
+        assert_eq!(legacy_filter_version(), "f0fa14596e92");
+    }
+}
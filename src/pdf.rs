@@ -1,91 +1,628 @@
-use std::{collections::HashSet, fs::File, io::Read, path::Path};
+use anyhow::{anyhow, Context};
+use lopdf::{encryption, Dictionary, Document, Object, ObjectId};
+use reqwest::Url;
+use tracing::{error, info, warn};
 
-use anyhow::Context;
-use reqwest::{Client, Url};
-use tracing::{info, instrument};
+use crate::{enums::PdfEngine, guard, structs::LinkExtraction};
 
-#[instrument]
-pub async fn get_pdf_github(url: Url) -> anyhow::Result<String> {
-    let client = Client::new();
+/// Anything longer is not a link anyone typed, it is a malformed or hostile document.
+pub const MAX_URI_LEN: usize = 8 * 1024;
 
-    let split_path = url.path().split('/').collect::<Vec<&str>>();
+/// Reads a PDF literal string, `bytes` starts right after the opening parenthesis.
+/// Balanced parentheses are part of the string, escaped ones never open or close it.
+/// Handles every escape from the spec, including octal codes and backslash line
+/// continuations that Word likes to split long URIs with.
+fn read_literal_string(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut depth = 0;
+    let mut iter = bytes.iter().peekable();
 
-    let repo_owner = split_path[1];
-    let repo_name = split_path[2];
-    let branch = split_path[4];
-    let file_path = split_path[5..].join("/");
+    while let Some(&byte) = iter.next() {
+        match byte {
+            b'\\' => match iter.next() {
+                Some(&escaped @ (b'(' | b')' | b'\\')) => out.push(escaped),
+                Some(&b'n') => out.push(b'\n'),
+                Some(&b'r') => out.push(b'\r'),
+                Some(&b't') => out.push(b'\t'),
+                Some(&b'b') => out.push(0x08),
+                Some(&b'f') => out.push(0x0c),
+                Some(&b'\r') => {
+                    let _ = iter.next_if_eq(&&b'\n');
+                }
+                Some(&b'\n') => {}
+                Some(&digit @ b'0'..=b'7') => {
+                    let mut code = u32::from(digit - b'0');
+                    for _ in 0..2 {
+                        match iter.next_if(|next| (b'0'..=b'7').contains(*next)) {
+                            Some(&next) => code = code * 8 + u32::from(next - b'0'),
+                            None => break,
+                        }
+                    }
+                    // High order overflow is ignored per the spec
+                    out.push((code & 0xff) as u8);
+                }
+                // Unknown escapes drop the backslash
+                Some(&other) => out.push(other),
+                None => return None,
+            },
+            b'(' => {
+                depth += 1;
+                out.push(byte);
+            }
+            b')' if depth == 0 => return Some(out),
+            b')' => {
+                depth -= 1;
+                out.push(byte);
+            }
+            _ => out.push(byte),
+        }
 
-    let pdf_url = format!("https://github.com/{repo_owner}/{repo_name}/raw/{branch}/{file_path}");
+        if out.len() > MAX_URI_LEN {
+            return None;
+        }
+    }
+
+    None
+}
 
-    let pdf = client
-        .get(&pdf_url)
-        .send()
-        .await
-        .context("Failed to download PDF")?
-        .text()
-        .await
-        .context("Failed to read PDF content")?;
+/// Reads a PDF hex string, `bytes` starts right after the opening angle bracket.
+/// Whitespace is ignored and an odd final digit is padded with zero.
+fn read_hex_string(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut digits = Vec::new();
 
-    info!("PDF fetched successfully from: {}", pdf_url);
+    for &byte in bytes {
+        match byte {
+            b'>' => {
+                if digits.len() % 2 == 1 {
+                    digits.push(0);
+                }
+                return Some(
+                    digits
+                        .chunks(2)
+                        .map(|pair| pair[0] << 4 | pair[1])
+                        .collect(),
+                );
+            }
+            byte if byte.is_ascii_whitespace() => {}
+            byte => digits.push(char::from(byte).to_digit(16)? as u8),
+        }
+
+        if digits.len() / 2 > MAX_URI_LEN {
+            return None;
+        }
+    }
 
-    Ok(pdf)
+    None
 }
 
-pub fn pdf_contents(pdf_path: &str) -> anyhow::Result<Vec<u8>> {
-    let path = Path::new(pdf_path);
-    let mut buf = Vec::new();
+pub fn get_unique_links(pdf: &[u8]) -> LinkExtraction {
+    let re_bytes = regex::bytes::Regex::new(r"/S\s*/URI\s*/URI\s*([(<])").unwrap();
+    let mut extraction = LinkExtraction::default();
+
+    for captures in re_bytes.captures_iter(pdf) {
+        let delimiter = captures.get(1).unwrap();
+        let rest = &pdf[delimiter.end()..];
+        let raw = if delimiter.as_bytes() == b"(" {
+            read_literal_string(rest)
+        } else {
+            read_hex_string(rest)
+        };
+
+        record_candidate(&mut extraction, raw);
+    }
+
+    extraction
+}
 
-    let mut file = File::open(path).context(format!("Failed to open PDF file: {pdf_path}"))?;
+pub fn record_candidate(extraction: &mut LinkExtraction, raw: Option<Vec<u8>>) {
+    let Some(raw) = raw else {
+        extraction.too_long += 1;
+        return;
+    };
 
-    let _ = file
-        .read_to_end(&mut buf)
-        .context(format!("Failed to read PDF file: {pdf_path}"))?;
+    let Ok(decoded) = std::str::from_utf8(&raw) else {
+        warn!(
+            "Skipping undecodable link: {:.100}",
+            String::from_utf8_lossy(&raw)
+        );
+        extraction.undecodable += 1;
+        return;
+    };
 
-    info!("PDF contents read successfully from: {}", pdf_path);
-    Ok(buf)
+    match Url::parse(decoded.trim()) {
+        Ok(url) => {
+            let _ = extraction.urls.insert(url);
+        }
+        Err(_) => extraction.unparsable += 1,
+    }
 }
 
-pub fn get_unique_links(pdf: &[u8]) -> HashSet<Url> {
-    let re_bytes = regex::bytes::Regex::new(r"/Type/Action/S/URI/URI\((.*?)\)").unwrap();
-    let raw_links: HashSet<Url> = re_bytes
-        .captures_iter(pdf)
-        .map(|capture| {
-            std::str::from_utf8(capture.get(1).unwrap().as_bytes()).expect("Invalid UTF-8")
-        })
-        .map(Url::parse)
-        .filter_map(Result::ok)
-        .collect();
-    raw_links
+/// Collects the target of every `/S /URI` action reachable from `object`, which covers
+/// link annotations whether the action is inline or a separate object. `id` is the
+/// object `object` is part of, an encrypted string is decrypted with its `key`.
+fn collect_uri_actions(
+    document: &Document,
+    key: Option<&[u8]>,
+    id: ObjectId,
+    object: &Object,
+    extraction: &mut LinkExtraction,
+) {
+    match object {
+        Object::Dictionary(dict) => collect_from_dict(document, key, id, dict, extraction),
+        Object::Stream(stream) => collect_from_dict(document, key, id, &stream.dict, extraction),
+        Object::Array(items) => {
+            for item in items {
+                collect_uri_actions(document, key, id, item, extraction);
+            }
+        }
+        _ => {}
+    }
 }
 
-pub async fn get_urls(
-    pdf_path: Option<String>,
-    external_source_url: Option<Url>,
-    given_urls: Option<Vec<String>>,
-) -> anyhow::Result<HashSet<Url>> {
-    let urls_to_check: HashSet<Url> = if let Some(given_urls) = given_urls {
-        given_urls
-            .iter()
-            .map(|url| Url::parse(url))
-            .filter_map(Result::ok)
-            .collect()
-    } else if let Some(pdf_path) = pdf_path {
-        let pdf = pdf_contents(&pdf_path)?;
-        get_unique_links(&pdf)
+fn collect_from_dict(
+    document: &Document,
+    key: Option<&[u8]>,
+    id: ObjectId,
+    dict: &Dictionary,
+    extraction: &mut LinkExtraction,
+) {
+    let is_uri_action = dict
+        .get(b"S")
+        .and_then(Object::as_name)
+        .is_ok_and(|name| name == b"URI");
+
+    if is_uri_action {
+        let target = dict.get(b"URI").ok().and_then(|target| match target {
+            Object::Reference(target_id) => document
+                .get_object(*target_id)
+                .ok()
+                .map(|target| (*target_id, target)),
+            target => Some((id, target)),
+        });
+        if let Some((owner, target @ Object::String(raw, _))) = target {
+            let raw = match key {
+                Some(key) => encryption::decrypt_object(key, owner, target).ok(),
+                None => Some(raw.clone()),
+            };
+            record_candidate(extraction, raw.filter(|raw| raw.len() <= MAX_URI_LEN));
+        }
+    }
+
+    for (_, value) in dict.iter() {
+        collect_uri_actions(document, key, id, value, extraction);
+    }
+}
+
+/// Parses the document structure, unlike the regex this sees links inside compressed
+/// object streams. Encrypted documents are read when they open without a password,
+/// which is how documents that only restrict editing or printing are saved.
+pub fn parse_unique_links(pdf: &[u8]) -> anyhow::Result<LinkExtraction> {
+    // lopdf panics on some malformed documents rather than returning an error
+    let document = guard::recover(
+        "PDF parser",
+        || Document::load_mem(pdf),
+        |details| warn!("{details}"),
+    )
+    .context("The PDF parser panicked")?
+    .context("Failed to parse PDF")?;
+    let key = if document.is_encrypted() {
+        let key = encryption::get_encryption_key(&document, "", true)
+            .map_err(|err| anyhow!("The PDF is encrypted and needs a password: {err:?}"))?;
+        Some(key)
     } else {
-        let pdf = get_pdf_github(external_source_url.unwrap())
-            .await
-            .context("Failed to fetch PDF from GitHub")?
-            .as_bytes()
-            .to_vec();
-        get_unique_links(&pdf)
+        None
     };
+    let mut extraction = LinkExtraction::default();
+
+    for (&id, object) in &document.objects {
+        collect_uri_actions(&document, key.as_deref(), id, object, &mut extraction);
+    }
+
+    Ok(extraction)
+}
+
+pub fn extract_links(pdf: &[u8], engine: PdfEngine) -> LinkExtraction {
+    if engine != PdfEngine::Regex {
+        match parse_unique_links(pdf) {
+            Ok(extraction) => {
+                info!("PDF parser found {} links", extraction.urls.len());
+                return extraction;
+            }
+            Err(err) if engine == PdfEngine::Parser => {
+                error!("PDF parser failed and the regex fallback is disabled: {err:?}");
+                return LinkExtraction::default();
+            }
+            Err(err) => warn!("PDF parser failed, falling back to the regex: {err:?}"),
+        }
+    }
+
+    let extraction = get_unique_links(pdf);
+    info!("Regex found {} links", extraction.urls.len());
+    extraction
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io::Write};
+
+    use flate2::{write::ZlibEncoder, Compression};
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn escape_literal(bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        for &byte in bytes {
+            if matches!(byte, b'(' | b')' | b'\\') {
+                out.push(b'\\');
+            }
+            out.push(byte);
+        }
+        out.push(b')');
+        out
+    }
+
+    proptest! {
+        #[test]
+        fn literal_strings_read_back(
+            bytes in prop::collection::vec(any::<u8>(), 0..512),
+            rest in prop::collection::vec(any::<u8>(), 0..16),
+        ) {
+            let mut encoded = escape_literal(&bytes);
+            encoded.extend(rest);
+            prop_assert_eq!(read_literal_string(&encoded), Some(bytes));
+        }
+
+        #[test]
+        fn octal_escapes_read_back(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+            let mut encoded: Vec<u8> = bytes
+                .iter()
+                .flat_map(|byte| format!("\\{byte:03o}").into_bytes())
+                .collect();
+            encoded.push(b')');
+            prop_assert_eq!(read_literal_string(&encoded), Some(bytes));
+        }
+
+        #[test]
+        fn hex_strings_read_back(
+            bytes in prop::collection::vec(any::<u8>(), 0..512),
+            upper in any::<bool>(),
+            spaced in any::<bool>(),
+        ) {
+            let mut encoded = String::new();
+            for byte in &bytes {
+                encoded.push_str(&if upper { format!("{byte:02X}") } else { format!("{byte:02x}") });
+                if spaced {
+                    encoded.push_str(" \n");
+                }
+            }
+            encoded.push('>');
+            prop_assert_eq!(read_hex_string(encoded.as_bytes()), Some(bytes));
+        }
+
+        #[test]
+        fn decoders_stay_within_the_length_limit(bytes in prop::collection::vec(any::<u8>(), 0..4096)) {
+            for decoded in [read_literal_string(&bytes), read_hex_string(&bytes)].into_iter().flatten() {
+                prop_assert!(decoded.len() <= MAX_URI_LEN);
+            }
+        }
 
-    if urls_to_check.is_empty() {
-        anyhow::bail!("No links found in PDF");
+        #[test]
+        fn every_regex_candidate_is_counted(
+            targets in prop::collection::vec(prop::collection::vec(any::<u8>(), 0..64), 0..8),
+        ) {
+            let mut pdf = b"%PDF-1.4\n".to_vec();
+            for target in &targets {
+                pdf.extend_from_slice(b"<< /S /URI /URI (");
+                pdf.extend(escape_literal(target));
+                pdf.extend_from_slice(b" >>\n");
+            }
+
+            let mut expected = LinkExtraction::default();
+            for target in &targets {
+                record_candidate(&mut expected, Some(target.clone()));
+            }
+
+            let extraction = get_unique_links(&pdf);
+            prop_assert_eq!(extraction.urls, expected.urls);
+            prop_assert_eq!(extraction.undecodable, expected.undecodable);
+            prop_assert_eq!(extraction.unparsable, expected.unparsable);
+            prop_assert_eq!(extraction.too_long, 0);
+        }
+    }
+
+    fn urls(extraction: &LinkExtraction) -> Vec<&str> {
+        let mut urls: Vec<&str> = extraction.urls.iter().map(Url::as_str).collect();
+        urls.sort_unstable();
+        urls
+    }
+
+    /// Link annotations the way Word and other exporters write them, one per escape form.
+    const WORD_EXPORT: &[u8] = br"%PDF-1.7
+12 0 obj
+<</Type/Action/S/URI/URI(https://example.com/a\(b\)c)>>
+endobj
+13 0 obj
+<</Type/Action/S/URI/URI(https://example.com/wiki/Rust_\(language\))>>
+endobj
+14 0 obj
+<</Type/Action/S/URI/URI(https://example.com/very/long/\
+path/split/by/word)>>
+endobj
+15 0 obj
+<</Type/Action/S/URI/URI(https://example.com/back\\slash)>>
+endobj
+16 0 obj
+<</Type/Action/S/URI/URI(https://example.com/\157ctal)>>
+endobj
+17 0 obj
+<</Type/Action/S /URI /URI <68747470733A2F2F6578616D706C652E636F6D2F6
+8 6578>>>
+endobj
+18 0 obj
+<</Type/Action/S/URI/URI(https://example.com/(balanced))>>
+endobj
+";
+
+    #[test]
+    fn every_escape_form_of_a_word_export_is_read() {
+        let extraction = get_unique_links(WORD_EXPORT);
+        assert_eq!(
+            urls(&extraction),
+            [
+                "https://example.com/(balanced)",
+                "https://example.com/a(b)c",
+                "https://example.com/back/slash",
+                "https://example.com/hex",
+                "https://example.com/octal",
+                "https://example.com/very/long/path/split/by/word",
+                "https://example.com/wiki/Rust_(language)",
+            ]
+        );
+        assert_eq!(
+            (
+                extraction.undecodable,
+                extraction.too_long,
+                extraction.unparsable
+            ),
+            (0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn bad_candidates_are_counted_not_fatal() {
+        let extraction = get_unique_links(
+            br"<</S/URI/URI(https://example.com/\377\376)>>
+<</S/URI/URI(not a url)>>
+<</S/URI/URI<6G>>>
+<</S/URI/URI(https://example.com/ok)>>
+<</S/URI/URI(https://example.com/never/closed",
+        );
+        assert_eq!(urls(&extraction), ["https://example.com/ok"]);
+        assert_eq!(extraction.undecodable, 1);
+        assert_eq!(extraction.unparsable, 1);
+        assert_eq!(extraction.too_long, 2);
+    }
+
+    /// Lays `objects` out as a PDF file with a valid cross-reference table.
+    fn pdf_file(objects: &[(u32, Vec<u8>)], trailer: &str) -> Vec<u8> {
+        let mut file = b"%PDF-1.5\n".to_vec();
+        let mut offsets = BTreeMap::new();
+        for (number, body) in objects {
+            offsets.insert(*number, file.len());
+            file.extend(format!("{number} 0 obj\n").into_bytes());
+            file.extend(body);
+            file.extend(b"\nendobj\n");
+        }
+
+        let size = offsets.keys().max().map_or(1, |max| max + 1);
+        let startxref = file.len();
+        file.extend(format!("xref\n0 {size}\n0000000000 65535 f \n").into_bytes());
+        for number in 1..size {
+            file.extend(match offsets.get(&number) {
+                Some(offset) => format!("{offset:010} 00000 n \n").into_bytes(),
+                None => b"0000000000 65535 f \n".to_vec(),
+            });
+        }
+        file.extend(
+            format!("trailer\n<< /Size {size} /Root 1 0 R {trailer} >>\nstartxref\n{startxref}\n%%EOF\n")
+                .into_bytes(),
+        );
+        file
+    }
+
+    /// A catalog and one page with the link annotations `annots`.
+    fn one_page(annots: &str) -> Vec<(u32, Vec<u8>)> {
+        vec![
+            (1, b"<< /Type /Catalog /Pages 2 0 R >>".to_vec()),
+            (2, b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_vec()),
+            (
+                3,
+                format!(
+                    "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Annots [{annots}] >>"
+                )
+                .into_bytes(),
+            ),
+        ]
+    }
+
+    fn link_annotation(action: &str) -> Vec<u8> {
+        format!("<< /Type /Annot /Subtype /Link /Rect [72 700 200 720] /A {action} >>").into_bytes()
+    }
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{byte:02x}")).collect()
     }
 
-    info!("Total number of links: {:?}", urls_to_check.len());
+    #[test]
+    fn the_parser_follows_annotations_to_indirect_actions() {
+        let mut objects = one_page("4 0 R 5 0 R");
+        objects.extend([
+            (
+                4,
+                link_annotation("<< /S /URI /URI (https://example.com/inline) >>"),
+            ),
+            (5, link_annotation("6 0 R")),
+            (6, b"<< /S /URI /URI 7 0 R >>".to_vec()),
+            (7, b"(https://example.com/indirect)".to_vec()),
+        ]);
+        let pdf = pdf_file(&objects, "");
+
+        assert_eq!(
+            urls(&parse_unique_links(&pdf).unwrap()),
+            ["https://example.com/indirect", "https://example.com/inline"]
+        );
+        // The regex only sees the string written right after the action
+        assert_eq!(
+            urls(&get_unique_links(&pdf)),
+            ["https://example.com/inline"]
+        );
+    }
 
-    Ok(urls_to_check)
+    #[test]
+    fn the_parser_reads_compressed_object_streams() {
+        let annotation = link_annotation("<< /S /URI /URI (https://example.com/compressed) >>");
+        let header = "4 0 ";
+        let mut content = header.as_bytes().to_vec();
+        content.extend(&annotation);
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut object_stream = format!(
+            "<< /Type /ObjStm /N 1 /First {} /Filter /FlateDecode /Length {} >>\nstream\n",
+            header.len(),
+            compressed.len()
+        )
+        .into_bytes();
+        object_stream.extend(compressed);
+        object_stream.extend(b"\nendstream");
+        let mut objects = one_page("4 0 R");
+        objects.push((5, object_stream));
+        let pdf = pdf_file(&objects, "");
+
+        assert_eq!(urls(&get_unique_links(&pdf)), Vec::<&str>::new());
+        assert_eq!(
+            urls(&parse_unique_links(&pdf).unwrap()),
+            ["https://example.com/compressed"]
+        );
+        assert_eq!(
+            urls(&extract_links(&pdf, PdfEngine::Auto)),
+            ["https://example.com/compressed"]
+        );
+    }
+
+    /// RC4 is its own inverse, encrypting is decrypting.
+    fn rc4(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut state: Vec<u8> = (0..=255).collect();
+        let mut j = 0u8;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, usize::from(j));
+        }
+        let (mut i, mut j) = (0u8, 0u8);
+        data.iter()
+            .map(|byte| {
+                i = i.wrapping_add(1);
+                j = j.wrapping_add(state[usize::from(i)]);
+                state.swap(usize::from(i), usize::from(j));
+                byte ^ state[usize::from(state[usize::from(i)].wrapping_add(state[usize::from(j)]))]
+            })
+            .collect()
+    }
+
+    /// An RC4 40-bit document that opens without a password, `user_check` replaces the
+    /// value that proves the empty password is the right one.
+    fn encrypted_pdf(user_check: Option<&[u8]>) -> Vec<u8> {
+        const PADDING: [u8; 32] = [
+            0x28, 0xbf, 0x4e, 0x5e, 0x4e, 0x75, 0x8a, 0x41, 0x64, 0x00, 0x4e, 0x56, 0xff, 0xfa,
+            0x01, 0x08, 0x2e, 0x2e, 0x00, 0xb6, 0xd0, 0x68, 0x3e, 0x80, 0x2f, 0x0c, 0xa9, 0xfe,
+            0x64, 0x53, 0x69, 0x7a,
+        ];
+        let build = |uri: &[u8], user: &[u8]| {
+            let mut objects = one_page("4 0 R");
+            objects.extend([
+                (
+                    4,
+                    link_annotation(&format!("<< /S /URI /URI <{}> >>", hex(uri))),
+                ),
+                (
+                    8,
+                    format!(
+                        "<< /Filter /Standard /V 1 /R 2 /O <{}> /U <{}> /P -4 >>",
+                        hex(&[0x11; 32]),
+                        hex(user)
+                    )
+                    .into_bytes(),
+                ),
+            ]);
+            pdf_file(
+                &objects,
+                "/Encrypt 8 0 R /ID [<0123456789abcdef> <0123456789abcdef>]",
+            )
+        };
+
+        // The key only depends on the password, O, P and the ID
+        let draft = Document::load_mem(&build(b"", &[0; 32])).unwrap();
+        let key = encryption::get_encryption_key(&draft, "", false).unwrap();
+        let plain = Object::string_literal("https://example.com/encrypted");
+        let uri = encryption::decrypt_object(&key, (4, 0), &plain).unwrap();
+        let user = user_check.map_or_else(|| rc4(&key, &PADDING), <[u8]>::to_vec);
+        build(&uri, &user)
+    }
+
+    #[test]
+    fn the_parser_decrypts_documents_without_a_password() {
+        let pdf = encrypted_pdf(None);
+        assert_eq!(
+            urls(&parse_unique_links(&pdf).unwrap()),
+            ["https://example.com/encrypted"]
+        );
+        // The regex can only see the encrypted bytes
+        assert!(!get_unique_links(&pdf)
+            .urls
+            .iter()
+            .any(|url| url.as_str() == "https://example.com/encrypted"));
+    }
+
+    #[test]
+    fn a_document_that_needs_a_password_falls_back_to_the_regex() {
+        let pdf = encrypted_pdf(Some(&[0x42; 32]));
+        let err = parse_unique_links(&pdf).unwrap_err();
+        assert!(err.to_string().contains("needs a password"), "{err:?}");
+        assert_eq!(
+            urls(&extract_links(&pdf, PdfEngine::Parser)),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn whatever_the_parser_rejects_goes_to_the_regex() {
+        let fragment = b"not a PDF << /S /URI /URI (https://example.com/fragment) >>";
+        assert!(parse_unique_links(fragment).is_err());
+        assert_eq!(
+            urls(&extract_links(fragment, PdfEngine::Auto)),
+            ["https://example.com/fragment"]
+        );
+        assert_eq!(
+            urls(&extract_links(fragment, PdfEngine::Parser)),
+            Vec::<&str>::new()
+        );
+        assert_eq!(
+            urls(&extract_links(fragment, PdfEngine::Regex)),
+            ["https://example.com/fragment"]
+        );
+    }
+
+    #[test]
+    fn long_and_unterminated_strings_are_rejected() {
+        assert_eq!(read_literal_string(b"no closing parenthesis"), None);
+        assert_eq!(read_literal_string(b"dangling escape\\"), None);
+        assert_eq!(read_hex_string(b"4142"), None);
+        assert_eq!(read_hex_string(b"41zz>"), None);
+        assert_eq!(read_hex_string(b"414>"), Some(b"A@".to_vec()));
+
+        let long = vec![b'a'; MAX_URI_LEN + 1];
+        assert_eq!(read_literal_string(&escape_literal(&long)), None);
+    }
 }
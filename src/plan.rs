@@ -0,0 +1,660 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashSet},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+use tracing::{info, warn};
+use url::Url;
+
+use crate::{
+    canonical,
+    enums::{CustomError, LinkType, TimeoutPolicy, UrlFilter},
+    report::registrable_domain,
+    structs::{
+        ActivePages, Config, DwellOverride, LinkExtraction, PageData, RunPlan, RunPreview,
+        ScreenshotMask, State, SweepState, Tier,
+    },
+    utilities::{pattern_specificity, url_filter_matches, url_matches},
+};
+
+pub const UNTIERED: &str = "untiered";
+
+/// The most specific matching pattern decides the tier, ties go to the tier listed first.
+pub fn assign_tier<'a>(url: &Url, tiers: &'a [Tier]) -> Option<&'a Tier> {
+    let mut best: Option<(&Tier, (bool, usize))> = None;
+
+    for tier in tiers {
+        for pattern in &tier.patterns {
+            if !url_matches(pattern, url) {
+                continue;
+            }
+
+            let specificity = pattern_specificity(pattern);
+            if best.is_none_or(|(_, best_specificity)| specificity > best_specificity) {
+                best = Some((tier, specificity));
+            }
+        }
+    }
+
+    best.map(|(tier, _)| tier)
+}
+
+pub fn tier_name(url: &Url, tiers: &[Tier]) -> String {
+    assign_tier(url, tiers).map_or(UNTIERED.to_string(), |tier| tier.name.clone())
+}
+
+/// Resuming skips anything inside the resume window regardless of tier, otherwise the
+/// tier's recheck interval applies, falling back to the global one.
+fn is_fresh(page_data: &PageData, tier: Option<&Tier>, config: &Config) -> bool {
+    let age = chrono::Utc::now()
+        .signed_duration_since(page_data.last_checked)
+        .to_std();
+    // A check time in the future counts as fresh
+    let checked_within = |window: Duration| age.as_ref().map_or(true, |age| *age < window);
+
+    if config.resume && checked_within(config.resume_window) {
+        return true;
+    }
+
+    tier.and_then(|tier| tier.recheck_after)
+        .or(config.recheck_after)
+        .is_some_and(checked_within)
+}
+
+/// The include patterns narrow the set first, the exclude patterns then filter what is
+/// left. Returns why the URL was filtered out.
+fn filter_reason(url: &Url, include: &[UrlFilter], exclude: &[UrlFilter]) -> Option<String> {
+    if !include.is_empty() && !include.iter().any(|filter| url_filter_matches(filter, url)) {
+        return Some("Not matched by any include pattern".to_string());
+    }
+
+    exclude
+        .iter()
+        .find(|filter| url_filter_matches(filter, url))
+        .map(|filter| match filter {
+            UrlFilter::Glob(pattern) => format!("Excluded by {pattern}"),
+            UrlFilter::Regex(regex) => format!("Excluded by re:{}", regex.as_str()),
+        })
+}
+
+pub fn plan_run(
+    urls: HashSet<Url>,
+    page_datas: &BTreeMap<Url, PageData>,
+    config: &Config,
+) -> anyhow::Result<RunPlan> {
+    let (include, exclude) = config.url_filters()?;
+    let mut plan = RunPlan {
+        to_check: HashSet::new(),
+        filtered: BTreeMap::new(),
+        skipped: 0,
+        tier_counts: BTreeMap::new(),
+    };
+
+    for url in urls {
+        if let Some(reason) = filter_reason(&url, &include, &exclude) {
+            let _ = plan.filtered.insert(url, reason);
+            continue;
+        }
+
+        if page_datas
+            .get(&url)
+            .is_some_and(|page_data| page_data.ignore.skip)
+        {
+            let _ = plan.filtered.insert(url, "Ignored".to_string());
+            continue;
+        }
+
+        let tier = assign_tier(&url, &config.tiers);
+
+        if page_datas
+            .get(&url)
+            .is_some_and(|page_data| is_fresh(page_data, tier, config))
+        {
+            plan.skipped += 1;
+            continue;
+        }
+
+        let name = tier.map_or(UNTIERED.to_string(), |tier| tier.name.clone());
+        *plan.tier_counts.entry(name).or_insert(0) += 1;
+        let _ = plan.to_check.insert(url);
+    }
+
+    for (tier, count) in &plan.tier_counts {
+        info!("Tier {tier} contributed {count} URLs to this run");
+    }
+    info!("Filtered out {} URLs", plan.filtered.len());
+    info!("Skipped {} recently checked URLs", plan.skipped);
+
+    Ok(plan)
+}
+
+/// Plans a run and groups what it would check by link type. Classifies by the URL
+/// alone, extensionless content links only show up as such once a run asks the server.
+/// `extraction` is what the source gave, its `urls` after `canonical::group` are `urls`.
+pub fn preview_run(
+    urls: HashSet<Url>,
+    extraction: &LinkExtraction,
+    page_datas: &BTreeMap<Url, PageData>,
+    config: &Config,
+) -> anyhow::Result<RunPreview> {
+    let plan = plan_run(urls, page_datas, config)?;
+
+    let mut by_type: BTreeMap<LinkType, Vec<(Url, bool)>> = BTreeMap::new();
+    for url in plan.to_check {
+        let link_type = crate::checker::check_link_type(&url, config)?;
+        let known = page_datas.contains_key(&url);
+        by_type.entry(link_type).or_default().push((url, known));
+    }
+    for urls in by_type.values_mut() {
+        urls.sort();
+    }
+
+    Ok(RunPreview {
+        by_type,
+        filtered: plan.filtered,
+        skipped: plan.skipped,
+        undecodable: extraction.undecodable,
+        too_long: extraction.too_long,
+        unparsable: extraction.unparsable,
+    })
+}
+
+/// Whether a crawl follows `link` found on `parent`: it has to match `crawl_allowlist`,
+/// or be on the same domain when that's empty, and get past the include/exclude patterns.
+fn crawl_allowed(
+    parent: &Url,
+    link: &Url,
+    filters: &(Vec<UrlFilter>, Vec<UrlFilter>),
+    config: &Config,
+) -> bool {
+    let allowed = if config.crawl_allowlist.is_empty() {
+        registrable_domain(link) == registrable_domain(parent)
+    } else {
+        config
+            .crawl_allowlist
+            .iter()
+            .any(|pattern| url_matches(pattern, link))
+    };
+
+    allowed && filter_reason(link, &filters.0, &filters.1).is_none()
+}
+
+/// Queues the links found on `parent` that the crawl follows and haven't been seen in
+/// this pass, until `crawl_max_pages` is reached. Returns every followed link that
+/// isn't a source URL, whether or not it was new, so all of its parents get recorded.
+pub fn crawl(parent: &Url, links: Vec<Url>, pass: &mut SweepState, config: &Config) -> Vec<Url> {
+    let Some(&depth) = pass.depths.get(parent) else {
+        return vec![];
+    };
+    if depth >= config.crawl_depth {
+        return vec![];
+    }
+    let filters = match config.url_filters() {
+        Ok(filters) => filters,
+        Err(err) => {
+            warn!("Not crawling {parent}: {err:#}");
+            return vec![];
+        }
+    };
+
+    let followed: BTreeSet<Url> = links
+        .iter()
+        .filter(|link| matches!(link.scheme(), "http" | "https"))
+        .map(|link| canonical::canonicalize(link, config))
+        .filter(|link| link != parent && crawl_allowed(parent, link, &filters, config))
+        .collect();
+
+    for link in &followed {
+        if pass.depths.contains_key(link) {
+            continue;
+        }
+        if pass.crawled >= config.crawl_max_pages {
+            info!("Reached crawl_max_pages, not following the rest of the links on {parent}");
+            break;
+        }
+        let _ = pass.depths.insert(link.clone(), depth + 1);
+        let _ = pass.pending.insert(link.clone());
+        pass.discovered.push(link.clone());
+        pass.crawled += 1;
+    }
+
+    followed
+        .into_iter()
+        .filter(|link| pass.depths.get(link).is_some_and(|depth| *depth > 0))
+        .collect()
+}
+
+/// How often waiting tabs are asked whether they're ready, with `wait_for_ready`.
+pub const READY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The entry whose most specific pattern matches `url`, ties go to the entry listed first.
+fn most_specific<'a, T>(url: &Url, entries: &'a [T], pattern: fn(&T) -> &str) -> Option<&'a T> {
+    let mut best: Option<(&T, (bool, usize))> = None;
+
+    for entry in entries {
+        if !url_matches(pattern(entry), url) {
+            continue;
+        }
+
+        let specificity = pattern_specificity(pattern(entry));
+        if best.is_none_or(|(_, best_specificity)| specificity > best_specificity) {
+            best = Some((entry, specificity));
+        }
+    }
+
+    best.map(|(entry, _)| entry)
+}
+
+fn dwell_override<'a>(url: &Url, config: &'a Config) -> Option<&'a DwellOverride> {
+    most_specific(url, &config.dwell_overrides, |dwell_override| {
+        &dwell_override.pattern
+    })
+}
+
+/// The mask of the most specific matching `screenshot_masks` entry.
+pub fn screenshot_mask<'a>(url: &Url, config: &'a Config) -> Option<&'a ScreenshotMask> {
+    most_specific(url, &config.screenshot_masks, |mask| &mask.pattern)
+}
+
+/// The dwell time of the matching `dwell_overrides` entry, URLs no entry matches get
+/// `page_dwell_time`.
+pub fn dwell_time(url: &Url, config: &Config) -> Duration {
+    dwell_override(url, config).map_or(config.page_dwell_time, |dwell_override| {
+        dwell_override.dwell
+    })
+}
+
+/// The selector a page has to match before it's ready, the matching `dwell_overrides`
+/// entry's if it has one.
+pub fn ready_selector<'a>(url: &Url, config: &'a Config) -> Option<&'a str> {
+    dwell_override(url, config)
+        .and_then(|dwell_override| dwell_override.ready_selector.as_deref())
+        .or(config.ready_selector.as_deref())
+}
+
+/// Whether `waiting` holds as many tabs as may be open at once, at least one always is.
+pub fn tab_pool_full(waiting: &[ActivePages], config: &Config) -> bool {
+    waiting.len() >= config.max_concurrent_tabs.max(1)
+}
+
+/// Removes and returns every waiting page that is ready or whose dwell time has elapsed
+/// by `now`.
+pub fn take_due(waiting: &mut Vec<ActivePages>, now: Instant) -> Vec<ActivePages> {
+    let (due, pending) = std::mem::take(waiting)
+        .into_iter()
+        .partition(|page| page.ready_at.is_some() || page.time_added + page.dwell <= now);
+    *waiting = pending;
+    due
+}
+
+/// When the next waiting page comes due. With `poll_interval` set, pages that aren't
+/// ready yet are looked at again after that long at the latest.
+pub fn next_deadline(waiting: &[ActivePages], poll_interval: Option<Duration>) -> Option<Instant> {
+    let deadline = waiting
+        .iter()
+        .map(|page| page.ready_at.unwrap_or(page.time_added + page.dwell))
+        .min()?;
+
+    match poll_interval {
+        Some(interval) if waiting.iter().any(|page| page.ready_at.is_none()) => {
+            Some(deadline.min(Instant::now() + interval))
+        }
+        _ => Some(deadline),
+    }
+}
+
+/// Redirects, missing markers and robots.txt refusals are deterministic, retrying them
+/// only costs time.
+pub fn is_retryable(state: &State, config: &Config) -> bool {
+    if state.error() == Some(CustomError::PageLoadTimeout)
+        && config.timeout_policy == TimeoutPolicy::RetryOnce
+    {
+        return true;
+    }
+
+    config.retry_attempts > 0
+        && state.link_type == LinkType::Generic
+        && state.error().is_some_and(|error| {
+            !matches!(
+                error,
+                CustomError::Redirected
+                    | CustomError::MarkerNotFound
+                    | CustomError::DisallowedByRobots
+            ) && config.retry_errors.contains(&error)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{failed_state, page_data, state, url};
+
+    fn tier(name: &str, patterns: &[&str], recheck_after: Option<Duration>) -> Tier {
+        Tier {
+            name: name.to_string(),
+            patterns: patterns.iter().map(ToString::to_string).collect(),
+            recheck_after,
+        }
+    }
+
+    fn checked_ago(age: chrono::Duration) -> PageData {
+        let mut page_data = page_data("https://example.com/", vec![state("<p>Page</p>")]);
+        page_data.last_checked = chrono::Utc::now() - age;
+        page_data
+    }
+
+    fn waiting(page: &str, time_added: Instant, dwell: Duration) -> ActivePages {
+        ActivePages {
+            url: url(page),
+            time_added,
+            linktype: LinkType::Generic,
+            navigation_error: None,
+            dwell,
+            ready_at: None,
+        }
+    }
+
+    const HOUR: Duration = Duration::from_secs(3600);
+
+    #[test]
+    fn the_most_specific_tier_pattern_wins() {
+        let tiers = [
+            tier("site", &["example.com"], None),
+            tier("docs", &["https://example.com/docs/"], None),
+            tier("also_site", &["example.com"], None),
+        ];
+
+        assert_eq!(
+            tier_name(&url("https://example.com/docs/a"), &tiers),
+            "docs"
+        );
+        assert_eq!(
+            tier_name(&url("https://www.example.com/docs/a"), &tiers),
+            "site"
+        );
+        // Equally specific, the tier listed first
+        assert_eq!(tier_name(&url("https://example.com/blog"), &tiers), "site");
+        assert!(assign_tier(&url("https://example.org/"), &tiers).is_none());
+        assert_eq!(tier_name(&url("https://example.org/"), &tiers), UNTIERED);
+    }
+
+    #[test]
+    fn the_tier_recheck_interval_overrides_the_global_one() {
+        let config = Config {
+            recheck_after: Some(HOUR),
+            ..Config::default()
+        };
+        let two_hours_ago = checked_ago(chrono::Duration::hours(2));
+        let slow = tier("slow", &["example.com"], Some(3 * HOUR));
+        let untimed = tier("untimed", &["example.com"], None);
+
+        assert!(is_fresh(&two_hours_ago, Some(&slow), &config));
+        assert!(!is_fresh(&two_hours_ago, Some(&untimed), &config));
+        assert!(!is_fresh(&two_hours_ago, None, &config));
+        assert!(is_fresh(
+            &checked_ago(chrono::Duration::minutes(30)),
+            None,
+            &config
+        ));
+        assert!(!is_fresh(&two_hours_ago, None, &Config::default()));
+    }
+
+    #[test]
+    fn resuming_skips_recent_checks_of_any_tier() {
+        let config = Config {
+            resume: true,
+            resume_window: 3 * HOUR,
+            ..Config::default()
+        };
+        let hourly = tier("hourly", &["example.com"], Some(HOUR));
+
+        assert!(is_fresh(
+            &checked_ago(chrono::Duration::hours(2)),
+            Some(&hourly),
+            &config
+        ));
+        assert!(!is_fresh(
+            &checked_ago(chrono::Duration::hours(4)),
+            Some(&hourly),
+            &config
+        ));
+        // A clock that went backwards
+        assert!(is_fresh(
+            &checked_ago(-chrono::Duration::hours(1)),
+            None,
+            &config
+        ));
+    }
+
+    #[test]
+    fn include_patterns_narrow_before_excludes_filter() {
+        let include = [UrlFilter::Glob("https://example.com/docs/".to_string())];
+        let exclude = [
+            UrlFilter::Regex(regex::Regex::new(r"\.pdf$").unwrap()),
+            UrlFilter::Glob("https://example.com/docs/old/".to_string()),
+        ];
+
+        assert_eq!(
+            filter_reason(&url("https://example.com/docs/a"), &include, &exclude),
+            None
+        );
+        assert_eq!(
+            filter_reason(&url("https://example.com/blog"), &include, &exclude).as_deref(),
+            Some("Not matched by any include pattern")
+        );
+        assert_eq!(
+            filter_reason(&url("https://example.com/docs/a.pdf"), &include, &exclude).as_deref(),
+            Some(r"Excluded by re:\.pdf$")
+        );
+        assert_eq!(
+            filter_reason(&url("https://example.com/docs/old/a"), &include, &exclude).as_deref(),
+            Some("Excluded by https://example.com/docs/old/")
+        );
+        assert_eq!(filter_reason(&url("https://example.org/"), &[], &[]), None);
+    }
+
+    #[test]
+    fn filtered_urls_are_listed_with_their_reason_instead_of_checked() {
+        let config = Config {
+            url_include_patterns: vec!["example.com".to_string()],
+            url_exclude_patterns: vec![
+                "re:^mailto:".to_string(),
+                "https://example.com/internal/".to_string(),
+            ],
+            ..Config::default()
+        };
+        let urls = HashSet::from([
+            url("https://example.com/docs"),
+            url("https://example.com/internal/wiki"),
+            url("https://sharepoint.example.org/"),
+        ]);
+
+        let plan = plan_run(urls, &BTreeMap::new(), &config).unwrap();
+        assert_eq!(
+            plan.to_check,
+            HashSet::from([url("https://example.com/docs")])
+        );
+        assert_eq!(
+            plan.filtered,
+            BTreeMap::from([
+                (
+                    url("https://example.com/internal/wiki"),
+                    "Excluded by https://example.com/internal/".to_string()
+                ),
+                (
+                    url("https://sharepoint.example.org/"),
+                    "Not matched by any include pattern".to_string()
+                ),
+            ])
+        );
+
+        let broken = Config {
+            url_exclude_patterns: vec!["re:(".to_string()],
+            ..Config::default()
+        };
+        assert!(plan_run(HashSet::new(), &BTreeMap::new(), &broken).is_err());
+    }
+
+    #[test]
+    fn pages_come_due_once_ready_or_their_dwell_is_over() {
+        let now = Instant::now();
+        let mut ready = waiting("https://example.com/ready", now, HOUR);
+        ready.ready_at = Some(now);
+        let mut pages = vec![
+            waiting("https://example.com/done", now - HOUR, HOUR),
+            waiting("https://example.com/waiting", now, HOUR),
+            ready,
+        ];
+
+        let due: Vec<String> = take_due(&mut pages, now)
+            .into_iter()
+            .map(|page| page.url.path().to_string())
+            .collect();
+        assert_eq!(due, ["/done", "/ready"]);
+        assert_eq!(pages.len(), 1);
+        assert_eq!(pages[0].url.path(), "/waiting");
+        assert!(take_due(&mut pages, now).is_empty());
+    }
+
+    #[test]
+    fn no_more_tabs_open_than_the_pool_allows() {
+        let now = Instant::now();
+        let pages: Vec<ActivePages> = (0..3)
+            .map(|i| waiting(&format!("https://example.com/{i}"), now, HOUR))
+            .collect();
+        let pool = |max_concurrent_tabs| Config {
+            max_concurrent_tabs,
+            ..Config::default()
+        };
+
+        assert!(!tab_pool_full(&pages[..2], &pool(3)));
+        assert!(tab_pool_full(&pages, &pool(3)));
+        assert!(tab_pool_full(&pages, &pool(2)));
+        // A pool of none still checks one page at a time
+        assert!(!tab_pool_full(&[], &pool(0)));
+        assert!(tab_pool_full(&pages[..1], &pool(0)));
+    }
+
+    #[test]
+    fn a_later_tab_with_a_shorter_dwell_is_checked_first() {
+        let now = Instant::now();
+        let mut pages = vec![
+            waiting("https://example.com/heavy", now, 3 * HOUR),
+            waiting("https://example.com/light", now, HOUR),
+        ];
+
+        assert!(take_due(&mut pages, now).is_empty());
+        let due = take_due(&mut pages, now + HOUR);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].url.path(), "/light");
+        assert_eq!(next_deadline(&pages, None), Some(now + 3 * HOUR));
+    }
+
+    #[test]
+    fn the_next_deadline_is_the_earliest_capped_by_the_poll_interval() {
+        assert_eq!(next_deadline(&[], None), None);
+        assert_eq!(next_deadline(&[], Some(Duration::from_secs(1))), None);
+
+        let now = Instant::now();
+        let pages = [
+            waiting("https://example.com/late", now, 2 * HOUR),
+            waiting("https://example.com/early", now, HOUR),
+        ];
+        assert_eq!(next_deadline(&pages, None), Some(now + HOUR));
+
+        let polled = next_deadline(&pages, Some(Duration::from_secs(1))).unwrap();
+        assert!(polled <= Instant::now() + Duration::from_secs(1));
+
+        // Pages that are all ready have nothing to poll for
+        let mut ready = waiting("https://example.com/ready", now, HOUR);
+        ready.ready_at = Some(now + HOUR);
+        assert_eq!(
+            next_deadline(&[ready], Some(Duration::from_secs(1))),
+            Some(now + HOUR)
+        );
+    }
+
+    #[test]
+    fn only_listed_transient_errors_are_retried() {
+        let config = Config::default();
+
+        assert!(is_retryable(&failed_state(CustomError::PageError), &config));
+        assert!(!is_retryable(&state("<p>Page</p>"), &config));
+        assert!(!is_retryable(
+            &failed_state(CustomError::Redirected),
+            &config
+        ));
+        assert!(!is_retryable(
+            &failed_state(CustomError::MarkerNotFound),
+            &config
+        ));
+        assert!(!is_retryable(
+            &failed_state(CustomError::DisallowedByRobots),
+            &config
+        ));
+        assert!(!is_retryable(
+            &failed_state(CustomError::PageLoadTimeout),
+            &config
+        ));
+
+        let mut content = failed_state(CustomError::PageError);
+        content.link_type = LinkType::Content;
+        assert!(!is_retryable(&content, &config));
+    }
+
+    #[test]
+    fn nothing_is_retried_without_retry_attempts_but_a_timeout_under_retry_once() {
+        let exhausted = Config {
+            retry_attempts: 0,
+            ..Config::default()
+        };
+        assert!(!is_retryable(
+            &failed_state(CustomError::PageError),
+            &exhausted
+        ));
+        assert!(!is_retryable(
+            &failed_state(CustomError::PageLoadTimeout),
+            &exhausted
+        ));
+
+        let retry_once = Config {
+            timeout_policy: TimeoutPolicy::RetryOnce,
+            ..exhausted
+        };
+        assert!(is_retryable(
+            &failed_state(CustomError::PageLoadTimeout),
+            &retry_once
+        ));
+        assert!(!is_retryable(
+            &failed_state(CustomError::PageError),
+            &retry_once
+        ));
+    }
+
+    #[test]
+    fn the_preview_shows_the_skipped_link_candidates() {
+        let extraction = LinkExtraction {
+            urls: HashSet::from([url("https://example.com/")]),
+            undecodable: 2,
+            too_long: 1,
+            unparsable: 3,
+        };
+        let preview = preview_run(
+            extraction.urls.clone(),
+            &extraction,
+            &BTreeMap::new(),
+            &Config::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            (preview.undecodable, preview.too_long, preview.unparsable),
+            (2, 1, 3)
+        );
+        assert!(preview.to_string().contains(
+            "Skipped link candidates in the source, undecodable: 2, too long or unterminated: 1, \
+             unparsable: 3\n"
+        ));
+    }
+}
@@ -1,38 +1,52 @@
 // Over your eyes! Don't look in here! :)
 
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Write,
     fs::OpenOptions,
     hash::Hash,
-    path::Path,
+    path::{Path, PathBuf},
     vec,
 };
 
+use anyhow::Context;
 use html_builder::{Buffer, Html5, Node};
+use tracing::{error, warn};
 
 use crate::{
-    enums::{InvalidReason, ValidReason},
-    structs::{DiffReport, Mode, PageData, ReportTableDataRow, State, Tables},
-    {get_loc, utilities::hash_string},
+    disc_op,
+    enums::{
+        CustomError, InvalidReason, LinkType, Locations, ReportFormat, ReportStatus, ReportTheme,
+        RunTrigger, ValidReason,
+    },
+    normalize, plan,
+    structs::{
+        AdaptiveToleranceConfig, Config, DiffReport, DomainCounts, Mode, PageData, Paths,
+        ReportEntry, ReportSummary, RunDiff, RunSummary, State, Tables,
+    },
+    {utilities, utilities::hash_string},
 };
 
-const NUM_VALID: usize = 8;
-const NUM_INVALID: usize = 5;
+const NUM_VALID: usize = 12;
+const NUM_INVALID: usize = 6;
+/// Local records are saved just before their check is recorded, a record this much
+/// older than the check still belongs to it.
+const RECORD_MATCH_WINDOW: chrono::TimeDelta = chrono::TimeDelta::minutes(2);
 
+/// Layout shared by every theme, colors come from the variables the theme sets.
 const CSS: &str = r"* {
-	background-color: #272727;
-	color: white;
+	background-color: var(--background);
+	color: var(--text);
 }
 table,
 th,
 td {
-	border: 1px solid white;
+	border: 1px solid var(--border);
 	border-collapse: collapse;
 	padding: 5px;
 }
-td:nth-child(9) {
-	border-right: 1px solid white;
+td:nth-child(18) {
+	border-right: 1px solid var(--border);
 }
 .empty {
 	border-left: none;
@@ -41,37 +55,153 @@ td:nth-child(9) {
 	padding: 0;
 }
 .valid {
-	color: #00ff00;
+	color: var(--valid);
 	border-left: none;
 	border-right: none;
 }
 .invalid {
-	color: red;
+	color: var(--invalid);
 	border-left: none;
 	border-right: none;
 }
+.stale {
+	color: var(--warning);
+}
+.flaky {
+	color: var(--warning);
+	font-weight: bold;
+}
+.slower {
+	color: var(--warning);
+}
+th.sort {
+	cursor: pointer;
+}
+th.sort:hover,
+tbody tr:hover td {
+	background-color: var(--hover);
+}
+th.asc::after {
+	content: ' ▲';
+}
+th.desc::after {
+	content: ' ▼';
+}
+.filter {
+	margin: 5px 0;
+}
 ";
 
-fn get_data_store() -> BTreeMap<url::Url, PageData> {
-    let data_store_file = OpenOptions::new()
-        .read(true)
-        .open(get_loc(crate::Locations::DataStore))
-        .expect("Could not open data store");
+const DARK_THEME: &str = r":root {
+	--background: #272727;
+	--text: white;
+	--border: white;
+	--valid: #00ff00;
+	--invalid: red;
+	--warning: orange;
+	--hover: #3a3a3a;
+}
+";
+
+/// Darker shades than the dark theme's, bright green and orange are unreadable on white.
+const LIGHT_THEME: &str = r":root {
+	--background: white;
+	--text: #1a1a1a;
+	--border: #1a1a1a;
+	--valid: #006b1b;
+	--invalid: #b00020;
+	--warning: #9a4a00;
+	--hover: #e6e6e6;
+}
+";
+
+/// The layout, the colors of `theme` and then the custom stylesheet if there is one.
+/// Prints always use the light colors.
+fn stylesheet(config: &Config) -> String {
+    let theme = match config.report_theme {
+        ReportTheme::Dark => format!("{DARK_THEME}@media print {{\n{LIGHT_THEME}}}\n"),
+        ReportTheme::Light => LIGHT_THEME.to_string(),
+        ReportTheme::Auto => {
+            format!("{DARK_THEME}@media (prefers-color-scheme: light), print {{\n{LIGHT_THEME}}}\n")
+        }
+    };
+    let mut css = format!("{CSS}{theme}");
+
+    if let Some(path) = &config.report_custom_css_path {
+        match std::fs::read_to_string(path) {
+            Ok(custom) => css.push_str(&custom),
+            Err(e) => warn!("Failed to read custom report CSS {path:?}, leaving it out: {e}"),
+        }
+    }
 
-    let page_datas: BTreeMap<url::Url, PageData> =
-        serde_json::from_reader(data_store_file).expect("Could not parse data store");
-    page_datas
+    css
 }
 
+/// Click a header to sort by that column, type in the box above a table to only show
+/// rows containing the text. Inline so the report keeps working as a single file.
+const SCRIPT: &str = r"const compare = (a, b) => {
+	const x = parseFloat(a), y = parseFloat(b);
+	return isNaN(x) || isNaN(y) ? a.localeCompare(b) : x - y;
+};
+document.querySelectorAll('table.sortable').forEach((table) => {
+	const body = table.tBodies[0];
+	const filter = document.createElement('input');
+	filter.type = 'search';
+	filter.placeholder = 'Filter rows';
+	filter.className = 'filter';
+	filter.addEventListener('input', () => {
+		const needle = filter.value.toLowerCase();
+		for (const row of body.rows) {
+			const text = [row.textContent, row.dataset.url, row.dataset.error].join(' ');
+			row.hidden = needle !== '' && !text.toLowerCase().includes(needle);
+		}
+	});
+	table.before(filter);
+
+	const headers = Array.from(table.tHead.rows[0].cells);
+	let column = 0;
+	for (const th of headers) {
+		const index = column;
+		column += th.colSpan;
+		if (th.colSpan > 1) continue;
+		th.classList.add('sort');
+		th.addEventListener('click', () => {
+			const ascending = !th.classList.contains('asc');
+			headers.forEach((other) => other.classList.remove('asc', 'desc'));
+			th.classList.add(ascending ? 'asc' : 'desc');
+			// The URL cell only shows part of the URL, sort by domain then the full URL
+			const key = (row) => index === 0
+				? `${row.dataset.domain} ${row.dataset.url}`
+				: (row.cells[index]?.textContent ?? '').trim();
+			const rows = Array.from(body.rows);
+			rows.sort((a, b) => (ascending ? 1 : -1) * compare(key(a), key(b)));
+			body.append(...rows);
+		});
+	}
+});
+";
+
+/// Fewer earlier states than this and a single odd check can decide the mode, pages
+/// that don't match it are reported as Unknown rather than Hash Only.
+const MIN_HISTORY_FOR_MODE: usize = 3;
+
+/// The most common of `values`, oldest first. A tie goes to the value seen most
+/// recently, the page has most likely settled on it.
 fn mode<T: Eq + Hash + Clone>(values: &[T]) -> Mode<T> {
     let mut counts = HashMap::new();
     let total = values.len();
 
     for value in values {
-        *counts.entry(value.clone()).or_insert(0) += 1;
+        *counts.entry(value).or_insert(0) += 1;
     }
 
-    if let Some((value, &count)) = counts.iter().max_by_key(|(_, &count)| count) {
+    let most_common = counts.values().copied().max();
+    if let Some((value, count)) = values
+        .iter()
+        .rev()
+        .map(|value| (value, counts[value]))
+        .find(|(_, count)| Some(*count) == most_common)
+    {
         Mode {
             value: Some(value.clone()),
             confidence: Some(count * 100 / total),
@@ -110,223 +240,1176 @@ fn diff_report(history: &[State]) -> DiffReport {
                 .collect::<Vec<String>>(),
         ),
         screenshot_hash: mode(&screenshot_hashes),
+        text_hash: mode(
+            &history
+                .iter()
+                .filter_map(|state| state.text_hash.clone())
+                .collect::<Vec<String>>(),
+        ),
+    }
+}
+
+fn median(values: &[f64]) -> Option<f64> {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(f64::total_cmp);
+
+    let mid = sorted.len() / 2;
+    match sorted.len() {
+        0 => None,
+        len if len % 2 == 0 => Some((sorted[mid - 1] + sorted[mid]) / 2.0),
+        _ => Some(sorted[mid]),
+    }
+}
+
+/// Whether `load_time_ms` is more than `factor` times the median load time of `history`.
+/// Needs `MIN_HISTORY_FOR_MODE` earlier load times, a factor of 0 turns it off.
+fn load_time_regressed(history: &[State], load_time_ms: Option<u64>, factor: f64) -> bool {
+    let Some(load_time_ms) = load_time_ms else {
+        return false;
+    };
+    let load_times: Vec<f64> = history
+        .iter()
+        .filter_map(|state| state.load_time_ms)
+        .map(|load_time| load_time as f64)
+        .collect();
+    if factor <= 0.0 || load_times.len() < MIN_HISTORY_FOR_MODE {
+        return false;
+    }
+
+    median(&load_times).is_some_and(|median| load_time_ms as f64 > median * factor)
+}
+
+/// Median absolute deviation, a spread measure that a single outlier can't drag around.
+fn mad(values: &[f64]) -> Option<f64> {
+    let center = median(values)?;
+    let deviations: Vec<f64> = values.iter().map(|value| (value - center).abs()).collect();
+    median(&deviations)
+}
+
+/// `median + k * MAD` of the observed distances, clamped to `floor..=ceiling`.
+/// Returns None when there are fewer than `min_samples` distances to learn from.
+fn learned_tolerance(distances: &[u32], adaptive: &AdaptiveToleranceConfig) -> Option<u32> {
+    if distances.len() < adaptive.min_samples.max(1) {
+        return None;
+    }
+
+    let values: Vec<f64> = distances.iter().map(|d| f64::from(*d)).collect();
+    let tolerance = median(&values)? + adaptive.k * mad(&values)?;
+
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let tolerance = tolerance.ceil() as u32;
+    Some(tolerance.clamp(adaptive.floor, adaptive.ceiling.max(adaptive.floor)))
+}
+
+/// Pairs hashed with different settings have no distance and are left out.
+fn pairwise_screenshot_distances(history: &[State]) -> Vec<u32> {
+    let screenshots: Vec<&State> = history
+        .iter()
+        .filter(|state| state.screenshot_hash.is_some())
+        .collect();
+
+    let mut distances = vec![];
+    for (i, a) in screenshots.iter().enumerate() {
+        for b in &screenshots[i + 1..] {
+            distances.extend(a.cal_screenshot_similarity(b));
+        }
+    }
+    distances
+}
+
+/// The tolerance to compare screenshots with, and whether it was learned from the history.
+/// Only screenshots taken the way `latest` was are learned from, the distance between a
+/// full page and a viewport screenshot or differently masked ones isn't noise.
+fn screenshot_tolerance(history: &[State], latest: &State, config: &Config) -> (u32, bool) {
+    if config.adaptive_tolerance.enabled {
+        let taken_alike: Vec<State> = history
+            .iter()
+            .filter(|state| {
+                state.full_page_screenshot == latest.full_page_screenshot
+                    && state.mask_version == latest.mask_version
+            })
+            .cloned()
+            .collect();
+        let distances = pairwise_screenshot_distances(&taken_alike);
+        if let Some(tolerance) = learned_tolerance(&distances, &config.adaptive_tolerance) {
+            return (tolerance, true);
+        }
+    }
+
+    (config.screenshot_diff_tolerance, false)
+}
+
+/// The looser of the absolute and the percentage tolerance around `target`.
+fn compression_tolerance(target: usize, absolute: usize, percent: f64) -> usize {
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    let relative = (target as f64 * percent.max(0.0) / 100.0).round() as usize;
+    absolute.max(relative)
+}
+
+/// Share of `history` that ended in an error, 0 for an empty history.
+#[allow(clippy::cast_precision_loss)]
+fn error_rate(history: &[State]) -> f64 {
+    if history.is_empty() {
+        return 0.0;
     }
+    let errors = history
+        .iter()
+        .filter(|state| !state.errors.is_empty())
+        .count();
+    errors as f64 / history.len() as f64
+}
+
+/// When the page source last differed from the successful check before it. Failed
+/// checks are skipped and so are hashes made with other source filters, neither
+/// says anything about the page.
+fn last_changed(
+    history: &[State],
+    filter_version: impl Fn(&State) -> String,
+) -> Option<chrono::DateTime<chrono::Utc>> {
+    let succeeded: Vec<&State> = history
+        .iter()
+        .filter(|state| state.errors.is_empty())
+        .collect();
+
+    succeeded
+        .windows(2)
+        .rev()
+        .find(|pair| {
+            filter_version(pair[0]) == filter_version(pair[1]) && pair[0].hash != pair[1].hash
+        })
+        .map(|pair| pair[1].check_time)
 }
 
 fn within(value: usize, target: usize, tolerance: usize) -> bool {
-    value >= target - tolerance && value <= target + tolerance
+    value >= target.saturating_sub(tolerance) && value <= target.saturating_add(tolerance)
 }
 
-fn mk_table(
-    body: &mut Node<'_>,
-    pages_title: &str,
-    table_data: Vec<ReportTableDataRow>,
-    local_dir: Option<&String>,
-) -> anyhow::Result<()> {
-    let mut div = body.div();
-    let mut h2 = div.h2();
-    writeln!(h2, "{pages_title}")?;
+/// An absolute `file://` URL, so links work wherever the report is opened from. The
+/// URL encoding covers spaces and drive letters, `'` is encoded as well since the
+/// attributes are single quoted.
+fn file_url(path: &Path, directory: bool) -> String {
+    let url = if directory {
+        url::Url::from_directory_path(path)
+    } else {
+        url::Url::from_file_path(path)
+    };
 
-    let mut table = body.table();
+    url.map_or_else(|()| path.to_string_lossy().into_owned(), String::from)
+        .replace('\'', "%27")
+}
+
+fn mk_table_head(table: &mut Node<'_>) -> anyhow::Result<()> {
     let mut thead = table.thead();
     let mut tr = thead.tr();
     writeln!(tr.th(), "URL")?;
+    writeln!(tr.th(), "Link type")?;
     writeln!(tr.th(), "Local data")?;
+    writeln!(tr.th(), "Screenshot")?;
     writeln!(tr.th(), "Errors")?;
+    writeln!(tr.th(), "Status code")?;
     writeln!(tr.th(), "Marker")?;
+    writeln!(tr.th(), "Tier")?;
+    writeln!(tr.th(), "Compared to")?;
+    writeln!(tr.th(), "Screenshot tolerance")?;
+    writeln!(tr.th(), "History")?;
+    writeln!(tr.th(), "Flaky")?;
+    writeln!(tr.th(), "Load time")?;
+    writeln!(tr.th(), "Failed assets")?;
     writeln!(tr.th().attr(&format!("colspan='{NUM_INVALID}'")), "Invalid")?;
     writeln!(tr.th().attr(&format!("colspan='{NUM_VALID}'")), "Valid")?;
 
-    let mut table_body = table.tbody();
+    Ok(())
+}
 
-    for row in table_data {
-        let mut tr = table_body.tr();
+/// `url_display` is the link text of the URL, what's useful depends on the layout.
+fn mk_row(
+    table_body: &mut Node<'_>,
+    row: ReportEntry,
+    url_display: &str,
+    local_records: bool,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    let pages_dir = paths.get(Locations::PagesSubdir);
+    let url = row.url;
+    let url_hash = hash_string(&url.to_string());
 
-        let url = row.url;
-        let domain = url
-            .domain()
-            .unwrap_or_else(|| url.host_str().unwrap_or(url.as_str()));
-        let url_display = &format!("{domain:.40}");
-        let url_hash = hash_string(&url.to_string());
+    // Stable values for the report script, whatever the cells show
+    let data_attr =
+        |name: &str, value: &str| format!("data-{name}='{}'", value.replace('\'', "&#39;"));
+    let mut tr = table_body
+        .tr()
+        .attr(&data_attr("url", url.as_str()))
+        .attr(&data_attr("domain", &registrable_domain(&url)))
+        .attr(&data_attr(
+            "error",
+            &row.errors
+                .iter()
+                .map(|error| format!("{error:?}"))
+                .collect::<Vec<String>>()
+                .join(" "),
+        ));
+
+    let mut url_td = tr.td();
+    writeln!(
+        url_td
+            .a()
+            .attr(&format!("href='{url}'"))
+            .attr("target='_blank'"),
+        "{url_display}"
+    )?;
+    if !row.aliases.is_empty() {
+        let mut details = url_td.details();
+        writeln!(details.summary(), "{} variants", row.aliases.len())?;
+        for alias in &row.aliases {
+            writeln!(
+                details
+                    .a()
+                    .attr(&format!("href='{alias}'"))
+                    .attr("target='_blank'"),
+                "{:.60}",
+                alias.as_str()
+            )?;
+            details.br();
+        }
+    }
+    if !row.linked_from.is_empty() {
+        let mut details = url_td.details();
+        writeln!(
+            details.summary(),
+            "Linked from {} pages",
+            row.linked_from.len()
+        )?;
+        for parent in &row.linked_from {
+            writeln!(
+                details
+                    .a()
+                    .attr(&format!("href='{parent}'"))
+                    .attr("target='_blank'"),
+                "{:.60}",
+                parent.as_str()
+            )?;
+            details.br();
+        }
+    }
+    if let Some(final_url) = &row.final_url {
+        let final_display = final_url.as_str();
+        writeln!(url_td, " → ")?;
+        writeln!(
+            url_td
+                .a()
+                .attr(&format!("href='{final_url}'"))
+                .attr("target='_blank'")
+                .attr(&format!("title='{final_url}'")),
+            "{final_display:.60}"
+        )?;
+    }
 
-        let mut url_td = tr.td();
+    let details_page = paths
+        .get(Locations::ReportDetails)
+        .join(format!("{url_hash}.html"));
+    if details_page.exists() {
+        url_td.br();
         writeln!(
             url_td
                 .a()
-                .attr(&format!("href='{url}'"))
+                .attr(&format!("href='{}'", file_url(&details_page, false)))
                 .attr("target='_blank'"),
-            "{url_display}"
+            "History"
+        )?;
+    }
+
+    writeln!(tr.td(), "{:?}", row.link_type)?;
+
+    let page_dir = pages_dir.join(&url_hash);
+    let mut data_td = tr.td();
+    if local_records {
+        writeln!(
+            data_td
+                .a()
+                .attr(&format!("href='{}'", file_url(&page_dir, true))),
+            "Data"
         )?;
 
-        let mut data_td = tr.td();
-        if let Some(local_dir) = local_dir {
+        let screenshot_changed = row
+            .invalid_reason
+            .as_ref()
+            .is_some_and(|reasons| reasons.contains(&InvalidReason::ScreenshotHash));
+        let diff_exists = page_dir.join(disc_op::SCREENSHOT_DIFF_FILE).exists();
+        if screenshot_changed && diff_exists {
+            let diff_path = page_dir.join(disc_op::SCREENSHOT_DIFF_FILE);
+            writeln!(data_td, " | ")?;
             writeln!(
-                data_td.a().attr(&format!("href='{local_dir}/{url_hash}'")),
-                "Data"
+                data_td
+                    .a()
+                    .attr(&format!("href='{}'", file_url(&diff_path, false)))
+                    .attr("target='_blank'"),
+                "Diff"
             )?;
-        } else {
-            writeln!(data_td, "None")?;
         }
+    } else {
+        writeln!(data_td, "None")?;
+    }
+    if row.source_truncated {
+        writeln!(
+            data_td
+                .span()
+                .attr("class='stale'")
+                .attr("title='Only the start of the page source was hashed and saved'"),
+            " (source cut off)"
+        )?;
+    }
+    if row.not_comparable {
+        writeln!(
+            data_td
+                .span()
+                .attr("class='stale'")
+                .attr("title='The history was checked with another browser locale or time zone'"),
+            " (not comparable)"
+        )?;
+    }
+    if let Some(local_record_error) = &row.local_record_error {
+        writeln!(
+            data_td.span().attr("class='stale'").attr(&format!(
+                "title='{}'",
+                local_record_error.replace('\'', "&#39;")
+            )),
+            " (save failed, may be stale)"
+        )?;
+    }
 
-        if let Some(errors) = row.errors {
-            writeln!(tr.td(), "{errors:?}")?;
-        } else {
-            writeln!(tr.td(), "None")?;
+    let thumbnail = page_dir.join(disc_op::THUMBNAIL_FILE);
+    let screenshot = disc_op::latest_screenshot(&page_dir);
+    let mut screenshot_td = tr.td();
+    match screenshot {
+        Some(screenshot) if local_records && thumbnail.exists() => {
+            let mut link = screenshot_td
+                .a()
+                .attr(&format!("href='{}'", file_url(&screenshot, false)))
+                .attr("target='_blank'");
+            let _ = link
+                .img()
+                .attr(&format!("src='{}'", file_url(&thumbnail, false)))
+                .attr("alt='Screenshot'")
+                .attr("loading='lazy'");
         }
+        _ => writeln!(screenshot_td, "—")?,
+    }
 
-        writeln!(tr.td(), "{}", row.marker)?;
-
-        if let Some(invalid_reason) = &row.invalid_reason {
-            for reason in invalid_reason {
-                writeln!(tr.td().attr("class='invalid'"), "{reason:?}")?;
-            }
+    let mut errors_td = tr.td();
+    for (i, error) in row.errors.iter().enumerate() {
+        if i > 0 {
+            errors_td.br();
         }
-        for _ in row.invalid_reason.iter().len()..NUM_INVALID {
-            writeln!(tr.td().attr("class='empty'"))?;
+        let validator_error = matches!(
+            error,
+            CustomError::CustomValidatorFailed | CustomError::CustomValidatorError
+        );
+        match &row.validator_reason {
+            Some(reason) if validator_error => writeln!(errors_td, "{error:?}: {reason}")?,
+            _ => writeln!(errors_td, "{error:?}")?,
+        }
+    }
+    if row.errors.is_empty() && row.succeeded_on_retry {
+        writeln!(errors_td, "None (passed on retry)")?;
+    } else if row.errors.is_empty() {
+        writeln!(errors_td, "None")?;
+    }
+
+    if let Some(status_code) = row.status_code {
+        writeln!(tr.td(), "{status_code}")?;
+    } else {
+        writeln!(tr.td(), "Unavailable")?;
+    }
+
+    writeln!(tr.td(), "{}", if row.marker { "Set" } else { "Not set" })?;
+    writeln!(tr.td(), "{}", row.tier)?;
+    writeln!(
+        tr.td(),
+        "{}",
+        if row.baseline { "Baseline" } else { "History" }
+    )?;
+    writeln!(
+        tr.td(),
+        "{} ({})",
+        row.screenshot_tolerance,
+        if row.tolerance_learned {
+            "learned"
+        } else {
+            "global"
         }
+    )?;
 
-        if let Some(valid_reason) = &row.valid_reason {
-            for reason in valid_reason {
-                writeln!(tr.td().attr("class='valid'"), "{reason:?}")?;
+    let changed = row
+        .last_changed
+        .map_or("no change seen".to_string(), |changed| {
+            format!("changed {}", changed.format("%Y-%m-%d"))
+        });
+    let checked_for = row.first_checked.map_or(0, |first_checked| {
+        chrono::Utc::now()
+            .signed_duration_since(first_checked)
+            .num_days()
+    });
+    writeln!(
+        tr.td(),
+        "{:.0}% failed, {changed}, checked for {checked_for} days",
+        row.error_rate * 100.0
+    )?;
+    if row.flaky {
+        writeln!(tr.td().attr("class='flaky'"), "Flaky")?;
+    } else {
+        writeln!(tr.td(), "—")?;
+    }
+    match row.load_time_ms {
+        Some(load_time_ms) if row.load_time_regressed => {
+            writeln!(tr.td().attr("class='slower'"), "{load_time_ms} ms, slower")?;
+        }
+        Some(load_time_ms) => writeln!(tr.td(), "{load_time_ms} ms")?,
+        None => writeln!(tr.td(), "—")?,
+    }
+    match row.failed_asset_count {
+        Some(0) => writeln!(tr.td(), "None")?,
+        Some(count) => {
+            let mut asset_td = tr.td().attr("class='invalid'");
+            let mut details = asset_td.details();
+            writeln!(details.summary(), "{count} failed")?;
+            for asset in &row.failed_assets {
+                writeln!(
+                    details
+                        .a()
+                        .attr(&format!("href='{asset}'"))
+                        .attr("target='_blank'"),
+                    "{asset:.60}"
+                )?;
+                details.br();
             }
+            if count > row.failed_assets.len() {
+                writeln!(details, "and {} more", count - row.failed_assets.len())?;
+            }
+        }
+        None => writeln!(tr.td(), "—")?,
+    }
+
+    if let Some(invalid_reason) = &row.invalid_reason {
+        for reason in invalid_reason {
+            writeln!(tr.td().attr("class='invalid'"), "{reason:?}")?;
         }
-        for _ in row.valid_reason.iter().len()..NUM_VALID {
-            writeln!(tr.td().attr("class='empty'"))?;
+    }
+    for _ in row.invalid_reason.iter().len()..NUM_INVALID {
+        writeln!(tr.td().attr("class='empty'"))?;
+    }
+
+    if let Some(valid_reason) = &row.valid_reason {
+        for reason in valid_reason {
+            writeln!(tr.td().attr("class='valid'"), "{reason:?}")?;
         }
     }
+    for _ in row.valid_reason.iter().len()..NUM_VALID {
+        writeln!(tr.td().attr("class='empty'"))?;
+    }
 
     Ok(())
 }
 
-fn save_report(root_buf: Buffer) {
-    let report_file_path = get_loc(crate::Locations::Report);
+fn mk_table(
+    body: &mut Node<'_>,
+    pages_title: &str,
+    table_data: Vec<ReportEntry>,
+    local_records: bool,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    let mut div = body.div();
+    let mut h2 = div.h2();
+    writeln!(h2, "{pages_title}")?;
+
+    let mut table = body.table().attr("class='sortable'");
+    mk_table_head(&mut table)?;
 
-    if Path::new(&report_file_path).exists() {
-        std::fs::remove_file(&report_file_path).unwrap();
+    let mut table_body = table.tbody();
+    for row in table_data {
+        let domain = row
+            .url
+            .domain()
+            .unwrap_or_else(|| row.url.host_str().unwrap_or(row.url.as_str()));
+        let url_display = format!("{domain:.40}");
+        mk_row(&mut table_body, row, &url_display, local_records, paths)?;
     }
 
-    let mut report_file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true)
-        .open(Path::new(&report_file_path))
-        .expect("Could not open report file");
+    Ok(())
+}
 
-    let page = root_buf.finish();
-    std::io::Write::write_all(&mut report_file, page.as_bytes())
-        .expect("Could not write report file");
+/// Second level labels that are part of the suffix under a two letter country code.
+const SECOND_LEVEL_SUFFIXES: [&str; 7] = ["ac", "co", "com", "edu", "gov", "net", "org"];
+
+/// The last two labels of the host, or three under suffixes like `co.uk`. A guess
+/// without the public suffix list, but good enough to group by. Links without a
+/// host are grouped by their scheme.
+pub fn registrable_domain(url: &url::Url) -> String {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            let labels: Vec<&str> = domain.trim_end_matches('.').split('.').collect();
+            let under_suffix = labels.len() >= 3
+                && labels[labels.len() - 1].len() == 2
+                && SECOND_LEVEL_SUFFIXES.contains(&labels[labels.len() - 2]);
+            let keep = if under_suffix { 3 } else { 2 };
+            labels[labels.len().saturating_sub(keep)..].join(".")
+        }
+        Some(host) => host.to_string(),
+        None => format!("{}:", url.scheme()),
+    }
 }
 
-#[allow(clippy::too_many_lines)]
-pub(crate) fn gen_post_run_report(config: &crate::Config) {
-    let page_datas = get_data_store();
+fn domain_counts(tables: &Tables) -> BTreeMap<String, DomainCounts> {
+    let mut counts: BTreeMap<String, DomainCounts> = BTreeMap::new();
+    for (table, count) in [
+        (&tables.error, (0, 0, 1)),
+        (&tables.unknown, (0, 1, 0)),
+        (&tables.hash_only, (0, 1, 0)),
+        (&tables.valid, (1, 0, 0)),
+    ] {
+        for row in table {
+            let domain_counts = counts.entry(registrable_domain(&row.url)).or_default();
+            domain_counts.valid += count.0;
+            domain_counts.invalid += count.1;
+            domain_counts.errors += count.2;
+        }
+    }
+    counts
+}
 
-    let mut root_buf = Buffer::new();
-    root_buf.doctype();
-    let mut html = root_buf.html().attr("lang='en'");
-    let mut head = html.head();
-    writeln!(head.title(), "Results!").unwrap();
-    let _ = head.meta().attr("charset='UTF-8'");
-    let _ = head
-        .meta()
-        .attr("name='viewport'")
-        .attr("content='width=device-width, initial-scale=1.0'");
+/// Same rows as `mk_table`, in one collapsible group per domain. Domains with the most
+/// errors come first, the groups of domains with errors start out open.
+fn mk_grouped_table(
+    body: &mut Node<'_>,
+    pages_title: &str,
+    table_data: Vec<ReportEntry>,
+    counts: &BTreeMap<String, DomainCounts>,
+    local_records: bool,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    writeln!(body.div().h2(), "{pages_title}")?;
 
-    writeln!(head.style(), "{CSS}").unwrap();
-    let mut body = html.body();
+    let mut groups: BTreeMap<String, Vec<ReportEntry>> = BTreeMap::new();
+    for row in table_data {
+        groups
+            .entry(registrable_domain(&row.url))
+            .or_default()
+            .push(row);
+    }
+    // The sort is stable, domains with as many errors stay alphabetical
+    let mut groups: Vec<(String, Vec<ReportEntry>)> = groups.into_iter().collect();
+    groups.sort_by_key(|(domain, _)| {
+        std::cmp::Reverse(counts.get(domain).map_or(0, |counts| counts.errors))
+    });
 
-    writeln!(body.h1(), "Results").unwrap();
-    let mut tables = Tables {
-        valid: vec![],
-        unknown: vec![],
-        hash_only: vec![],
-        error: vec![],
-    };
+    for (domain, mut rows) in groups {
+        rows.sort_by(|a, b| a.url.as_str().cmp(b.url.as_str()));
+        let domain_counts = counts.get(&domain).copied().unwrap_or_default();
 
-    for (url, page_data) in page_datas {
-        let mut history: Vec<State> = page_data.current_state();
-        let last_state = history
-            .pop()
-            .unwrap_or_else(|| panic!("No state for url: {url}"));
+        let mut details = body.details();
+        if domain_counts.errors > 0 {
+            details = details.attr("open");
+        }
+        writeln!(
+            details.summary(),
+            "{domain} ({} here): {} valid / {} invalid / {} errors",
+            rows.len(),
+            domain_counts.valid,
+            domain_counts.invalid,
+            domain_counts.errors
+        )?;
 
-        if history.is_empty() {
-            continue;
+        let mut table = details.table().attr("class='sortable'");
+        mk_table_head(&mut table)?;
+        let mut table_body = table.tbody();
+        for row in rows {
+            // The domain is the group header, the path is what tells the rows apart
+            let url_display = format!("{:.40}", &row.url[url::Position::BeforePath..]);
+            mk_row(&mut table_body, row, &url_display, local_records, paths)?;
         }
+    }
 
-        let mut invalid_reason = vec![];
-        let mut valid_reason = vec![];
+    Ok(())
+}
 
-        let dr = diff_report(&history);
-        if last_state.hash.eq(&dr.page_hash.value.unwrap()) {
-            valid_reason.push(ValidReason::PageHash);
-        } else {
-            invalid_reason.push(InvalidReason::PageHash);
+fn mk_summary(body: &mut Node<'_>, tables: &Tables) -> anyhow::Result<()> {
+    let columns = [
+        &tables.error,
+        &tables.unknown,
+        &tables.hash_only,
+        &tables.valid,
+        &tables.first_seen,
+        &tables.ignored,
+        &tables.not_in_source,
+    ];
+    let mut counts: BTreeMap<&str, [usize; 7]> = BTreeMap::new();
+    for (i, table) in columns.iter().enumerate() {
+        for row in table.iter() {
+            counts.entry(row.tier.as_str()).or_insert([0; 7])[i] += 1;
         }
+    }
 
-        if let Mode {
-            value: Some(value),
-            confidence: Some(_),
-        } = dr.compression
-        {
-            if last_state.compress_length.eq(&value) {
-                valid_reason.push(ValidReason::CompressionExact);
-            } else if within(
-                last_state.compress_length,
-                value,
-                config.compression_length_tolerance,
-            ) {
-                valid_reason.push(ValidReason::CompressionWithinTolerance);
-            } else {
-                invalid_reason.push(InvalidReason::Compression);
-            }
+    writeln!(body.h2(), "Summary")?;
+    let mut table = body.table();
+    let mut thead = table.thead();
+    let mut tr = thead.tr();
+    for title in [
+        "Tier",
+        "Error",
+        "Unknown",
+        "Hash Only",
+        "Valid",
+        "First seen",
+        "Ignored",
+        "No longer in source",
+        "Total",
+    ] {
+        writeln!(tr.th(), "{title}")?;
+    }
+
+    let mut table_body = table.tbody();
+    for (tier, tier_counts) in counts {
+        let mut tr = table_body.tr();
+        writeln!(tr.td(), "{tier}")?;
+        for count in tier_counts {
+            writeln!(tr.td(), "{count}")?;
         }
+        writeln!(tr.td(), "{}", tier_counts.iter().sum::<usize>())?;
+    }
 
-        let screenshot_diff =
-            last_state.cal_screenshot_similarity(dr.screenshot_hash.value.clone());
+    Ok(())
+}
 
-        if last_state.screenshot_hash.eq(&dr.screenshot_hash.value) {
-            valid_reason.push(ValidReason::ScreenshotHashExact);
-        } else if dr.screenshot_hash.confidence.unwrap_or(0) > config.screenshot_diff_confidence {
-            if screenshot_diff.is_some()
-                && screenshot_diff.unwrap() < config.screenshot_diff_tolerance
-            {
-                valid_reason.push(ValidReason::ScreenshotHashWithinTolerance);
-            } else {
-                invalid_reason.push(InvalidReason::ScreenshotHash);
-            }
-        } else {
-            invalid_reason.push(InvalidReason::ScreenshotHash);
-        }
+fn mk_run_summary(body: &mut Node<'_>, summary: &RunSummary) -> anyhow::Result<()> {
+    writeln!(body.h2(), "Last run")?;
 
-        if let Mode {
-            value: Some(value),
-            confidence: Some(_),
-        } = dr.title
-        {
-            if last_state.title.unwrap_or_default().eq(&value) {
-                valid_reason.push(ValidReason::Title);
-            } else {
-                invalid_reason.push(InvalidReason::Title);
+    let mut rows = vec![
+        ("Started", summary.started.to_rfc2822()),
+        (
+            "Finished",
+            summary
+                .finished
+                .map_or("Did not finish".to_string(), |finished| {
+                    finished.to_rfc2822()
+                }),
+        ),
+        (
+            "Started by",
+            match summary.trigger {
+                RunTrigger::Manual => "Hand",
+                RunTrigger::Scheduled => "Schedule",
             }
-        }
+            .to_string(),
+        ),
+        ("URLs checked", summary.urls_checked.to_string()),
+        ("New URLs", summary.new_urls.to_string()),
+        ("Previously seen", summary.previously_seen.to_string()),
+    ];
+    for (status, count) in &summary.status_counts {
+        rows.push((status_title(*status), count.to_string()));
+    }
+    rows.push(("Skipped by filters", summary.skipped.len().to_string()));
+    rows.push(("Browser restarts", summary.driver_restarts.to_string()));
+    rows.push(("Pages timed out", summary.timed_out.to_string()));
+    if !summary.extensions_skipped.is_empty() {
+        rows.push(("Extensions skipped", summary.extensions_skipped.join(", ")));
+    }
+    rows.push((
+        "Browser driver",
+        summary
+            .driver_version
+            .clone()
+            .unwrap_or("Unknown".to_string()),
+    ));
+    if summary.cancelled {
+        rows.push(("Cancelled", "Yes".to_string()));
+    }
 
-        let status = if last_state.error.is_some() {
-            "error"
-        } else if invalid_reason.is_empty() {
-            "valid"
-        } else if invalid_reason.contains(&InvalidReason::PageHash) && invalid_reason.len() == 1 {
-            "hash_only"
-        } else {
-            "unknown"
-        };
+    let mut table = body.table();
+    let mut table_body = table.tbody();
+    for (title, value) in rows {
+        let mut tr = table_body.tr();
+        writeln!(tr.th(), "{title}")?;
+        writeln!(tr.td(), "{value}")?;
+    }
 
-        let row = ReportTableDataRow {
-            url: url.clone(),
-            marker: if page_data.marker.is_some() {
-                "Set".to_string()
+    Ok(())
+}
+
+fn status_title(status: ReportStatus) -> &'static str {
+    match status {
+        ReportStatus::Error => "Error",
+        ReportStatus::Unknown => "Unknown",
+        ReportStatus::HashOnly => "Hash Only",
+        ReportStatus::Valid => "Valid",
+        ReportStatus::FirstSeen => "First seen",
+        ReportStatus::Ignored => "Ignored",
+        ReportStatus::NotInSource => "No longer in source",
+    }
+}
+
+fn mk_skipped_table(
+    body: &mut Node<'_>,
+    skipped: &BTreeMap<url::Url, String>,
+) -> anyhow::Result<()> {
+    writeln!(body.div().h2(), "Skipped")?;
+
+    let mut table = body.table();
+    let mut thead = table.thead();
+    let mut tr = thead.tr();
+    writeln!(tr.th(), "URL")?;
+    writeln!(tr.th(), "Reason")?;
+
+    let mut table_body = table.tbody();
+    for (url, reason) in skipped {
+        let mut tr = table_body.tr();
+        writeln!(
+            tr.td()
+                .a()
+                .attr(&format!("href='{url}'"))
+                .attr("target='_blank'"),
+            "{:.60}",
+            url.as_str()
+        )?;
+        writeln!(tr.td(), "{reason}")?;
+    }
+
+    Ok(())
+}
+
+/// The records in `records` saved for the check at `check_time`.
+fn records_of(
+    records: &[(chrono::DateTime<chrono::Utc>, PathBuf)],
+    check_time: chrono::DateTime<chrono::Utc>,
+) -> Vec<&PathBuf> {
+    records
+        .iter()
+        .filter(|(saved, _)| *saved <= check_time && check_time - *saved <= RECORD_MATCH_WINDOW)
+        .map(|(_, path)| path)
+        .collect()
+}
+
+fn mk_details_page(
+    url: &url::Url,
+    page_data: &PageData,
+    local_records: bool,
+    css: &str,
+    paths: &Paths,
+) -> anyhow::Result<String> {
+    let page_dir = paths.get(Locations::PagesSubdir).join(&page_data.url_hash);
+    let records = if local_records {
+        disc_op::timed_records(&page_dir)
+    } else {
+        vec![]
+    };
+
+    let mut root_buf = Buffer::new();
+    root_buf.doctype();
+    let mut html = root_buf.html().attr("lang='en'");
+    let mut head = html.head();
+    writeln!(head.title(), "History of {url}")?;
+    let _ = head.meta().attr("charset='UTF-8'");
+    writeln!(head.style().raw(), "{css}")?;
+    let mut body = html.body();
+
+    writeln!(body.h1(), "History")?;
+    writeln!(
+        body.p()
+            .a()
+            .attr(&format!("href='{url}'"))
+            .attr("target='_blank'"),
+        "{url}"
+    )?;
+
+    let mut table = body.table();
+    let mut thead = table.thead();
+    let mut tr = thead.tr();
+    for heading in [
+        "Checked",
+        "Title",
+        "Hash",
+        "Compress length",
+        "Screenshot hash",
+        "Error",
+        "Records",
+    ] {
+        writeln!(tr.th(), "{heading}")?;
+    }
+    let mut table_body = table.tbody();
+    for state in page_data.history.iter().rev() {
+        let mut tr = table_body.tr();
+        writeln!(
+            tr.td(),
+            "{}",
+            state.check_time.format("%Y-%m-%d %H:%M:%S UTC")
+        )?;
+        writeln!(tr.td(), "{}", state.title.as_deref().unwrap_or("—"))?;
+        writeln!(
+            tr.td().attr(&format!("title='{}'", state.hash)),
+            "{:.12}",
+            state.hash
+        )?;
+        writeln!(tr.td(), "{}", state.compress_length)?;
+        writeln!(
+            tr.td(),
+            "{}",
+            state.screenshot_hash.as_deref().unwrap_or("—")
+        )?;
+        if state.errors.is_empty() {
+            writeln!(tr.td(), "—")?;
+        } else {
+            let errors: Vec<String> = state.errors.iter().map(|e| format!("{e:?}")).collect();
+            writeln!(tr.td().attr("class='invalid'"), "{}", errors.join(", "))?;
+        }
+
+        let mut records_td = tr.td();
+        let state_records = records_of(&records, state.check_time);
+        if state_records.is_empty() {
+            writeln!(records_td, "—")?;
+        }
+        for path in state_records {
+            let name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            let kind = if name.starts_with("screenshot_") {
+                "Screenshot"
             } else {
-                "Not set".to_string()
-            },
+                "Page"
+            };
+            writeln!(
+                records_td
+                    .a()
+                    .attr(&format!("href='{}'", file_url(path, false)))
+                    .attr("target='_blank'"),
+                "{kind}"
+            )?;
+            records_td.br();
+        }
+    }
+
+    Ok(root_buf.finish())
+}
+
+/// Writes a page listing every stored state of each URL, named by its `url_hash`, and
+/// removes the pages of URLs that are no longer in the data store.
+fn gen_details_pages(
+    page_datas: &BTreeMap<url::Url, PageData>,
+    config: &Config,
+    paths: &Paths,
+) -> anyhow::Result<()> {
+    let css = stylesheet(config);
+    let details_dir = paths.get(Locations::ReportDetails);
+    std::fs::create_dir_all(&details_dir)
+        .with_context(|| format!("Failed to create directory: {details_dir:?}"))?;
+
+    let mut current = HashSet::new();
+    for (url, page_data) in page_datas {
+        let file_name = format!("{}.html", page_data.url_hash);
+        let path = details_dir.join(&file_name);
+        std::fs::write(
+            &path,
+            mk_details_page(url, page_data, config.keep_local_records, &css, paths)?,
+        )
+        .with_context(|| format!("Failed to write details page: {path:?}"))?;
+        let _ = current.insert(file_name);
+    }
+
+    let entries = std::fs::read_dir(&details_dir)
+        .with_context(|| format!("Failed to read directory: {details_dir:?}"))?;
+    for path in entries.filter_map(Result::ok).map(|entry| entry.path()) {
+        let stale = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.ends_with(".html") && !current.contains(name));
+        if stale {
+            if let Err(e) = std::fs::remove_file(&path) {
+                warn!("Failed to remove old details page {path:?}: {e:?}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn save_report(root_buf: Buffer, paths: &Paths) -> anyhow::Result<PathBuf> {
+    let report_file_path = paths.get(Locations::Report);
+
+    if Path::new(&report_file_path).exists() {
+        std::fs::remove_file(&report_file_path)
+            .with_context(|| format!("Failed to remove old report: {report_file_path:?}"))?;
+    }
+
+    let mut report_file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(Path::new(&report_file_path))
+        .with_context(|| format!("Failed to open report file: {report_file_path:?}"))?;
+
+    let page = root_buf.finish();
+    std::io::Write::write_all(&mut report_file, page.as_bytes())
+        .with_context(|| format!("Failed to write report file: {report_file_path:?}"))?;
+
+    Ok(report_file_path)
+}
+
+#[allow(clippy::too_many_lines)]
+pub fn classify(page_datas: BTreeMap<url::Url, PageData>, config: &Config) -> Vec<ReportEntry> {
+    let mut entries = vec![];
+    let legacy_version = normalize::legacy_filter_version();
+    // The last run that read the source marked everything it found
+    let latest_in_source = page_datas
+        .values()
+        .filter_map(|page_data| page_data.last_seen_in_source)
+        .max();
+    let filter_version = |state: &State| {
+        state
+            .filter_version
+            .clone()
+            .unwrap_or_else(|| legacy_version.clone())
+    };
+
+    for (url, page_data) in page_datas {
+        let mut history: Vec<State> = page_data.current_state();
+        let Some(last_state) = history.pop() else {
+            warn!("No state for {url}, leaving it out of the report");
+            continue;
+        };
+
+        let baseline = page_data.reference_state.is_some();
+        let first_seen = history.is_empty() && !baseline;
+
+        let error_rate = error_rate(&page_data.history);
+        let flaky = error_rate * 100.0 > config.flaky_error_percent && error_rate < 1.0;
+
+        let mut invalid_reason = vec![];
+        let mut valid_reason = vec![];
+
+        // An approved baseline replaces the rolling mode of the history. Pages rendered
+        // in another locale or time zone show other dates and banners, such states are
+        // left out rather than counted as changes
+        let compared_to: Vec<State> = match &page_data.reference_state {
+            Some(reference) => std::slice::from_ref(reference),
+            None => history.as_slice(),
+        }
+        .iter()
+        .filter(|state| {
+            state.http_only
+                || last_state.http_only
+                || (state.browser_locale == last_state.browser_locale
+                    && state.browser_timezone == last_state.browser_timezone)
+        })
+        .cloned()
+        .collect();
+        let not_comparable = !first_seen && compared_to.is_empty();
+        let dr = diff_report(&compared_to);
+        let ignore = page_data.ignore;
+
+        // Hashes made with other source filters say nothing about the page, the source
+        // is only compared against states normalized the same way. A response body isn't
+        // comparable to a rendered page either, nor is a cut off source to a whole one
+        let comparable: Vec<State> = compared_to
+            .iter()
+            .filter(|state| {
+                filter_version(state) == filter_version(&last_state)
+                    && state.http_only == last_state.http_only
+                    && state.source_truncated == last_state.source_truncated
+            })
+            .cloned()
+            .collect();
+        if !comparable.is_empty() {
+            let source_dr = diff_report(&comparable);
+            if source_dr.page_hash.value.as_ref() == Some(&last_state.hash) {
+                valid_reason.push(ValidReason::PageHash);
+            } else if !ignore.page_hash {
+                invalid_reason.push(InvalidReason::PageHash);
+            }
+
+            if let Mode {
+                value: Some(value),
+                confidence: Some(_),
+            } = source_dr.compression
+            {
+                if last_state.compress_length.eq(&value) {
+                    valid_reason.push(ValidReason::CompressionExact);
+                } else if within(
+                    last_state.compress_length,
+                    value,
+                    compression_tolerance(
+                        value,
+                        config.compression_length_tolerance,
+                        config.compression_tolerance_percent,
+                    ),
+                ) {
+                    valid_reason.push(ValidReason::CompressionWithinTolerance);
+                } else if !ignore.page_hash {
+                    invalid_reason.push(InvalidReason::Compression);
+                }
+            }
+        }
+
+        // Same text means the source only changed in markup, that alone isn't a change
+        // of the page. States from before the text was hashed don't take part
+        if let (Some(text_hash), Some(value)) = (&last_state.text_hash, &dr.text_hash.value) {
+            if text_hash == value {
+                valid_reason.push(ValidReason::TextHash);
+                invalid_reason.retain(|reason| {
+                    !matches!(reason, InvalidReason::PageHash | InvalidReason::Compression)
+                });
+            } else if !ignore.page_hash {
+                invalid_reason.push(InvalidReason::TextHash);
+            }
+        }
+
+        // Full page and viewport screenshots never match and neither do differently
+        // masked ones, a switch leaves the screenshot out until there are states taken
+        // the new way to compare to
+        let screenshot_states: Vec<State> = compared_to
+            .iter()
+            .filter(|state| {
+                last_state.http_only
+                    || (state.full_page_screenshot == last_state.full_page_screenshot
+                        && state.mask_version == last_state.mask_version)
+            })
+            .cloned()
+            .collect();
+        let screenshot_dr = diff_report(&screenshot_states);
+        let mode_state = screenshot_dr
+            .screenshot_hash
+            .value
+            .as_ref()
+            .and_then(|value| {
+                screenshot_states
+                    .iter()
+                    .find(|state| state.screenshot_hash.as_ref() == Some(value))
+            });
+        let screenshot_diff =
+            mode_state.and_then(|state| last_state.cal_screenshot_similarity(state));
+        let hashers_differ = last_state.screenshot_hash.is_some()
+            && mode_state
+                .is_some_and(|state| state.screenshot_hasher() != last_state.screenshot_hasher());
+        let (screenshot_tolerance, tolerance_learned) =
+            screenshot_tolerance(&history, &last_state, config);
+
+        if first_seen || not_comparable {
+            // Nothing to compare the screenshot to yet
+        } else if hashers_differ {
+            // Hashes made with other hash settings can't be compared, the screenshot is
+            // unknown until the history has hashes made the new way
+            if !ignore.screenshot {
+                invalid_reason.push(InvalidReason::ScreenshotHash);
+            }
+        } else if last_state
+            .screenshot_hash
+            .eq(&screenshot_dr.screenshot_hash.value)
+        {
+            valid_reason.push(ValidReason::ScreenshotHashExact);
+        } else if last_state.http_only || screenshot_dr.screenshot_hash.value.is_none() {
+            // One side was only checked over HTTP or took the other kind of screenshot,
+            // there is nothing to compare
+        } else if screenshot_diff.is_none() {
+            // A hash that can't be decoded says nothing either way, however sure the
+            // mode is
+        } else if screenshot_dr.screenshot_hash.confidence.unwrap_or(0)
+            > config.screenshot_diff_confidence
+        {
+            match screenshot_diff {
+                Some(diff) if diff < screenshot_tolerance => {
+                    valid_reason.push(ValidReason::ScreenshotHashWithinTolerance);
+                }
+                _ if !ignore.screenshot => {
+                    invalid_reason.push(InvalidReason::ScreenshotHash);
+                }
+                _ => {}
+            }
+        } else if !ignore.screenshot {
+            invalid_reason.push(InvalidReason::ScreenshotHash);
+        }
+
+        if let Mode {
+            value: Some(value),
+            confidence: Some(_),
+        } = dr.title
+        {
+            if last_state.http_only {
+                // HTTP checks don't read a title
+            } else if last_state.title.clone().unwrap_or_default().eq(&value) {
+                valid_reason.push(ValidReason::Title);
+            } else if !ignore.title {
+                invalid_reason.push(InvalidReason::Title);
+            }
+        }
+
+        if last_state.anchor_found {
+            valid_reason.push(ValidReason::AnchorFound);
+        }
+        if last_state.link_type == LinkType::Mailto && last_state.errors.is_empty() {
+            valid_reason.push(ValidReason::EmailSyntax);
+        }
+        if last_state.mx_verified {
+            valid_reason.push(ValidReason::MxRecords);
+        }
+
+        // A configured history shorter than the minimum can never reach it
+        let min_history = MIN_HISTORY_FOR_MODE.min(config.history_length.saturating_sub(1));
+        let short_history = !baseline && history.len() < min_history;
+
+        let status = if ignore.skip {
+            ReportStatus::Ignored
+        } else if latest_in_source.is_some_and(|latest| page_data.last_seen() < latest) {
+            ReportStatus::NotInSource
+        } else if !last_state.errors.is_empty() {
+            ReportStatus::Error
+        } else if first_seen || not_comparable {
+            ReportStatus::FirstSeen
+        } else if invalid_reason.is_empty() {
+            ReportStatus::Valid
+        } else if invalid_reason.contains(&InvalidReason::PageHash)
+            && invalid_reason.len() == 1
+            && !short_history
+        {
+            ReportStatus::HashOnly
+        } else {
+            ReportStatus::Unknown
+        };
+
+        entries.push(ReportEntry {
+            url: url.clone(),
+            aliases: page_data.aliases.iter().cloned().collect(),
+            linked_from: page_data.linked_from.iter().cloned().collect(),
+            status,
+            tier: plan::tier_name(&url, &config.tiers),
+            link_type: last_state.link_type,
+            screenshot_distance: screenshot_diff,
+            screenshot_tolerance,
+            tolerance_learned,
+            title: last_state.title,
+            check_time: last_state.check_time,
+            succeeded_on_retry: last_state.succeeded_on_retry,
+            error_rate,
+            last_changed: last_changed(&page_data.history, filter_version),
+            first_checked: page_data.history.first().map(|state| state.check_time),
+            flaky,
+            load_time_ms: last_state.load_time_ms,
+            failed_asset_count: last_state.failed_asset_count,
+            failed_assets: last_state.failed_assets.clone().unwrap_or_default(),
+            source_truncated: last_state.source_truncated,
+            not_comparable,
+            load_time_regressed: load_time_regressed(
+                &history,
+                last_state.load_time_ms,
+                config.load_time_regression_factor,
+            ),
+            status_code: last_state.status_code,
+            final_url: last_state.final_url.filter(|final_url| *final_url != url),
+            local_record_error: last_state.local_record_error,
+            validator_reason: last_state.validator_reason,
+            marker: page_data.marker.is_some(),
+            baseline,
             invalid_reason: if invalid_reason.is_empty() {
                 None
             } else {
@@ -337,31 +1420,1380 @@ pub(crate) fn gen_post_run_report(config: &crate::Config) {
             } else {
                 Some(valid_reason)
             },
-            errors: last_state.error,
-        };
+            errors: last_state.errors,
+        });
+    }
 
-        match status {
-            "error" => tables.error.push(row),
-            "hash_only" => tables.hash_only.push(row),
-            "valid" => tables.valid.push(row),
-            _ => tables.unknown.push(row),
+    entries.sort_by(|a, b| a.url.as_str().cmp(b.url.as_str()));
+    entries
+}
+
+fn split_tables(entries: Vec<ReportEntry>) -> Tables {
+    let mut tables = Tables {
+        valid: vec![],
+        unknown: vec![],
+        hash_only: vec![],
+        error: vec![],
+        first_seen: vec![],
+        ignored: vec![],
+        not_in_source: vec![],
+        last_run: None,
+    };
+
+    for entry in entries {
+        match entry.status {
+            ReportStatus::Error => tables.error.push(entry),
+            ReportStatus::HashOnly => tables.hash_only.push(entry),
+            ReportStatus::Valid => tables.valid.push(entry),
+            ReportStatus::Unknown => tables.unknown.push(entry),
+            ReportStatus::FirstSeen => tables.first_seen.push(entry),
+            ReportStatus::Ignored => tables.ignored.push(entry),
+            ReportStatus::NotInSource => tables.not_in_source.push(entry),
         }
     }
 
+    tables
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn join_reasons<T: std::fmt::Debug>(reasons: Option<&Vec<T>>) -> String {
+    reasons
+        .map(|reasons| {
+            reasons
+                .iter()
+                .map(|reason| format!("{reason:?}"))
+                .collect::<Vec<String>>()
+                .join(";")
+        })
+        .unwrap_or_default()
+}
+
+pub(crate) fn gen_csv_report(tables: &Tables, paths: &Paths) -> anyhow::Result<PathBuf> {
+    let mut csv = String::from(concat!(
+        "url,status,marker,error,valid_reasons,invalid_reasons,aliases,linked_from,",
+        "load_time_ms,load_time_regressed,failed_assets\n",
+    ));
+
+    for (status, table) in [
+        ("error", &tables.error),
+        ("unknown", &tables.unknown),
+        ("hash_only", &tables.hash_only),
+        ("valid", &tables.valid),
+        ("first_seen", &tables.first_seen),
+        ("ignored", &tables.ignored),
+        ("not_in_source", &tables.not_in_source),
+    ] {
+        for row in table {
+            let fields = [
+                row.url.to_string(),
+                status.to_string(),
+                if row.marker { "set" } else { "not set" }.to_string(),
+                row.errors
+                    .iter()
+                    .map(|e| format!("{e:?}"))
+                    .collect::<Vec<String>>()
+                    .join(" "),
+                join_reasons(row.valid_reason.as_ref()),
+                join_reasons(row.invalid_reason.as_ref()),
+                row.aliases
+                    .iter()
+                    .map(url::Url::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+                row.linked_from
+                    .iter()
+                    .map(url::Url::as_str)
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+                row.load_time_ms
+                    .map(|load_time_ms| load_time_ms.to_string())
+                    .unwrap_or_default(),
+                row.load_time_regressed.to_string(),
+                row.failed_assets.join(" "),
+            ];
+            let line: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+            writeln!(csv, "{}", line.join(","))?;
+        }
+    }
+
+    let report_csv_path = paths.get(Locations::ReportCsv);
+    std::fs::write(&report_csv_path, csv)
+        .with_context(|| format!("Failed to write CSV report: {report_csv_path:?}"))?;
+
+    Ok(report_csv_path)
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Why a failed JUnit test case failed, its errors and invalid reasons.
+fn failure_message(entry: &ReportEntry) -> String {
+    let mut reasons: Vec<String> = entry.errors.iter().map(|e| format!("{e:?}")).collect();
+    if let Some(invalid_reasons) = &entry.invalid_reason {
+        reasons.extend(invalid_reasons.iter().map(|reason| format!("{reason:?}")));
+    }
+
+    if reasons.is_empty() {
+        "No reason recorded".to_string()
+    } else {
+        reasons.join(", ")
+    }
+}
+
+/// One test suite per report table and one test case per URL, classed by host so CI
+/// groups them by site. Error and unknown URLs fail.
+pub(crate) fn gen_junit_report(tables: &Tables, path: &Path) -> anyhow::Result<PathBuf> {
+    let suites = [
+        ("error", &tables.error, true),
+        ("unknown", &tables.unknown, true),
+        ("hash_only", &tables.hash_only, false),
+        ("valid", &tables.valid, false),
+        ("first_seen", &tables.first_seen, false),
+        ("ignored", &tables.ignored, false),
+        ("not_in_source", &tables.not_in_source, false),
+    ];
+    let tests: usize = suites.iter().map(|(_, table, _)| table.len()).sum();
+    let failures = tables.error.len() + tables.unknown.len();
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    writeln!(
+        xml,
+        "<testsuites name=\"link_rustler\" tests=\"{tests}\" failures=\"{failures}\">"
+    )?;
+    for (status, table, failing) in suites {
+        writeln!(
+            xml,
+            "  <testsuite name=\"{status}\" tests=\"{}\" failures=\"{}\">",
+            table.len(),
+            if failing { table.len() } else { 0 }
+        )?;
+        for entry in table {
+            write!(
+                xml,
+                "    <testcase classname=\"{}\" name=\"{}\"",
+                xml_escape(entry.url.host_str().unwrap_or(entry.url.scheme())),
+                xml_escape(entry.url.as_str())
+            )?;
+            if !failing {
+                writeln!(xml, "/>")?;
+                continue;
+            }
+
+            let mut details = vec![];
+            if let Some(status_code) = entry.status_code {
+                details.push(format!("HTTP status {status_code}"));
+            }
+            if let Some(final_url) = &entry.final_url {
+                details.push(format!("Ended up at {final_url}"));
+            }
+            if let Some(reason) = &entry.validator_reason {
+                details.push(format!("Validator: {reason}"));
+            }
+            writeln!(xml, ">")?;
+            writeln!(
+                xml,
+                "      <failure message=\"{}\" type=\"{status}\">{}</failure>",
+                xml_escape(&failure_message(entry)),
+                xml_escape(&details.join("\n"))
+            )?;
+            writeln!(xml, "    </testcase>")?;
+        }
+        writeln!(xml, "  </testsuite>")?;
+    }
+    writeln!(xml, "</testsuites>")?;
+
+    std::fs::write(path, xml).with_context(|| format!("Failed to write JUnit report: {path:?}"))?;
+
+    Ok(path.to_path_buf())
+}
+
+pub(crate) fn gen_json_report(entries: &[ReportEntry], paths: &Paths) -> anyhow::Result<PathBuf> {
+    let json = serde_json::to_string_pretty(entries).context("Failed to serialize report")?;
+
+    let report_json_path = paths.get(Locations::ReportJson);
+    std::fs::write(&report_json_path, json)
+        .with_context(|| format!("Failed to write JSON report: {report_json_path:?}"))?;
+
+    Ok(report_json_path)
+}
+
+fn gen_html_report(tables: Tables, config: &Config, paths: &Paths) -> anyhow::Result<PathBuf> {
+    let mut root_buf = Buffer::new();
+    root_buf.doctype();
+    let mut html = root_buf.html().attr("lang='en'");
+    let mut head = html.head();
+    writeln!(head.title(), "Results!")?;
+    let _ = head.meta().attr("charset='UTF-8'");
+    let _ = head
+        .meta()
+        .attr("name='viewport'")
+        .attr("content='width=device-width, initial-scale=1.0'");
+
+    // Raw, a custom stylesheet can use child selectors
+    writeln!(head.style().raw(), "{}", stylesheet(config))?;
+    let mut body = html.body();
+
+    writeln!(body.h1(), "Results")?;
+    if let Some(last_run) = &tables.last_run {
+        mk_run_summary(&mut body, last_run)?;
+    }
+    mk_summary(&mut body, &tables)?;
+    match disc_op::measure_pages_dir_size(paths) {
+        Ok(usage) => writeln!(
+            body.p(),
+            "Local records use {}",
+            utilities::format_bytes(usage.total)
+        )?,
+        Err(e) => warn!("Failed to measure local records: {e:?}"),
+    }
+
+    let counts = domain_counts(&tables);
     for (title, table) in [
         ("Error", tables.error),
         ("Unknown", tables.unknown),
         ("Hash Only", tables.hash_only),
         ("Valid", tables.valid),
+        ("First seen", tables.first_seen),
+        ("Ignored", tables.ignored),
+        ("No longer in source", tables.not_in_source),
     ] {
+        if config.report_group_by_domain {
+            mk_grouped_table(&mut body, title, table, &counts, true, paths)?;
+        } else {
+            mk_table(&mut body, title, table, true, paths)?;
+        }
+    }
+    if let Some(last_run) = &tables.last_run {
+        mk_skipped_table(&mut body, &last_run.skipped)?;
+    }
+    // Written raw, the default escaping would break the script
+    if config.report_interactive {
+        writeln!(body.script().raw(), "{SCRIPT}")?;
+    }
+
+    save_report(root_buf, paths)
+}
+
+/// Sorts every URL of either run by how it changed. A URL that left the source is
+/// removed even while its stored data lingers. URLs the include/exclude patterns kept
+/// out of one of the runs are neither new nor removed, they are only listed.
+pub(crate) fn diff_runs(from: &RunSummary, to: &RunSummary) -> RunDiff {
+    let in_source = |summary: &RunSummary, url: &url::Url| {
+        summary
+            .statuses
+            .get(url)
+            .copied()
+            .filter(|status| *status != ReportStatus::NotInSource)
+    };
+    let urls: BTreeSet<&url::Url> = from
+        .statuses
+        .keys()
+        .chain(to.statuses.keys())
+        .chain(from.skipped.keys())
+        .chain(to.skipped.keys())
+        .collect();
+
+    let mut diff = RunDiff::default();
+    for url in urls {
+        let list = match (in_source(from, url), in_source(to, url)) {
+            (Some(before), Some(after)) => {
+                match (before == ReportStatus::Error, after == ReportStatus::Error) {
+                    (false, true) => &mut diff.newly_broken,
+                    (true, false) => &mut diff.recovered,
+                    _ => continue,
+                }
+            }
+            (None, Some(_)) if from.skipped.contains_key(url) => &mut diff.filtered_in_one,
+            (None, Some(_)) => &mut diff.new_urls,
+            (Some(_), None) if to.skipped.contains_key(url) => &mut diff.filtered_in_one,
+            (Some(_), None) => &mut diff.removed_urls,
+            (None, None) => continue,
+        };
+        list.push(url.clone());
+    }
+
+    diff
+}
+
+/// A report of only what changed between two runs, the rows show what is stored for
+/// each URL now. URLs forgotten since are listed without details.
+pub fn gen_diff_report(
+    from: &RunSummary,
+    to: &RunSummary,
+    page_datas: BTreeMap<url::Url, PageData>,
+    config: &Config,
+    paths: &Paths,
+) -> anyhow::Result<(RunDiff, PathBuf)> {
+    let diff = diff_runs(from, to);
+    let mut entries: BTreeMap<url::Url, ReportEntry> = classify(page_datas, config)
+        .into_iter()
+        .map(|entry| (entry.url.clone(), entry))
+        .collect();
+
+    let mut root_buf = Buffer::new();
+    root_buf.doctype();
+    let mut html = root_buf.html().attr("lang='en'");
+    let mut head = html.head();
+    writeln!(head.title(), "Changes")?;
+    let _ = head.meta().attr("charset='UTF-8'");
+    writeln!(head.style().raw(), "{}", stylesheet(config))?;
+    let mut body = html.body();
+
+    writeln!(body.h1(), "Changes")?;
+    writeln!(
+        body.p(),
+        "From the run started {} to the run started {}: {} newly broken, {} recovered, \
+        {} new, {} removed",
+        from.started.to_rfc2822(),
+        to.started.to_rfc2822(),
+        diff.newly_broken.len(),
+        diff.recovered.len(),
+        diff.new_urls.len(),
+        diff.removed_urls.len()
+    )?;
+
+    for (title, urls) in [
+        ("Newly broken", &diff.newly_broken),
+        ("Recovered", &diff.recovered),
+        ("New URLs", &diff.new_urls),
+        ("Removed URLs", &diff.removed_urls),
+    ] {
+        if urls.is_empty() {
+            continue;
+        }
+
+        let (rows, forgotten): (Vec<_>, Vec<_>) = urls
+            .iter()
+            .map(|url| entries.remove(url).ok_or(url))
+            .partition(Result::is_ok);
         mk_table(
             &mut body,
             title,
-            table,
-            Some(&format!("{:?}", get_loc(crate::Locations::PagesSubdir))),
-        )
-        .unwrap();
+            rows.into_iter().filter_map(Result::ok).collect(),
+            true,
+            paths,
+        )?;
+        if !forgotten.is_empty() {
+            writeln!(body.p(), "No longer in the data store:")?;
+            let mut ul = body.ul();
+            for url in forgotten.into_iter().filter_map(Result::err) {
+                writeln!(ul.li(), "{url}")?;
+            }
+        }
+    }
+
+    if !diff.filtered_in_one.is_empty() {
+        writeln!(body.h2(), "Filtered out of one of the runs")?;
+        let mut ul = body.ul();
+        for url in &diff.filtered_in_one {
+            writeln!(ul.li(), "{url}")?;
+        }
+    }
+    if config.report_interactive {
+        writeln!(body.script().raw(), "{SCRIPT}")?;
     }
 
-    save_report(root_buf);
+    let path = paths.get(Locations::ReportDiff);
+    std::fs::write(&path, root_buf.finish())
+        .with_context(|| format!("Failed to write the changes report: {path:?}"))?;
+
+    Ok((diff, path))
+}
+
+/// Reports on `page_datas` as given, pass `DataStore::snapshot` for the stored results.
+/// Fails if any of the configured report formats couldn't be written.
+pub fn gen_post_run_report(
+    page_datas: BTreeMap<url::Url, PageData>,
+    config: &Config,
+    paths: &Paths,
+) -> anyhow::Result<ReportSummary> {
+    if config.report_formats.contains(&ReportFormat::Html) {
+        gen_details_pages(&page_datas, config, paths)?;
+    }
+
+    let entries = classify(page_datas, config);
+    let mut written = vec![];
+
+    if config.report_formats.contains(&ReportFormat::Json) {
+        written.push(gen_json_report(&entries, paths)?);
+    }
+
+    let mut tables = split_tables(entries);
+    match disc_op::load_latest_run_summary(paths) {
+        Ok(last_run) => tables.last_run = last_run,
+        Err(e) => error!("{e:?}"),
+    }
+
+    if config.report_formats.contains(&ReportFormat::Csv) {
+        written.push(gen_csv_report(&tables, paths)?);
+    }
+    if let Some(junit_path) = &config.junit_path {
+        written.push(gen_junit_report(&tables, Path::new(junit_path))?);
+    }
+
+    let mut summary = ReportSummary {
+        error: tables.error.len(),
+        unknown: tables.unknown.len(),
+        hash_only: tables.hash_only.len(),
+        valid: tables.valid.len(),
+        first_seen: tables.first_seen.len(),
+        ignored: tables.ignored.len(),
+        not_in_source: tables.not_in_source.len(),
+        written,
+    };
+
+    if config.report_formats.contains(&ReportFormat::Html) {
+        for entry in tables
+            .error
+            .iter()
+            .chain(&tables.unknown)
+            .chain(&tables.hash_only)
+            .chain(&tables.valid)
+            .chain(&tables.first_seen)
+            .chain(&tables.ignored)
+            .chain(&tables.not_in_source)
+        {
+            if let Err(e) = disc_op::refresh_thumbnail(&entry.url, paths) {
+                error!("{e:?}");
+            }
+        }
+        summary
+            .written
+            .push(gen_html_report(tables, config, paths)?);
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use image_hasher::ImageHash;
+
+    use super::*;
+    use crate::{
+        enums::CustomError,
+        structs::IgnoreRules,
+        test_support::{failed_state, page_data, run_summary, state, url},
+    };
+
+    fn screenshot(hash: &str) -> State {
+        let mut state = state("<html></html>");
+        state.screenshot_hash = Some(hash.to_string());
+        state
+    }
+
+    fn decodable(byte: u8) -> String {
+        ImageHash::<Box<[u8]>>::from_bytes(&[byte; 8])
+            .unwrap()
+            .to_base64()
+    }
+
+    fn classify_one(history: Vec<State>) -> ReportEntry {
+        let page_datas = BTreeMap::from([(
+            url("https://example.com/"),
+            page_data("https://example.com/", history),
+        )]);
+        classify(page_datas, &Config::default()).remove(0)
+    }
+
+    fn screenshot_invalid(entry: &ReportEntry) -> bool {
+        entry
+            .invalid_reason
+            .as_ref()
+            .is_some_and(|reasons| reasons.contains(&InvalidReason::ScreenshotHash))
+    }
+
+    /// Half the history agrees, below the default confidence of 60.
+    fn unsure_history() -> Vec<State> {
+        vec![
+            screenshot(&decodable(0)),
+            screenshot(&decodable(0)),
+            screenshot(&decodable(0x0f)),
+            screenshot(&decodable(0xf0)),
+        ]
+    }
+
+    fn rendered_in(locale: &str, content: &str) -> State {
+        let mut state = state(content);
+        state.browser_locale = Some(locale.to_string());
+        state
+    }
+
+    fn same_page(checks: usize) -> Vec<State> {
+        (0..checks).map(|_| state("<p>Unchanged</p>")).collect()
+    }
+
+    fn changed_page(checks: usize) -> Vec<State> {
+        let mut history = same_page(checks - 1);
+        history.push(state("<p>Changed</p>"));
+        history
+    }
+
+    fn table_urls(table: &[ReportEntry]) -> Vec<&str> {
+        table.iter().map(|entry| entry.url.path()).collect()
+    }
+
+    /// Paths in `dir` with the data directory the reports go to in place.
+    fn report_paths(dir: &std::path::Path) -> Paths {
+        let paths = Paths::new(dir.join("config"), dir.join("data"));
+        std::fs::create_dir_all(paths.get(Locations::BaseData)).unwrap();
+        paths
+    }
+
+    fn only(format: ReportFormat) -> Config {
+        Config {
+            report_formats: vec![format],
+            ..Config::default()
+        }
+    }
+
+    /// Log output written while a test runs, see `stylesheet_logging`.
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// The stylesheet of `config` and what was logged while making it.
+    fn stylesheet_logging(config: &Config) -> (String, String) {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let css = tracing::subscriber::with_default(subscriber, || stylesheet(config));
+        let log = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        (css, log)
+    }
+
+    #[test]
+    fn the_report_uses_the_colors_of_its_theme() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        for (theme, dark, light) in [
+            (ReportTheme::Dark, true, true),
+            (ReportTheme::Light, false, true),
+            (ReportTheme::Auto, true, true),
+        ] {
+            let config = Config {
+                report_theme: theme,
+                ..Config::default()
+            };
+            let _ = gen_post_run_report(BTreeMap::new(), &config, &paths).unwrap();
+
+            let html = std::fs::read_to_string(paths.get(Locations::Report)).unwrap();
+            assert!(html.contains(CSS), "{theme:?}");
+            assert_eq!(html.contains(DARK_THEME), dark, "{theme:?}");
+            assert_eq!(html.contains(LIGHT_THEME), light, "{theme:?}");
+        }
+        let light = stylesheet(&Config {
+            report_theme: ReportTheme::Light,
+            ..Config::default()
+        });
+        assert!(!light.contains("@media"));
+        let auto = stylesheet(&Config {
+            report_theme: ReportTheme::Auto,
+            ..Config::default()
+        });
+        assert!(auto.contains("prefers-color-scheme: light"));
+    }
+
+    #[test]
+    fn custom_css_comes_after_the_theme() {
+        let dir = tempfile::tempdir().unwrap();
+        let custom = dir.path().join("custom.css");
+        std::fs::write(&custom, "td { font-size: 9pt; }").unwrap();
+        let config = Config {
+            report_custom_css_path: Some(custom.display().to_string()),
+            ..Config::default()
+        };
+
+        let (css, log) = stylesheet_logging(&config);
+        assert!(css.ends_with("td { font-size: 9pt; }"), "{css}");
+        assert!(!log.contains("WARN"), "{log}");
+    }
+
+    #[test]
+    fn a_missing_custom_css_file_is_only_a_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        let config = Config {
+            report_custom_css_path: Some(dir.path().join("missing.css").display().to_string()),
+            ..Config::default()
+        };
+
+        let (css, log) = stylesheet_logging(&config);
+        assert_eq!(css, stylesheet(&Config::default()));
+        assert!(log.contains("WARN"), "{log}");
+        assert!(log.contains("Failed to read custom report CSS"), "{log}");
+        assert!(gen_post_run_report(BTreeMap::new(), &config, &paths).is_ok());
+    }
+
+    #[test]
+    fn an_empty_csv_report_has_only_the_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+
+        let summary =
+            gen_post_run_report(BTreeMap::new(), &only(ReportFormat::Csv), &paths).unwrap();
+
+        assert_eq!(summary.written, [paths.get(Locations::ReportCsv)]);
+        assert_eq!(
+            std::fs::read_to_string(paths.get(Locations::ReportCsv)).unwrap(),
+            "url,status,marker,error,valid_reasons,invalid_reasons,aliases,linked_from,\
+             load_time_ms,load_time_regressed,failed_assets\n"
+        );
+        assert!(!paths.get(Locations::Report).exists());
+    }
+
+    #[test]
+    fn a_report_is_written_from_the_map_it_is_given() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        let config = Config {
+            report_formats: vec![ReportFormat::Html, ReportFormat::Csv, ReportFormat::Json],
+            ..Config::default()
+        };
+        let page_datas = BTreeMap::from([
+            (
+                url("https://example.com/fine"),
+                page_data("https://example.com/fine", same_page(3)),
+            ),
+            (
+                url("https://example.com/broken"),
+                page_data(
+                    "https://example.com/broken",
+                    vec![failed_state(CustomError::ConnectionError)],
+                ),
+            ),
+        ]);
+
+        let summary = gen_post_run_report(page_datas, &config, &paths).unwrap();
+
+        assert_eq!((summary.valid, summary.error), (1, 1));
+        assert_eq!(
+            summary.written,
+            [
+                paths.get(Locations::ReportJson),
+                paths.get(Locations::ReportCsv),
+                paths.get(Locations::Report),
+            ]
+        );
+        let html = std::fs::read_to_string(paths.get(Locations::Report)).unwrap();
+        assert!(html.contains("https://example.com/broken"));
+        // Nothing is read from or written to a data store
+        assert!(!paths.get(Locations::DataStore).exists());
+    }
+
+    #[test]
+    fn an_unwritable_report_directory_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Paths::new(dir.path().join("config"), dir.path().join("data"));
+        // A file where the data directory should be
+        std::fs::write(paths.get(Locations::BaseData), "").unwrap();
+
+        for format in [ReportFormat::Html, ReportFormat::Csv, ReportFormat::Json] {
+            let page_datas = BTreeMap::from([(
+                url("https://example.com/"),
+                page_data("https://example.com/", same_page(2)),
+            )]);
+            let result = gen_post_run_report(page_datas, &only(format), &paths);
+            assert!(result.is_err(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn junit_reports_fail_error_and_unknown_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        let junit_path = dir.path().join("junit.xml");
+        let config = Config {
+            report_formats: vec![],
+            junit_path: Some(junit_path.display().to_string()),
+            ..Config::default()
+        };
+        let mut missing = failed_state(CustomError::HttpClientError);
+        missing.status_code = Some(404);
+        let mut validated = failed_state(CustomError::CustomValidatorFailed);
+        validated.validator_reason = Some("Expected <main> & a \"title\"".to_string());
+        let page_datas = BTreeMap::from([
+            (
+                url("https://example.com/search?q=a&b=<c>"),
+                page_data("https://example.com/search?q=a&b=<c>", vec![missing]),
+            ),
+            (
+                url("https://example.com/form"),
+                page_data("https://example.com/form", vec![validated]),
+            ),
+            (
+                url("https://example.com/changed"),
+                page_data("https://example.com/changed", changed_page(2)),
+            ),
+            (
+                url("https://example.com/fine"),
+                page_data("https://example.com/fine", same_page(3)),
+            ),
+        ]);
+
+        let summary = gen_post_run_report(page_datas, &config, &paths).unwrap();
+
+        assert_eq!(summary.written, std::slice::from_ref(&junit_path));
+        assert_eq!(
+            std::fs::read_to_string(&junit_path).unwrap(),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="link_rustler" tests="4" failures="3">
+  <testsuite name="error" tests="2" failures="2">
+    <testcase classname="example.com" name="https://example.com/form">
+      <failure message="CustomValidatorFailed" type="error">Validator: Expected &lt;main&gt; &amp; a &quot;title&quot;</failure>
+    </testcase>
+    <testcase classname="example.com" name="https://example.com/search?q=a&amp;b=%3Cc%3E">
+      <failure message="HttpClientError" type="error">HTTP status 404</failure>
+    </testcase>
+  </testsuite>
+  <testsuite name="unknown" tests="1" failures="1">
+    <testcase classname="example.com" name="https://example.com/changed">
+      <failure message="PageHash" type="unknown"></failure>
+    </testcase>
+  </testsuite>
+  <testsuite name="hash_only" tests="0" failures="0">
+  </testsuite>
+  <testsuite name="valid" tests="1" failures="0">
+    <testcase classname="example.com" name="https://example.com/fine"/>
+  </testsuite>
+  <testsuite name="first_seen" tests="0" failures="0">
+  </testsuite>
+  <testsuite name="ignored" tests="0" failures="0">
+  </testsuite>
+  <testsuite name="not_in_source" tests="0" failures="0">
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    fn url_paths(urls: &[url::Url]) -> Vec<&str> {
+        urls.iter().map(url::Url::path).collect()
+    }
+
+    /// A run that ended with `statuses` and kept `skipped` out.
+    fn run(statuses: &[(&str, ReportStatus)], skipped: &[&str]) -> RunSummary {
+        let mut summary = run_summary();
+        summary.statuses = statuses
+            .iter()
+            .map(|(page, status)| (url(page), *status))
+            .collect();
+        summary.skipped = skipped
+            .iter()
+            .map(|page| (url(page), "excluded".to_string()))
+            .collect();
+        summary
+    }
+
+    #[test]
+    fn every_transition_between_runs_is_sorted_out() {
+        use ReportStatus::{Error, HashOnly, NotInSource, Unknown, Valid};
+
+        let from = run(
+            &[
+                ("https://example.com/breaks", Valid),
+                ("https://example.com/recovers", Error),
+                ("https://example.com/still-broken", Error),
+                ("https://example.com/changes", Valid),
+                ("https://example.com/removed", Valid),
+                ("https://example.com/left-source", Unknown),
+                ("https://example.com/filtered-out", Valid),
+            ],
+            &["https://example.com/filtered-in"],
+        );
+        let to = run(
+            &[
+                ("https://example.com/breaks", Error),
+                ("https://example.com/recovers", HashOnly),
+                ("https://example.com/still-broken", Error),
+                ("https://example.com/changes", Unknown),
+                ("https://example.com/left-source", NotInSource),
+                ("https://example.com/filtered-in", Error),
+                ("https://example.com/new", Error),
+            ],
+            &["https://example.com/filtered-out"],
+        );
+
+        let diff = diff_runs(&from, &to);
+        assert_eq!(url_paths(&diff.newly_broken), ["/breaks"]);
+        assert_eq!(url_paths(&diff.recovered), ["/recovers"]);
+        assert_eq!(url_paths(&diff.new_urls), ["/new"]);
+        assert_eq!(url_paths(&diff.removed_urls), ["/left-source", "/removed"]);
+        assert_eq!(
+            url_paths(&diff.filtered_in_one),
+            ["/filtered-in", "/filtered-out"]
+        );
+
+        // Going back the other way swaps every list
+        let back = diff_runs(&to, &from);
+        assert_eq!(back.newly_broken, diff.recovered);
+        assert_eq!(back.recovered, diff.newly_broken);
+        assert_eq!(back.new_urls, diff.removed_urls);
+        assert_eq!(back.removed_urls, diff.new_urls);
+        assert_eq!(back.filtered_in_one, diff.filtered_in_one);
+    }
+
+    #[test]
+    fn csv_rows_quote_fields_with_commas() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        let mut broken = page_data(
+            "https://example.com/a,b",
+            vec![failed_state(CustomError::ConnectionError)],
+        );
+        broken.marker = Some("Welcome".to_string());
+        let page_datas = BTreeMap::from([
+            (url("https://example.com/a,b"), broken),
+            (
+                url("https://example.com/fine"),
+                page_data("https://example.com/fine", same_page(3)),
+            ),
+        ]);
+
+        let _ = gen_post_run_report(page_datas, &only(ReportFormat::Csv), &paths).unwrap();
+
+        let csv = std::fs::read_to_string(paths.get(Locations::ReportCsv)).unwrap();
+        let rows: Vec<&str> = csv.lines().skip(1).collect();
+        assert_eq!(rows.len(), 2, "{csv}");
+        assert!(
+            rows[0].starts_with("\"https://example.com/a,b\",error,set,ConnectionError,"),
+            "{csv}"
+        );
+        assert!(
+            rows[1].starts_with("https://example.com/fine,valid,not set,,"),
+            "{csv}"
+        );
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn a_failed_local_record_is_flagged_in_the_html_report() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = report_paths(dir.path());
+        let mut unsaved = state("<p>Page</p>");
+        unsaved.local_record_error = Some("Failed to create directory: 'pages'".to_string());
+        let page_datas = BTreeMap::from([
+            (
+                url("https://example.com/unsaved"),
+                page_data("https://example.com/unsaved", vec![unsaved]),
+            ),
+            (
+                url("https://example.com/saved"),
+                page_data("https://example.com/saved", vec![state("<p>Page</p>")]),
+            ),
+        ]);
+
+        let _ = gen_post_run_report(page_datas, &only(ReportFormat::Html), &paths).unwrap();
+
+        let html = std::fs::read_to_string(paths.get(Locations::Report)).unwrap();
+        assert_eq!(html.matches("(save failed, may be stale)").count(), 1);
+        assert!(html.contains("Failed to create directory: &#39;pages&#39;"));
+    }
+
+    #[test]
+    fn a_redirected_page_reports_where_it_ended_up() {
+        let mut redirected = failed_state(CustomError::Redirected);
+        redirected.final_url = Some(url("https://example.com/moved"));
+        let mut same = state("<p>Page</p>");
+        same.final_url = Some(url("https://example.com/"));
+
+        let entry = classify_one(vec![redirected]);
+        assert_eq!(entry.final_url, Some(url("https://example.com/moved")));
+        assert_eq!(entry.errors, [CustomError::Redirected]);
+        assert_eq!(classify_one(vec![same]).final_url, None);
+    }
+
+    #[test]
+    fn a_tie_goes_to_the_most_recent_value() {
+        assert_eq!(mode(&["a", "b"]).value, Some("b"));
+        assert_eq!(mode(&["a", "b", "b", "a"]).value, Some("a"));
+        assert_eq!(mode(&["b", "a", "a", "b", "c"]).value, Some("b"));
+
+        let tied = mode(&["a", "a", "b", "b"]);
+        assert_eq!(tied.value, Some("b"));
+        assert_eq!(tied.confidence, Some(50));
+        assert_eq!(mode::<&str>(&[]).value, None);
+    }
+
+    #[test]
+    fn a_tied_history_always_compares_against_the_newer_page() {
+        let older = state("<p>Older</p>");
+        let newer = state("<p>Newer</p>");
+        let history = vec![older.clone(), older, newer.clone(), newer.clone()];
+
+        for _ in 0..20 {
+            assert_eq!(
+                diff_report(&history).page_hash.value.as_ref(),
+                Some(&newer.hash)
+            );
+        }
+    }
+
+    #[test]
+    fn tolerances_around_small_targets_do_not_underflow() {
+        assert!(within(0, 120, 300));
+        assert!(within(420, 120, 300));
+        assert!(!within(421, 120, 300));
+        assert!(within(usize::MAX, usize::MAX - 1, 300));
+    }
+
+    #[test]
+    fn a_zero_tolerance_only_accepts_the_target() {
+        assert_eq!(compression_tolerance(1000, 0, 0.0), 0);
+        assert!(within(1000, 1000, 0));
+        assert!(!within(999, 1000, 0));
+        assert!(!within(1001, 1000, 0));
+    }
+
+    #[test]
+    fn the_looser_of_both_tolerances_applies() {
+        // 2.5 % of 1 KB is less than the absolute 300 bytes, of 1 MB it is more
+        assert_eq!(compression_tolerance(1000, 300, 2.5), 300);
+        assert_eq!(compression_tolerance(1_000_000, 300, 2.5), 25_000);
+        assert_eq!(compression_tolerance(1000, 0, 2.5), 25);
+        assert!(within(1025, 1000, 25));
+        assert!(!within(1026, 1000, 25));
+        // Halves round away from zero
+        assert_eq!(compression_tolerance(10, 0, 5.0), 1);
+        assert_eq!(compression_tolerance(10, 0, 4.9), 0);
+        assert_eq!(compression_tolerance(1000, 10, -50.0), 10);
+        assert_eq!(compression_tolerance(usize::MAX, 0, 100.0), usize::MAX);
+    }
+
+    fn compressed_to(length: usize) -> State {
+        let mut state = state("<p>Unchanged</p>");
+        state.compress_length = length;
+        state
+    }
+
+    #[test]
+    fn a_percentage_tolerance_decides_the_compression_reason() {
+        let history = || {
+            let mut history: Vec<State> = (0..4).map(|_| compressed_to(100_000)).collect();
+            history.push(compressed_to(102_000));
+            history
+        };
+        let classify_with = |percent| {
+            let config = Config {
+                compression_tolerance_percent: percent,
+                ..Config::default()
+            };
+            let page_datas = BTreeMap::from([(
+                url("https://example.com/"),
+                page_data("https://example.com/", history()),
+            )]);
+            classify(page_datas, &config).remove(0)
+        };
+
+        let loose = classify_with(2.0);
+        assert_eq!(loose.status, ReportStatus::Valid);
+        assert!(loose
+            .valid_reason
+            .is_some_and(|reasons| reasons.contains(&ValidReason::CompressionWithinTolerance)));
+        let tight = classify_with(1.99);
+        assert!(tight
+            .invalid_reason
+            .is_some_and(|reasons| reasons.contains(&InvalidReason::Compression)));
+    }
+
+    #[test]
+    fn every_error_of_a_check_is_reported() {
+        let mut last = state("<p>Unchanged</p>");
+        last.errors = vec![CustomError::Redirected, CustomError::MarkerNotFound];
+        let mut history = same_page(4);
+        history.push(last);
+
+        let entry = classify_one(history);
+        assert_eq!(entry.status, ReportStatus::Error);
+        assert_eq!(
+            entry.errors,
+            [CustomError::Redirected, CustomError::MarkerNotFound]
+        );
+    }
+
+    #[test]
+    fn histories_of_any_length_land_in_a_table() {
+        let mut empty = page_data("https://example.com/empty", vec![state("")]);
+        empty.history.clear();
+        let page_datas = BTreeMap::from([
+            (url("https://example.com/empty"), empty),
+            (
+                url("https://example.com/one"),
+                page_data("https://example.com/one", same_page(1)),
+            ),
+            (
+                url("https://example.com/one-failed"),
+                page_data(
+                    "https://example.com/one-failed",
+                    vec![failed_state(CustomError::PageLoadTimeout)],
+                ),
+            ),
+            (
+                url("https://example.com/two"),
+                page_data("https://example.com/two", same_page(2)),
+            ),
+            (
+                url("https://example.com/two-changed"),
+                page_data("https://example.com/two-changed", changed_page(2)),
+            ),
+            (
+                url("https://example.com/six"),
+                page_data("https://example.com/six", same_page(6)),
+            ),
+            (
+                url("https://example.com/six-changed"),
+                page_data("https://example.com/six-changed", changed_page(6)),
+            ),
+        ]);
+
+        let tables = split_tables(classify(page_datas, &Config::default()));
+        assert_eq!(table_urls(&tables.first_seen), ["/one"]);
+        assert_eq!(table_urls(&tables.error), ["/one-failed"]);
+        assert_eq!(table_urls(&tables.valid), ["/six", "/two"]);
+        // One earlier check is too short a history to tell a change from flapping
+        assert_eq!(table_urls(&tables.unknown), ["/two-changed"]);
+        assert_eq!(table_urls(&tables.hash_only), ["/six-changed"]);
+    }
+
+    #[test]
+    fn short_configured_histories_still_classify() {
+        for (history_length, status) in [
+            (1, ReportStatus::FirstSeen),
+            (2, ReportStatus::HashOnly),
+            (5, ReportStatus::HashOnly),
+        ] {
+            let config = Config {
+                history_length,
+                ..Config::default()
+            };
+            let mut checks = changed_page(6).into_iter();
+            let mut page_data = page_data("https://example.com/", vec![checks.next().unwrap()]);
+            for check in checks {
+                page_data.update(check, history_length);
+            }
+            assert_eq!(page_data.history.len(), history_length);
+
+            let page_datas = BTreeMap::from([(url("https://example.com/"), page_data)]);
+            let entry = classify(page_datas, &config).remove(0);
+            assert_eq!(entry.status, status, "history_length {history_length}");
+        }
+    }
+
+    /// Five checks of one page, then one where the source, screenshot and title changed.
+    fn changed_everywhere() -> Vec<State> {
+        let check = |content: &str, title: &str, screenshot_byte| {
+            let mut check = state(content);
+            check.screenshot_hash = Some(decodable(screenshot_byte));
+            check.title = Some(title.to_string());
+            check
+        };
+        let mut history: Vec<State> = (0..5).map(|_| check("<p>Old</p>", "Old", 0)).collect();
+        history.push(check("<p>New</p>", "New", 0xff));
+        history
+    }
+
+    #[test]
+    fn an_ignored_difference_never_makes_a_page_invalid() {
+        for rules in 0..8 {
+            let ignore = IgnoreRules {
+                page_hash: rules & 1 != 0,
+                screenshot: rules & 2 != 0,
+                title: rules & 4 != 0,
+                skip: false,
+            };
+            let mut page_data = page_data("https://example.com/", changed_everywhere());
+            page_data.ignore = ignore;
+            let page_datas = BTreeMap::from([(url("https://example.com/"), page_data)]);
+            let entry = classify(page_datas, &Config::default()).remove(0);
+
+            let reasons = entry.invalid_reason.unwrap_or_default();
+            let ignored = |reason: &InvalidReason| match reason {
+                InvalidReason::PageHash | InvalidReason::Compression | InvalidReason::TextHash => {
+                    ignore.page_hash
+                }
+                InvalidReason::ScreenshotHash => ignore.screenshot,
+                InvalidReason::Title => ignore.title,
+                _ => false,
+            };
+            assert!(!reasons.iter().any(ignored), "{ignore:?}: {reasons:?}");
+            if rules == 0 {
+                for reason in [
+                    InvalidReason::PageHash,
+                    InvalidReason::ScreenshotHash,
+                    InvalidReason::Title,
+                ] {
+                    assert!(reasons.contains(&reason), "{reasons:?}");
+                }
+            }
+            assert_eq!(
+                entry.status == ReportStatus::Valid,
+                rules == 7,
+                "{ignore:?}: {reasons:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn a_skipped_page_goes_to_the_ignored_table() {
+        let mut page_data = page_data("https://example.com/", changed_everywhere());
+        page_data.ignore.skip = true;
+        let page_datas = BTreeMap::from([(url("https://example.com/"), page_data)]);
+
+        let tables = split_tables(classify(page_datas, &Config::default()));
+        assert_eq!(table_urls(&tables.ignored), ["/"]);
+        assert!(tables.unknown.is_empty());
+    }
+
+    /// `content` checked `hours` after the first check, which was at 1_700_000_000.
+    fn checked_at(hours: i64, content: &str) -> State {
+        let mut state = state(content);
+        state.check_time =
+            chrono::DateTime::from_timestamp(1_700_000_000 + hours * 3600, 0).unwrap();
+        state
+    }
+
+    #[test]
+    fn the_error_rate_is_the_share_of_failed_checks() {
+        assert!(error_rate(&[]).abs() < f64::EPSILON);
+        let history = [
+            state("a"),
+            failed_state(CustomError::PageError),
+            state("a"),
+            state("a"),
+        ];
+        assert!((error_rate(&history) - 0.25).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn the_last_change_skips_failed_checks_and_other_filters() {
+        let filter_version = |state: &State| state.filter_version.clone().unwrap_or_default();
+        let mut refiltered = checked_at(4, "c");
+        refiltered.filter_version = Some("other".to_string());
+        let mut failed = failed_state(CustomError::PageError);
+        failed.check_time = checked_at(2, "").check_time;
+
+        let history = [
+            checked_at(0, "a"),
+            checked_at(1, "b"),
+            failed,
+            checked_at(3, "b"),
+            refiltered,
+        ];
+        assert_eq!(
+            last_changed(&history, filter_version),
+            Some(checked_at(1, "").check_time)
+        );
+        assert_eq!(last_changed(&history[..1], filter_version), None);
+        assert_eq!(
+            last_changed(&[checked_at(0, "a"), checked_at(1, "a")], filter_version),
+            None
+        );
+    }
+
+    #[test]
+    fn pages_failing_some_of_the_time_are_flaky() {
+        let flaky = |history: Vec<State>| classify_one(history).flaky;
+        let sometimes = vec![failed_state(CustomError::PageError), state("a"), state("a")];
+        assert!(flaky(sometimes));
+        // Under the default 30%
+        let rarely = vec![
+            failed_state(CustomError::PageError),
+            state("a"),
+            state("a"),
+            state("a"),
+        ];
+        assert!(!flaky(rarely));
+        // Always failing is broken, not flaky
+        assert!(!flaky(vec![failed_state(CustomError::PageError); 3]));
+    }
+
+    #[test]
+    fn a_screenshot_masked_differently_is_not_compared() {
+        let mut masked = screenshot(&decodable(0xff));
+        masked.mask_version = Some("carousel".to_string());
+        let mut history = vec![screenshot(&decodable(0)); 4];
+        history.push(masked.clone());
+        assert!(!screenshot_invalid(&classify_one(history)));
+
+        let mut history = vec![masked.clone(); 4];
+        history.push(screenshot(&decodable(0)));
+        history[4].mask_version = masked.mask_version;
+        assert!(screenshot_invalid(&classify_one(history)));
+    }
+
+    fn loaded_in(load_times: &[Option<u64>]) -> Vec<State> {
+        load_times
+            .iter()
+            .map(|load_time_ms| {
+                let mut state = state("<p>Page</p>");
+                state.load_time_ms = *load_time_ms;
+                state
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_median_of_an_even_count_is_the_mean_of_the_middle_two() {
+        assert_eq!(median(&[]), None);
+        assert_eq!(median(&[3.0, 1.0, 2.0]), Some(2.0));
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), Some(2.5));
+    }
+
+    #[test]
+    fn a_load_time_regresses_past_the_factor_times_the_median() {
+        // Median of 200, one slow outlier doesn't move it
+        let history = loaded_in(&[Some(100), Some(200), None, Some(250), Some(5000), Some(150)]);
+        assert!(!load_time_regressed(&history, Some(400), 2.0));
+        assert!(load_time_regressed(&history, Some(401), 2.0));
+        assert!(!load_time_regressed(&history, None, 2.0));
+        assert!(!load_time_regressed(&history, Some(10_000), 0.0));
+
+        let short = loaded_in(&[Some(100), None, Some(100)]);
+        assert!(!load_time_regressed(&short, Some(10_000), 2.0));
+    }
+
+    #[test]
+    fn a_slower_page_stays_valid() {
+        let config = Config {
+            load_time_regression_factor: 2.0,
+            ..Config::default()
+        };
+        let page_datas = BTreeMap::from([(
+            url("https://example.com/"),
+            page_data(
+                "https://example.com/",
+                loaded_in(&[Some(100), Some(100), Some(100), Some(900)]),
+            ),
+        )]);
+
+        let entry = classify(page_datas, &config).remove(0);
+        assert!(entry.load_time_regressed);
+        assert_eq!(entry.load_time_ms, Some(900));
+        assert_eq!(entry.status, ReportStatus::Valid);
+    }
+
+    #[test]
+    fn a_check_in_another_locale_is_not_comparable() {
+        let entry = classify_one(vec![
+            rendered_in("de-DE", "<p>14. November</p>"),
+            rendered_in("de-DE", "<p>14. November</p>"),
+            rendered_in("en-US", "<p>November 14</p>"),
+        ]);
+        assert!(entry.not_comparable);
+        assert_eq!(entry.status, ReportStatus::FirstSeen);
+        assert_eq!(entry.invalid_reason, None);
+    }
+
+    #[test]
+    fn a_change_in_the_same_locale_is_still_compared() {
+        let entry = classify_one(vec![
+            rendered_in("en-US", "<p>November 13</p>"),
+            rendered_in("de-DE", "<p>14. November</p>"),
+            rendered_in("en-US", "<p>November 14</p>"),
+        ]);
+        assert!(!entry.not_comparable);
+        assert_ne!(entry.status, ReportStatus::FirstSeen);
+        assert!(entry
+            .invalid_reason
+            .is_some_and(|reasons| reasons.contains(&InvalidReason::PageHash)));
+    }
+
+    #[test]
+    fn an_undecodable_screenshot_hash_is_not_evaluated_when_unsure() {
+        let mut history = unsure_history();
+        history.push(screenshot("not a hash"));
+
+        let entry = classify_one(history);
+        assert!(!screenshot_invalid(&entry), "{:?}", entry.invalid_reason);
+        assert_eq!(entry.screenshot_distance, None);
+    }
+
+    #[test]
+    fn a_different_screenshot_is_invalid_when_unsure() {
+        let mut history = unsure_history();
+        history.push(screenshot(&decodable(0xff)));
+
+        assert!(screenshot_invalid(&classify_one(history)));
+    }
+
+    /// Mostly the same screenshot, now and then a bit or two off.
+    fn noisy_history() -> Vec<State> {
+        [0x00, 0x00, 0x01, 0x00, 0x00, 0x02, 0x00, 0x00, 0x04, 0x00]
+            .into_iter()
+            .map(|byte| screenshot(&decodable(byte)))
+            .collect()
+    }
+
+    #[test]
+    fn a_noisy_page_learns_a_looser_tolerance() {
+        let mut history = noisy_history();
+        history.push(screenshot(&decodable(0x08)));
+
+        let entry = classify_one(history);
+        assert!(entry.tolerance_learned);
+        assert!(entry.screenshot_tolerance > Config::default().screenshot_diff_tolerance);
+        assert_eq!(entry.screenshot_distance, Some(8));
+        assert!(!screenshot_invalid(&entry), "{:?}", entry.invalid_reason);
+    }
+
+    #[test]
+    fn a_real_change_on_a_noisy_page_is_still_flagged() {
+        let mut history = noisy_history();
+        history.push(screenshot(&decodable(0xff)));
+
+        let entry = classify_one(history);
+        assert!(entry.tolerance_learned);
+        assert!(screenshot_invalid(&entry), "{:?}", entry.valid_reason);
+    }
+
+    #[test]
+    fn the_tolerance_is_only_learned_from_screenshots_taken_the_same_way() {
+        let taken = |byte: u8, full_page: bool, mask: Option<&str>| {
+            let mut state = screenshot(&decodable(byte));
+            state.full_page_screenshot = full_page;
+            state.mask_version = mask.map(str::to_string);
+            state
+        };
+        // Older viewport and masked screenshots are noisy, the full page ones never changed
+        let mut history: Vec<State> = noisy_history()
+            .into_iter()
+            .map(|mut state| {
+                state.full_page_screenshot = false;
+                state
+            })
+            .chain([0x01, 0x02, 0x04, 0x08].map(|byte| taken(byte, true, Some("masked"))))
+            .chain([0x00; 6].map(|byte| taken(byte, true, None)))
+            .collect();
+        history.push(taken(0x03, true, None));
+
+        let entry = classify_one(history);
+        assert_eq!(entry.screenshot_distance, Some(16));
+        assert!(entry.screenshot_tolerance < 16);
+        assert!(screenshot_invalid(&entry), "{:?}", entry.valid_reason);
+    }
 }
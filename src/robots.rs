@@ -0,0 +1,266 @@
+use std::{sync::PoisonError, time::Duration};
+
+use reqwest::{StatusCode, Url};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    structs::{ProxyConfig, RobotsCache, RobotsGroup, RobotsRules},
+    utilities::http_client,
+};
+
+const ROBOTS_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `*` matches any run of characters and a trailing `$` anchors the end, everything
+/// else is literal. Patterns only ever match from the start of the path.
+fn compile_pattern(pattern: &str) -> Option<regex::Regex> {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(pattern) => (pattern, true),
+        None => (pattern, false),
+    };
+
+    let body = pattern
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<String>>()
+        .join(".*");
+    let end = if anchored { "$" } else { "" };
+
+    regex::Regex::new(&format!("^{body}{end}")).ok()
+}
+
+pub fn parse(robots_txt: &str) -> RobotsRules {
+    let mut rules = RobotsRules::default();
+    let mut group = RobotsGroup::default();
+    let mut in_agents = false;
+
+    for line in robots_txt.lines() {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        let Some((field, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+
+        match field.trim().to_lowercase().as_str() {
+            "user-agent" => {
+                // A user-agent after rules starts the next group
+                if !in_agents && !group.agents.is_empty() {
+                    rules.groups.push(std::mem::take(&mut group));
+                }
+                group.agents.push(value.to_lowercase());
+                in_agents = true;
+            }
+            field @ ("allow" | "disallow") => {
+                in_agents = false;
+                // An empty Disallow allows everything, same as no rule at all
+                if value.is_empty() || group.agents.is_empty() {
+                    continue;
+                }
+                if let Some(pattern) = compile_pattern(value) {
+                    group.rules.push((pattern, value.len(), field == "allow"));
+                }
+            }
+            _ => in_agents = false,
+        }
+    }
+
+    if !group.agents.is_empty() {
+        rules.groups.push(group);
+    }
+    rules
+}
+
+/// Follows the usual precedence: only the groups naming the most specific matching agent
+/// apply, falling back to `*`, and within them the longest matching rule wins with
+/// `Allow` winning ties. A URL no rule matches is allowed.
+pub fn is_allowed(rules: &RobotsRules, user_agent: &str, url: &Url) -> bool {
+    let user_agent = user_agent.to_lowercase();
+    let agent_len = |agent: &str| {
+        if agent == "*" {
+            Some(0)
+        } else if user_agent.contains(agent) {
+            Some(agent.len())
+        } else {
+            None
+        }
+    };
+
+    let Some(best) = rules
+        .groups
+        .iter()
+        .flat_map(|group| group.agents.iter())
+        .filter_map(|agent| agent_len(agent))
+        .max()
+    else {
+        return true;
+    };
+
+    let mut path = url.path().to_string();
+    if let Some(query) = url.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+
+    rules
+        .groups
+        .iter()
+        .filter(|group| {
+            group
+                .agents
+                .iter()
+                .any(|agent| agent_len(agent) == Some(best))
+        })
+        .flat_map(|group| group.rules.iter())
+        .filter(|(pattern, _, _)| pattern.is_match(&path))
+        .max_by_key(|(_, len, allow)| (*len, *allow))
+        .is_none_or(|(_, _, allow)| *allow)
+}
+
+/// A robots.txt that is missing or can't be fetched allows everything.
+#[instrument(skip(proxy))]
+async fn fetch(origin: &str, user_agent: &str, proxy: Option<&ProxyConfig>) -> RobotsRules {
+    let robots_url = format!("{origin}/robots.txt");
+    let response = match http_client(proxy).build() {
+        Ok(client) => {
+            client
+                .get(&robots_url)
+                .header(reqwest::header::USER_AGENT, user_agent)
+                .timeout(ROBOTS_TIMEOUT)
+                .send()
+                .await
+        }
+        Err(err) => Err(err),
+    };
+
+    match response {
+        Ok(response) if response.status().is_success() => match response.text().await {
+            Ok(body) => parse(&body),
+            Err(err) => {
+                warn!("Failed to read {robots_url}: {err:?}");
+                RobotsRules::default()
+            }
+        },
+        Ok(response) => {
+            if response.status() != StatusCode::NOT_FOUND {
+                warn!("Unexpected status for {robots_url}: {}", response.status());
+            }
+            RobotsRules::default()
+        }
+        Err(err) => {
+            warn!("Failed to fetch {robots_url}: {err:?}");
+            RobotsRules::default()
+        }
+    }
+}
+
+/// Fetches the robots.txt of each origin once per run.
+pub async fn allowed(
+    cache: &RobotsCache,
+    url: &Url,
+    user_agent: &str,
+    proxy: Option<&ProxyConfig>,
+) -> bool {
+    let origin = url.origin().ascii_serialization();
+
+    let cached = cache
+        .rules
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&origin)
+        .map(|rules| is_allowed(rules, user_agent, url));
+    if let Some(allowed) = cached {
+        return allowed;
+    }
+
+    let rules = fetch(&origin, user_agent, proxy).await;
+    let allowed = is_allowed(&rules, user_agent, url);
+    info!("Fetched robots.txt for {origin}");
+    let _ = cache
+        .rules
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(origin, rules);
+
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROBOTS_TXT: &str = "\
+# Everyone else
+User-agent: *
+Disallow: /private/
+Allow: /private/press/
+Disallow: /*.pdf$
+Disallow: /search?
+
+User-agent: LinkRustler
+User-agent: OtherBot
+Disallow: /drafts/ # not ready
+Allow: /drafts/published
+
+User-agent: LinkRustler-Strict
+Disallow: /
+";
+
+    fn allowed(user_agent: &str, path: &str) -> bool {
+        let url = Url::parse(&format!("https://example.com{path}")).unwrap();
+        is_allowed(&parse(ROBOTS_TXT), user_agent, &url)
+    }
+
+    #[test]
+    fn groups_collect_consecutive_user_agents() {
+        let rules = parse(ROBOTS_TXT);
+        let agents: Vec<_> = rules.groups.iter().map(|group| &group.agents).collect();
+        assert_eq!(
+            agents,
+            [
+                &vec!["*".to_string()],
+                &vec!["linkrustler".to_string(), "otherbot".to_string()],
+                &vec!["linkrustler-strict".to_string()],
+            ]
+        );
+        assert_eq!(rules.groups[1].rules.len(), 2);
+    }
+
+    #[test]
+    fn the_most_specific_agent_group_applies_alone() {
+        // The catch-all rules don't apply to an agent with its own group
+        assert!(allowed("LinkRustler/0.2", "/private/"));
+        assert!(!allowed("LinkRustler/0.2", "/drafts/"));
+        assert!(!allowed("linkrustler-strict", "/anything"));
+        assert!(!allowed("SomeBrowser", "/private/"));
+    }
+
+    #[test]
+    fn the_longest_match_wins_and_allow_wins_ties() {
+        assert!(allowed("SomeBrowser", "/private/press/release"));
+        assert!(allowed("LinkRustler", "/drafts/published"));
+
+        let rules = parse("User-agent: *\nDisallow: /page\nAllow: /page\n");
+        let url = Url::parse("https://example.com/page").unwrap();
+        assert!(is_allowed(&rules, "any", &url));
+    }
+
+    #[test]
+    fn wildcards_and_end_anchors() {
+        assert!(!allowed("SomeBrowser", "/docs/report.pdf"));
+        assert!(allowed("SomeBrowser", "/docs/report.pdf?download=1"));
+        assert!(!allowed("SomeBrowser", "/search?q=links"));
+        assert!(allowed("SomeBrowser", "/search"));
+    }
+
+    #[test]
+    fn an_empty_or_missing_robots_txt_allows_everything() {
+        let url = Url::parse("https://example.com/private/").unwrap();
+        assert!(is_allowed(&parse(""), "any", &url));
+        assert!(is_allowed(
+            &parse("User-agent: *\nDisallow:\n"),
+            "any",
+            &url
+        ));
+        // Rules before any user-agent belong to no group
+        assert!(is_allowed(&parse("Disallow: /\n"), "any", &url));
+    }
+}
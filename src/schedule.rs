@@ -0,0 +1,283 @@
+use std::{str::FromStr, time::Duration};
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta, TimeZone};
+use humantime_serde::re::humantime;
+use tokio::sync::watch;
+use tracing::info;
+
+use crate::enums::Schedule;
+
+/// The longest the scheduler sleeps at once, the wall clock is looked at again after
+/// it in case it jumped, after a suspend or a clock change.
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    /// `HH:MM` is a daily run at that local time, anything else is read as an interval
+    /// like `24h` or `90m`.
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let value = value.trim();
+        if let Ok(time) = NaiveTime::parse_from_str(value, "%H:%M") {
+            return Ok(Schedule::Daily(time));
+        }
+
+        match humantime::parse_duration(value) {
+            Ok(interval) if interval.is_zero() => anyhow::bail!("The schedule interval is zero"),
+            Ok(interval) => Ok(Schedule::Every(interval)),
+            Err(err) => anyhow::bail!(
+                "Unknown schedule: {value}, expected a time of day like 02:30 or an interval \
+                like 24h ({err})"
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for Schedule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Schedule::Every(interval) => write!(f, "{}", humantime::format_duration(*interval)),
+            Schedule::Daily(time) => write!(f, "{}", time.format("%H:%M")),
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<Schedule> for String {
+    fn from(schedule: Schedule) -> Self {
+        schedule.to_string()
+    }
+}
+
+/// The first trigger of `schedule` after `after`, `None` when that is beyond what a
+/// date can hold.
+///
+/// A daily time that falls in a DST gap runs when the gap ends, one that happens twice
+/// when the clocks go back runs the first time.
+pub fn next_trigger<Tz: TimeZone>(
+    schedule: Schedule,
+    after: &DateTime<Tz>,
+) -> Option<DateTime<Tz>> {
+    let time = match schedule {
+        Schedule::Every(interval) => {
+            return TimeDelta::from_std(interval)
+                .ok()
+                .and_then(|interval| after.clone().checked_add_signed(interval));
+        }
+        Schedule::Daily(time) => time,
+    };
+
+    let timezone = after.timezone();
+    let mut date = after.date_naive();
+    loop {
+        let mut local = date.and_time(time);
+        // Gaps are an hour at most anywhere, a day covers any odd one
+        let trigger = loop {
+            if let Some(trigger) = timezone.from_local_datetime(&local).earliest() {
+                break Some(trigger);
+            }
+            local += TimeDelta::minutes(1);
+            if local > date.and_time(time) + TimeDelta::days(1) {
+                break None;
+            }
+        };
+
+        match trigger {
+            Some(trigger) if trigger > *after => return Some(trigger),
+            _ => date = date.succ_opt()?,
+        }
+    }
+}
+
+/// Waits for the first trigger after `after` of the schedule in `schedule`, which may
+/// change or be turned off while waiting. A change starts the wait over from the time
+/// it came in. `on_next` is told every time the next trigger changes, `None` while
+/// nothing is scheduled. Triggers missed while the computer slept come due once.
+///
+/// Returns the trigger that came due, or `None` once the sender is gone.
+pub async fn wait_for_trigger(
+    schedule: &mut watch::Receiver<Option<Schedule>>,
+    mut after: DateTime<Local>,
+    on_next: &impl Fn(Option<DateTime<Local>>),
+) -> Option<DateTime<Local>> {
+    loop {
+        let current = *schedule.borrow_and_update();
+        let mut next = current.and_then(|current| next_trigger(current, &after));
+        if let Some(current) = current {
+            let now = Local::now();
+            while let Some(later) = next
+                .and_then(|next| next_trigger(current, &next))
+                .filter(|later| *later <= now)
+            {
+                next = Some(later);
+            }
+        }
+        on_next(next);
+
+        let Some(next) = next else {
+            schedule.changed().await.ok()?;
+            after = Local::now();
+            continue;
+        };
+        info!("Next scheduled run at {next}");
+
+        loop {
+            let left = (next - Local::now()).to_std().unwrap_or_default();
+            if left.is_zero() {
+                return Some(next);
+            }
+
+            tokio::select! {
+                changed = schedule.changed() => {
+                    changed.ok()?;
+                    after = Local::now();
+                    break;
+                }
+                () = tokio::time::sleep(left.min(SCHEDULER_TICK)) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, NaiveDate};
+    use chrono_tz::Europe::Berlin;
+
+    use super::*;
+
+    fn daily(time: &str) -> Schedule {
+        time.parse().unwrap()
+    }
+
+    fn berlin(date: (i32, u32, u32), time: (u32, u32)) -> DateTime<chrono_tz::Tz> {
+        let local = NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+            .unwrap()
+            .and_hms_opt(time.0, time.1, 0)
+            .unwrap();
+        Berlin.from_local_datetime(&local).earliest().unwrap()
+    }
+
+    #[test]
+    fn parses_times_of_day_and_intervals() {
+        assert_eq!(
+            daily("02:30"),
+            Schedule::Daily(NaiveTime::from_hms_opt(2, 30, 0).unwrap())
+        );
+        assert_eq!(
+            " 24h ".parse::<Schedule>().unwrap(),
+            Schedule::Every(Duration::from_secs(24 * 60 * 60))
+        );
+        assert!("0s".parse::<Schedule>().is_err());
+        assert!("25:00".parse::<Schedule>().is_err());
+        assert!("tuesdays".parse::<Schedule>().is_err());
+    }
+
+    #[test]
+    fn display_parses_back() {
+        for schedule in ["02:30", "1h 30m", "7days"] {
+            let parsed: Schedule = schedule.parse().unwrap();
+            assert_eq!(parsed.to_string().parse::<Schedule>().unwrap(), parsed);
+        }
+    }
+
+    #[test]
+    fn a_daily_time_runs_today_until_it_has_passed() {
+        let after = berlin((2024, 6, 10), (1, 0));
+        assert_eq!(
+            next_trigger(daily("02:30"), &after),
+            Some(berlin((2024, 6, 10), (2, 30)))
+        );
+
+        let after = berlin((2024, 6, 10), (2, 30));
+        assert_eq!(
+            next_trigger(daily("02:30"), &after),
+            Some(berlin((2024, 6, 11), (2, 30)))
+        );
+    }
+
+    #[test]
+    fn an_interval_is_added_to_the_last_trigger() {
+        let after = FixedOffset::east_opt(0)
+            .unwrap()
+            .with_ymd_and_hms(2024, 6, 10, 23, 0, 0)
+            .unwrap();
+        let schedule = "90m".parse().unwrap();
+        assert_eq!(
+            next_trigger(schedule, &after),
+            Some(after + TimeDelta::minutes(90))
+        );
+    }
+
+    #[test]
+    fn a_daily_time_in_the_spring_gap_runs_when_the_gap_ends() {
+        // 02:00 to 03:00 doesn't exist in Berlin on 2024-03-31
+        let after = berlin((2024, 3, 31), (1, 0));
+        let trigger = next_trigger(daily("02:30"), &after).unwrap();
+
+        assert_eq!(trigger, berlin((2024, 3, 31), (3, 0)));
+        assert_eq!(trigger.offset().to_string(), "CEST");
+        // And at 02:30 again the day after
+        assert_eq!(
+            next_trigger(daily("02:30"), &trigger),
+            Some(berlin((2024, 4, 1), (2, 30)))
+        );
+    }
+
+    #[test]
+    fn a_daily_time_that_happens_twice_in_autumn_runs_once() {
+        // 02:00 to 03:00 happens twice in Berlin on 2024-10-27
+        let after = berlin((2024, 10, 27), (0, 0));
+        let first = next_trigger(daily("02:30"), &after).unwrap();
+        assert_eq!(first.offset().to_string(), "CEST");
+        assert_eq!(
+            first.naive_local().time(),
+            NaiveTime::from_hms_opt(2, 30, 0).unwrap()
+        );
+
+        let second = next_trigger(daily("02:30"), &first).unwrap();
+        assert_eq!(second, berlin((2024, 10, 28), (2, 30)));
+        assert_eq!(second - first, TimeDelta::hours(25));
+    }
+
+    #[test]
+    fn an_interval_across_a_clock_change_is_elapsed_time() {
+        let after = berlin((2024, 3, 30), (12, 0));
+        assert_eq!(
+            next_trigger("24h".parse().unwrap(), &after),
+            Some(berlin((2024, 3, 31), (13, 0)))
+        );
+    }
+
+    #[test]
+    fn beyond_the_last_date_there_is_no_trigger() {
+        let after = DateTime::<chrono::Utc>::MAX_UTC;
+        assert_eq!(next_trigger(daily("02:30"), &after), None);
+        assert_eq!(next_trigger("1h".parse().unwrap(), &after), None);
+    }
+
+    #[tokio::test]
+    async fn missed_triggers_come_due_once() {
+        let (_tx, mut rx) = watch::channel(Some("1m".parse().unwrap()));
+        let after = Local::now() - TimeDelta::hours(3);
+
+        let trigger = wait_for_trigger(&mut rx, after, &|_| {}).await.unwrap();
+        let missed_by = Local::now() - trigger;
+        assert!(missed_by >= TimeDelta::zero() && missed_by < TimeDelta::minutes(1));
+    }
+
+    #[tokio::test]
+    async fn no_schedule_waits_until_the_sender_is_gone() {
+        let (tx, mut rx) = watch::channel(None);
+        drop(tx);
+
+        assert_eq!(wait_for_trigger(&mut rx, Local::now(), &|_| {}).await, None);
+    }
+}
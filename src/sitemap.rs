@@ -0,0 +1,161 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::Read,
+    sync::OnceLock,
+    time::Duration,
+};
+
+use anyhow::Context;
+use flate2::read::GzDecoder;
+use regex::Regex;
+use reqwest::Url;
+use tracing::{info, instrument, warn};
+
+use crate::{
+    enums::SitemapKind,
+    extract::unescape_entities,
+    structs::{LinkExtraction, ProxyConfig},
+    utilities::http_client,
+};
+
+const SITEMAP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Index files nested deeper than this are skipped, real sites use one level.
+const MAX_SITEMAP_DEPTH: usize = 3;
+/// Most sitemap files fetched for one run, an index may list at most 50,000.
+const MAX_SITEMAPS: usize = 1000;
+/// Most page URLs taken from all sitemaps together, the rest is left out.
+const MAX_SITEMAP_URLS: usize = 100_000;
+
+fn sitemap_rules() -> &'static [Regex; 3] {
+    static RULES: OnceLock<[Regex; 3]> = OnceLock::new();
+    RULES.get_or_init(|| {
+        [
+            // The root element, after the XML declaration, comments and doctype
+            Regex::new(concat!(
+                r"(?s)^\x{FEFF}?\s*(?:<\?.*?\?>\s*|<!--.*?-->\s*|<!DOCTYPE[^>]*>\s*)*",
+                r"<(?:\w+:)?(urlset|sitemapindex)\b",
+            ))
+            .expect("valid root rule"),
+            Regex::new(r"</(?:\w+:)?(urlset|sitemapindex)\s*>\s*$").expect("valid closing rule"),
+            Regex::new(r"(?s)<(?:\w+:)?loc>\s*(.*?)\s*</(?:\w+:)?loc>").expect("valid loc rule"),
+        ]
+    })
+}
+
+/// The `<loc>` entries of a sitemap, page URLs for a `urlset` and child sitemaps for a
+/// `sitemapindex`. Anything else, including a root element that is never closed, is an
+/// error rather than an empty sitemap.
+pub fn parse(xml: &str, sitemap_url: &Url) -> anyhow::Result<(SitemapKind, Vec<String>)> {
+    let [root, closing, loc] = sitemap_rules();
+
+    let Some(root_name) = root.captures(xml).map(|captures| captures[1].to_string()) else {
+        anyhow::bail!("{sitemap_url} is not a sitemap, expected a urlset or sitemapindex");
+    };
+    if closing
+        .captures(xml)
+        .map(|captures| captures[1].to_string())
+        != Some(root_name.clone())
+    {
+        anyhow::bail!("{sitemap_url} is malformed, its {root_name} element is never closed");
+    }
+
+    let kind = if root_name == "urlset" {
+        SitemapKind::Urls
+    } else {
+        SitemapKind::Index
+    };
+    let locations = loc
+        .captures_iter(xml)
+        .map(|captures| unescape_entities(&captures[1]))
+        .filter(|location| !location.is_empty())
+        .collect();
+
+    Ok((kind, locations))
+}
+
+/// Gzipped sitemaps are recognised by their content, servers don't agree on whether
+/// `.xml.gz` is sent with a gzip content encoding or as is.
+#[instrument(skip(proxy))]
+async fn fetch(url: &Url, proxy: Option<&ProxyConfig>) -> anyhow::Result<String> {
+    let bytes = http_client(proxy)
+        .build()
+        .context("Failed to create HTTP client")?
+        .get(url.clone())
+        .timeout(SITEMAP_TIMEOUT)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("Failed to download sitemap {url}"))?
+        .bytes()
+        .await
+        .with_context(|| format!("Failed to read sitemap {url}"))?;
+
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut xml = String::new();
+        let _ = GzDecoder::new(bytes.as_ref())
+            .read_to_string(&mut xml)
+            .with_context(|| format!("Failed to decompress sitemap {url}"))?;
+        return Ok(xml);
+    }
+
+    String::from_utf8(bytes.to_vec()).with_context(|| format!("Sitemap {url} isn't UTF-8"))
+}
+
+/// Every page listed by the sitemap at `sitemap_url`, following index files breadth
+/// first up to `MAX_SITEMAP_DEPTH`. Stops at `MAX_SITEMAPS` files or
+/// `MAX_SITEMAP_URLS` pages.
+#[instrument(skip(proxy))]
+pub async fn sitemap_links(
+    sitemap_url: &Url,
+    proxy: Option<&ProxyConfig>,
+) -> anyhow::Result<LinkExtraction> {
+    let mut extraction = LinkExtraction::default();
+    let mut queue = VecDeque::from([(sitemap_url.clone(), 0)]);
+    let mut seen = HashSet::from([sitemap_url.clone()]);
+    let mut fetched = 0;
+    let mut left_out = 0;
+
+    while let Some((url, depth)) = queue.pop_front() {
+        if fetched == MAX_SITEMAPS {
+            warn!(
+                "Stopped after {MAX_SITEMAPS} sitemaps, {} left unread",
+                queue.len() + 1
+            );
+            break;
+        }
+        fetched += 1;
+
+        let xml = fetch(&url, proxy).await?;
+        let (kind, locations) = parse(&xml, &url)?;
+        for location in locations {
+            let Ok(location) = url.join(&location) else {
+                extraction.unparsable += 1;
+                continue;
+            };
+
+            match kind {
+                SitemapKind::Urls if extraction.urls.len() < MAX_SITEMAP_URLS => {
+                    let _ = extraction.urls.insert(location);
+                }
+                SitemapKind::Urls => left_out += 1,
+                SitemapKind::Index if depth + 1 > MAX_SITEMAP_DEPTH => {
+                    warn!("Skipping {location}, sitemaps nest deeper than {MAX_SITEMAP_DEPTH}");
+                }
+                SitemapKind::Index => {
+                    if seen.insert(location.clone()) {
+                        queue.push_back((location, depth + 1));
+                    }
+                }
+            }
+        }
+    }
+
+    if left_out > 0 {
+        warn!("Only the first {MAX_SITEMAP_URLS} sitemap URLs are checked, {left_out} left out");
+    }
+    info!(
+        "Read {fetched} sitemaps, {} URLs listed",
+        extraction.urls.len()
+    );
+    Ok(extraction)
+}
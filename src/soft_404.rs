@@ -0,0 +1,244 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    time::SystemTime,
+};
+
+use thirtyfour::WebDriver;
+use tokio::time::Instant;
+use tracing::{info, instrument};
+use url::Url;
+
+use crate::{
+    driver,
+    enums::{CustomError, LinkType},
+    plan,
+    structs::{Config, Paths, RunControl, State},
+    utilities::hash_string,
+};
+
+/// One probe covers every URL of an origin.
+pub fn origin_key(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+/// A path no site has, on the origin of `url`.
+fn probe_url(url: &Url) -> Option<Url> {
+    let seed = format!("{}{:?}", origin_key(url), SystemTime::now());
+    url.join(&format!(
+        "/link-rustler-probe-{}",
+        &hash_string(&seed)[..32]
+    ))
+    .ok()
+}
+
+/// A probe URL for every origin with at least `soft_404_min_urls` of `urls`.
+fn probe_urls(urls: &HashSet<Url>, config: &Config) -> Vec<Url> {
+    let mut origins: BTreeMap<String, (usize, &Url)> = BTreeMap::new();
+    for url in urls.iter().filter(|url| url.scheme().starts_with("http")) {
+        origins.entry(origin_key(url)).or_insert((0, url)).0 += 1;
+    }
+
+    origins
+        .into_values()
+        .filter(|(count, _)| *count >= config.soft_404_min_urls)
+        .filter_map(|(_, url)| probe_url(url))
+        .collect()
+}
+
+/// Loads a page that doesn't exist on every origin of `urls`, as many at a time as tabs
+/// may be open. The probes are checked like any page, without local records, crawling
+/// or validators, and returned by `origin_key`.
+#[instrument(skip_all)]
+pub async fn probe(
+    mut driver: WebDriver,
+    urls: &HashSet<Url>,
+    config: &Config,
+    paths: &Paths,
+    control: &RunControl,
+) -> anyhow::Result<(WebDriver, HashMap<String, State>)> {
+    let probe_config = Config {
+        keep_local_records: false,
+        crawl_depth: 0,
+        validators: vec![],
+        ..config.clone()
+    };
+    let probe_urls = probe_urls(urls, config);
+    info!("Probing {} origins for soft 404s", probe_urls.len());
+
+    let mut probes = HashMap::new();
+    for batch in probe_urls.chunks(config.max_concurrent_tabs.max(1)) {
+        if control.is_cancelled() {
+            break;
+        }
+
+        let mut opened = vec![];
+        for probe_url in batch {
+            let navigation_error;
+            (driver, navigation_error) =
+                driver::new_tab(driver, probe_url, probe_url.as_str(), config.browser).await?;
+            opened.push((probe_url, navigation_error));
+        }
+        let dwell = batch
+            .iter()
+            .map(|probe_url| plan::dwell_time(probe_url, config))
+            .max()
+            .unwrap_or(config.page_dwell_time);
+        crate::checker::sleep_until_or_cancelled(Instant::now() + dwell, control).await;
+
+        for (probe_url, navigation_error) in opened {
+            let state = crate::checker::check_link(
+                &driver,
+                probe_url,
+                None,
+                &probe_config,
+                paths,
+                LinkType::Generic,
+                navigation_error,
+            )
+            .await;
+            driver::safely_close_window(&driver, probe_url).await?;
+            info!(
+                "Probed {probe_url}, status {:?}, error {:?}",
+                state.status_code, state.errors
+            );
+            let _ = probes.insert(origin_key(probe_url), state);
+        }
+    }
+
+    Ok((driver, probes))
+}
+
+/// Whether `state` looks like the page its origin served for the probe. Origins that
+/// failed the probe, with a real 404 for instance, answer missing pages properly and
+/// nothing of theirs is flagged.
+pub fn is_soft_404(url: &Url, state: &State, probe: &State, config: &Config) -> bool {
+    if !state.errors.is_empty() || state.http_only || !probe.errors.is_empty() {
+        return false;
+    }
+    // A site sending missing pages to its home page would flag the home page itself
+    if probe.final_url.as_ref() == Some(url) {
+        return false;
+    }
+
+    state.hash == probe.hash
+        || (state.text_hash.is_some() && state.text_hash == probe.text_hash)
+        || state
+            .cal_screenshot_similarity(probe)
+            .is_some_and(|distance| distance < config.screenshot_diff_tolerance)
+}
+
+/// Fails `state` with `Soft404` when it matches its origin's probe.
+pub fn flag(url: &Url, state: &mut State, probes: &HashMap<String, State>, config: &Config) {
+    let Some(probe) = probes.get(&origin_key(url)) else {
+        return;
+    };
+
+    if is_soft_404(url, state, probe, config) {
+        info!("{url} looks like the page for a missing page of its site");
+        state.errors.insert(0, CustomError::Soft404);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image_hasher::ImageHash;
+
+    use super::*;
+    use crate::test_support::{failed_state, state, url};
+
+    fn screenshot(content: &str, byte: u8) -> State {
+        let mut state = state(content);
+        state.screenshot_hash = Some(
+            ImageHash::<Box<[u8]>>::from_bytes(&[byte; 8])
+                .unwrap()
+                .to_base64(),
+        );
+        state
+    }
+
+    #[test]
+    fn a_page_like_the_probe_is_a_soft_404() {
+        let config = Config::default();
+        let page = url("https://example.com/gone");
+        let mut probe = screenshot("<h1>Oops, nothing here</h1>", 0);
+        probe.text_hash = Some(hash_string(&"Oops, nothing here".to_string()));
+
+        assert!(is_soft_404(&page, &probe, &probe, &config));
+        // Only the markup differs
+        let mut same_text = screenshot("<h1 class='x'>Oops, nothing here</h1>", 0xff);
+        same_text.text_hash.clone_from(&probe.text_hash);
+        assert!(is_soft_404(&page, &same_text, &probe, &config));
+        // The same design around another request id
+        let mut same_design = screenshot("<h1>Oops, nothing here</h1><!-- 42 -->", 0);
+        same_design.text_hash = None;
+        assert!(is_soft_404(&page, &same_design, &probe, &config));
+
+        let real_page = screenshot("<h1>Our products</h1>", 0xff);
+        assert!(!is_soft_404(&page, &real_page, &probe, &config));
+    }
+
+    #[test]
+    fn failed_checks_and_redirects_home_are_not_soft_404s() {
+        let config = Config::default();
+        let home = url("https://example.com/");
+        let probe = state("<h1>Welcome</h1>");
+
+        let failed = failed_state(CustomError::PageError);
+        assert!(!is_soft_404(&home, &failed, &failed, &config));
+        assert!(!is_soft_404(&home, &probe, &failed, &config));
+
+        let mut sent_home = probe.clone();
+        sent_home.final_url = Some(home.clone());
+        assert!(!is_soft_404(&home, &probe, &sent_home, &config));
+        assert!(is_soft_404(
+            &url("https://example.com/gone"),
+            &probe,
+            &sent_home,
+            &config
+        ));
+    }
+
+    #[test]
+    fn only_pages_of_a_probed_origin_are_flagged() {
+        let config = Config::default();
+        let probe = state("<h1>Not found</h1>");
+        let probes = HashMap::from([(origin_key(&url("https://example.com/x")), probe.clone())]);
+
+        let mut flagged = probe.clone();
+        flag(
+            &url("https://example.com/gone"),
+            &mut flagged,
+            &probes,
+            &config,
+        );
+        assert_eq!(flagged.errors, [CustomError::Soft404]);
+
+        let mut elsewhere = probe.clone();
+        flag(
+            &url("https://example.org/gone"),
+            &mut elsewhere,
+            &probes,
+            &config,
+        );
+        assert!(elsewhere.errors.is_empty());
+    }
+
+    #[test]
+    fn origins_with_too_few_urls_are_not_probed() {
+        let config = Config {
+            soft_404_min_urls: 2,
+            ..Config::default()
+        };
+        let urls = HashSet::from([
+            url("https://example.com/a"),
+            url("https://example.com/b"),
+            url("https://example.org/a"),
+            url("mailto:someone@example.com"),
+        ]);
+
+        let probes = probe_urls(&urls, &config);
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].host_str(), Some("example.com"));
+        assert!(probes[0].path().starts_with("/link-rustler-probe-"));
+    }
+}
@@ -0,0 +1,63 @@
+//! Builders the unit tests share, only compiled for tests.
+
+use std::collections::BTreeMap;
+
+use url::Url;
+
+use crate::{
+    enums::{CustomError, LinkType, RunTrigger},
+    structs::{Config, PageData, RunSummary, State},
+    utilities::hash_string,
+};
+
+pub fn url(url: &str) -> Url {
+    Url::parse(url).unwrap()
+}
+
+/// A successful check of a page with `content` as its source.
+pub fn state(content: &str) -> State {
+    State::new(content, None, None, LinkType::Generic, vec![])
+}
+
+pub fn failed_state(error: CustomError) -> State {
+    State::new("", None, None, LinkType::Generic, vec![error])
+}
+
+/// `history` oldest first, the last state is the most recent check.
+pub fn page_data(url: &str, history: Vec<State>) -> PageData {
+    let mut states = history.into_iter();
+    let mut page_data = PageData::new(
+        states.next().expect("a page has at least one state"),
+        hash_string(&url.to_string()),
+        None,
+    );
+    page_data.history.extend(states);
+    page_data
+}
+
+/// One checked page per URL, keyed the way the data store keys them.
+pub fn page_datas(urls: &[&str]) -> BTreeMap<Url, PageData> {
+    urls.iter()
+        .map(|page| (url(page), page_data(page, vec![state(page)])))
+        .collect()
+}
+
+pub fn run_summary() -> RunSummary {
+    RunSummary {
+        started: chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        finished: chrono::DateTime::from_timestamp(1_700_000_090, 500_000_000),
+        cancelled: false,
+        urls_checked: 0,
+        new_urls: 0,
+        previously_seen: 0,
+        status_counts: BTreeMap::new(),
+        skipped: BTreeMap::new(),
+        statuses: BTreeMap::new(),
+        extensions_skipped: vec![],
+        trigger: RunTrigger::Manual,
+        driver_restarts: 0,
+        timed_out: 0,
+        driver_version: None,
+        config: Config::default(),
+    }
+}